@@ -3,10 +3,16 @@ use std::path::PathBuf;
 use anyhow::{bail, Result};
 use clap::Args;
 use ds_rom::{
-    crypto::blowfish::BlowfishKey,
-    rom::{Rom, RomLoadOptions, RomSaveError},
+    compress::lz77::Lz77Strategy,
+    crypto::hmac_sha1::HmacSha1,
+    rom::{
+        dsiware::{DsiWare, DsiWareSaveError},
+        Rom, RomLoadOptions, RomSaveError,
+    },
 };
 
+use crate::load_blowfish_key;
+
 /// Builds a ROM from a path generated by `extract`
 #[derive(Args)]
 pub struct Build {
@@ -18,27 +24,100 @@ pub struct Build {
     #[arg(long, short = '7')]
     arm7_bios: Option<PathBuf>,
 
+    /// Raw 0x1048-byte Blowfish key file, can be used instead of the ARM7 BIOS
+    #[arg(long)]
+    blowfish_key: Option<PathBuf>,
+
+    /// HMAC-SHA1 key file, used to recompute the ARM9, ARM7 and banner HMACs in the header
+    #[arg(long)]
+    header_hmac_key: Option<PathBuf>,
+
+    /// HMAC-SHA1 key file, used to update the DSi digest hashtables. Required if the ROM has a digest and was modified
+    #[arg(long)]
+    digest_hmac_key: Option<PathBuf>,
+
     /// Output ROM
     #[arg(long, short = 'o')]
     rom: PathBuf,
 
+    /// Output DSiWare title metadata (TMD), if the ROM was extracted as DSiWare
+    #[arg(long)]
+    tmd: Option<PathBuf>,
+
     /// Skip compressing code modules
     #[arg(long)]
     no_compress: bool,
+
+    /// Compress code modules as small as possible instead of like the retail compressor, the ROM will not match the original
+    #[arg(long)]
+    optimal_compression: bool,
+
+    /// Number of threads to compress code modules with, defaults to the number of available CPU cores
+    #[arg(long, short = 'j')]
+    jobs: Option<usize>,
+
+    /// Reuse compressed code modules from previous builds, stored in `.dsrom-cache/` next to the config YAML
+    #[arg(long)]
+    cache: bool,
+
+    /// Write asset files with identical contents only once to make the ROM smaller, it will not match the original
+    #[arg(long)]
+    dedupe_files: bool,
 }
 
 impl Build {
     pub fn run(&self) -> Result<()> {
-        let key =
-            if let Some(arm7_bios) = &self.arm7_bios { Some(BlowfishKey::from_arm7_bios_path(arm7_bios)?) } else { None };
+        let key = load_blowfish_key(self.arm7_bios.as_ref(), self.blowfish_key.as_ref())?;
+
+        let header_hmac_sha1 = if let Some(header_hmac_key) = &self.header_hmac_key {
+            Some(HmacSha1::from_key_file(header_hmac_key)?)
+        } else {
+            None
+        };
+        let digest_hmac_sha1 = if let Some(digest_hmac_key) = &self.digest_hmac_key {
+            Some(HmacSha1::from_key_file(digest_hmac_key)?)
+        } else {
+            None
+        };
+
+        let mut options = RomLoadOptions {
+            key: key.as_ref(),
+            compress: !self.no_compress,
+            lz77_strategy: if self.optimal_compression { Lz77Strategy::Optimal } else { Lz77Strategy::Greedy },
+            compression_cache: self.cache,
+            header_hmac_sha1: header_hmac_sha1.as_ref(),
+            digest_hmac_sha1: digest_hmac_sha1.as_ref(),
+            ..Default::default()
+        };
+        if let Some(jobs) = self.jobs {
+            options.compression_workers = jobs;
+        }
+
+        if let Some(tmd_path) = &self.tmd {
+            let mut dsiware = match DsiWare::load(&self.config, options) {
+                Err(DsiWareSaveError::RomSave { source: RomSaveError::BlowfishKeyNeeded }) => {
+                    bail!("The ROM is encrypted, please provide ARM7 BIOS or Blowfish key");
+                }
+                result => result?,
+            };
+            if self.dedupe_files {
+                dsiware.rom_mut().set_dedupe_files(true);
+            }
+            let (raw_rom, tmd) = dsiware.build(key.as_ref())?;
+            raw_rom.save(&self.rom)?;
+            std::fs::write(tmd_path, tmd.full_data())?;
+            return Ok(());
+        }
 
-        let options = RomLoadOptions { key: key.as_ref(), compress: !self.no_compress, ..Default::default() };
-        let rom = match Rom::load(&self.config, options) {
+        let mut rom = match Rom::load(&self.config, options) {
             Err(RomSaveError::BlowfishKeyNeeded) => {
-                bail!("The ROM is encrypted, please provide ARM7 BIOS");
+                bail!("The ROM is encrypted, please provide ARM7 BIOS or Blowfish key");
             }
             result => result?,
         };
+        if self.dedupe_files {
+            rom.set_dedupe_files(true);
+        }
         let raw_rom = rom.build(key.as_ref())?;
         raw_rom.save(&self.rom)?;
         Ok(())
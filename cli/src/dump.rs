@@ -4,11 +4,17 @@ use anyhow::{bail, Context, Result};
 use clap::{Args, Subcommand};
 use ds_rom::{
     compress::lz77::Lz77,
-    crypto::{blowfish::BlowfishKey, hmac_sha1::HmacSha1},
+    crypto::{
+        hmac_sha1::HmacSha1,
+        rsa::{RsaPublicKey, RsaVerifier},
+    },
     rom::{self, raw, Arm9, Logo, Overlay, Rom},
 };
 
-use crate::print_hex;
+use crate::{load_blowfish_key, print_hex};
+
+/// Size of the regions to report LZ77 savings for.
+const LZ77_REGION_SIZE: usize = 0x1000;
 
 /// Prints information about a ROM
 #[derive(Args)]
@@ -21,6 +27,10 @@ pub struct Dump {
     #[arg(long, short = '7')]
     arm7_bios: Option<PathBuf>,
 
+    /// Raw 0x1048-byte Blowfish key file, can be used instead of the ARM7 BIOS
+    #[arg(long)]
+    blowfish_key: Option<PathBuf>,
+
     /// Encrypts the secure area.
     #[arg(long, short = 'e')]
     encrypt: bool,
@@ -39,8 +49,7 @@ pub struct Dump {
 
 impl Dump {
     pub fn run(&self) -> Result<()> {
-        let key =
-            if let Some(arm7_bios) = &self.arm7_bios { Some(BlowfishKey::from_arm7_bios_path(arm7_bios)?) } else { None };
+        let key = load_blowfish_key(self.arm7_bios.as_ref(), self.blowfish_key.as_ref())?;
 
         let rom = raw::Rom::from_file(self.rom.clone())?;
         let header = rom.header()?;
@@ -111,6 +120,11 @@ struct DumpHeader {
     /// Changes the header logo to this PNG.
     #[arg(long, short = 'l')]
     header_logo: Option<PathBuf>,
+
+    /// RSA public key modulus files to verify the header signature against. Can be passed multiple times. No keys are
+    /// bundled, so the signature can't be verified without them.
+    #[arg(long)]
+    rsa_key: Vec<PathBuf>,
 }
 
 impl DumpHeader {
@@ -124,6 +138,15 @@ impl DumpHeader {
 
         println!("ROM header:\n{}", header.display(2));
 
+        if !self.rsa_key.is_empty() {
+            let mut verifier = RsaVerifier::new();
+            for path in &self.rsa_key {
+                let name = path.file_stem().unwrap_or(path.as_os_str()).to_string_lossy();
+                verifier.add_key(name, RsaPublicKey::from_modulus_file(path)?);
+            }
+            println!("RSA signature: {}", header.verify_rsa_signature(&verifier));
+        }
+
         Ok(())
     }
 }
@@ -135,6 +158,10 @@ struct DumpArm9 {
     #[arg(long, short = 'L')]
     compare_lz77: bool,
 
+    /// Prints the LZ77 comparison as a JSON report of the first diverging token and savings per region.
+    #[arg(long, short = 'j', requires = "compare_lz77")]
+    json: bool,
+
     /// Shows the LZ77 tokens of a compressed module.
     #[arg(long, short = 'z')]
     show_lz77_tokens: bool,
@@ -151,7 +178,11 @@ impl DumpArm9 {
             recompressed.decompress()?;
             recompressed.compress()?;
 
-            compare_lz77(arm9.full_data(), recompressed.full_data(), 0x4000, arm9.base_address() as usize);
+            if self.json {
+                print_lz77_diff(arm9.full_data(), recompressed.full_data())?;
+            } else {
+                compare_lz77(arm9.full_data(), recompressed.full_data(), 0x4000, arm9.base_address() as usize);
+            }
         }
 
         if self.show_lz77_tokens {
@@ -336,6 +367,10 @@ struct DumpArm9Overlay {
     #[arg(long, short = 'L')]
     compare_lz77: bool,
 
+    /// Prints the LZ77 comparison as a JSON report of the first diverging token and savings per region.
+    #[arg(long, short = 'j', requires = "compare_lz77")]
+    json: bool,
+
     /// Shows the LZ77 tokens of a compressed module.
     #[arg(long, short = 'z')]
     show_lz77_tokens: bool,
@@ -364,7 +399,11 @@ impl DumpArm9Overlay {
             recompressed.decompress()?;
             recompressed.compress()?;
 
-            compare_lz77(overlay.full_data(), recompressed.full_data(), 0, overlay.base_address() as usize);
+            if self.json {
+                print_lz77_diff(overlay.full_data(), recompressed.full_data())?;
+            } else {
+                compare_lz77(overlay.full_data(), recompressed.full_data(), 0, overlay.base_address() as usize);
+            }
         }
 
         if self.show_lz77_tokens {
@@ -428,6 +467,15 @@ fn compare_lz77(data_before: &[u8], data_after: &[u8], start: usize, base_addres
     }
 }
 
+fn print_lz77_diff(data_before: &[u8], data_after: &[u8]) -> Result<()> {
+    let before = Lz77 {}.parse_tokens(data_before)?;
+    let after = Lz77 {}.parse_tokens(data_after)?;
+    let diff = before.diff(&after, LZ77_REGION_SIZE);
+    println!("{}", serde_json::to_string_pretty(&diff)?);
+
+    Ok(())
+}
+
 /// Prints the contents of the ARM9 footer.
 #[derive(Args)]
 struct DumpArm9Footer {}
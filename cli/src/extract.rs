@@ -2,11 +2,13 @@ use std::path::PathBuf;
 
 use anyhow::{bail, Result};
 use clap::Args;
-use ds_rom::{
-    crypto::blowfish::BlowfishKey,
-    rom::{raw, Rom, RomSaveError},
+use ds_rom::rom::{
+    dsiware::{DsiWare, DsiWareSaveError},
+    raw, Rom, RomSaveError,
 };
 
+use crate::load_blowfish_key;
+
 /// Extracts a ROM to a given path
 #[derive(Args)]
 pub struct Extract {
@@ -18,21 +20,60 @@ pub struct Extract {
     #[arg(long, short = '7')]
     arm7_bios: Option<PathBuf>,
 
+    /// Raw 0x1048-byte Blowfish key file, can be used instead of the ARM7 BIOS
+    #[arg(long)]
+    blowfish_key: Option<PathBuf>,
+
+    /// DSiWare title metadata (TMD), if the ROM is a DSiWare SRL
+    #[arg(long)]
+    tmd: Option<PathBuf>,
+
     /// Output path
     #[arg(long, short = 'o')]
     path: PathBuf,
+
+    /// Save BIOS-compressed asset files decompressed, they are compressed again when building
+    #[arg(long)]
+    decompress_files: bool,
+
+    /// Expand NARC asset files into folders, they are packed again when building
+    #[arg(long)]
+    expand_narcs: bool,
+
+    /// Record the ID of every asset file, so that adding files when building doesn't change the IDs of existing files.
+    /// New files must be placed in new folders or in the folder of the file with the highest ID, so they can only be added
+    /// to the root folder if it holds that file
+    #[arg(long)]
+    pin_file_ids: bool,
 }
 
 impl Extract {
     pub fn run(&self) -> Result<()> {
         let raw_rom = raw::Rom::from_file(&self.rom)?;
-        let key =
-            if let Some(arm7_bios) = &self.arm7_bios { Some(BlowfishKey::from_arm7_bios_path(arm7_bios)?) } else { None };
-        let rom = Rom::extract(&raw_rom)?;
+        let key = load_blowfish_key(self.arm7_bios.as_ref(), self.blowfish_key.as_ref())?;
+
+        if let Some(tmd) = &self.tmd {
+            let tmd = std::fs::read(tmd)?;
+            let mut dsiware = DsiWare::extract(&raw_rom, &tmd)?;
+            dsiware.rom_mut().set_decompress_files(self.decompress_files);
+            dsiware.rom_mut().set_expand_narcs(self.expand_narcs);
+            dsiware.rom_mut().set_pin_file_ids(self.pin_file_ids);
+            return match dsiware.save(&self.path, key.as_ref()) {
+                Err(DsiWareSaveError::RomSave { source: RomSaveError::BlowfishKeyNeeded }) => {
+                    bail!("The ROM is encrypted, please provide ARM7 BIOS or Blowfish key");
+                }
+                result => Ok(result?),
+            };
+        }
+
+        let mut rom = Rom::extract(&raw_rom)?;
+        rom.set_decompress_files(self.decompress_files);
+        rom.set_expand_narcs(self.expand_narcs);
+        rom.set_pin_file_ids(self.pin_file_ids);
 
         match rom.save(&self.path, key.as_ref()) {
             Err(RomSaveError::BlowfishKeyNeeded) => {
-                bail!("The ROM is encrypted, please provide ARM7 BIOS");
+                bail!("The ROM is encrypted, please provide ARM7 BIOS or Blowfish key");
             }
             result => Ok(result?),
         }
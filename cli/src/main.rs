@@ -2,11 +2,12 @@ mod build;
 mod dump;
 mod extract;
 
-use std::io::Write;
+use std::{io::Write, path::PathBuf};
 
 use anyhow::Result;
 use build::Build;
 use clap::{Parser, Subcommand};
+use ds_rom::crypto::blowfish::BlowfishKey;
 use dump::Dump;
 use extract::Extract;
 use log::LevelFilter;
@@ -42,6 +43,18 @@ fn main() -> Result<()> {
     args.command.run()
 }
 
+/// Loads the Blowfish key from the ARM7 BIOS or a raw key file, falling back to the `DSROM_BLOWFISH_KEY` environment
+/// variable.
+pub fn load_blowfish_key(arm7_bios: Option<&PathBuf>, blowfish_key: Option<&PathBuf>) -> Result<Option<BlowfishKey>> {
+    if let Some(arm7_bios) = arm7_bios {
+        Ok(Some(BlowfishKey::from_arm7_bios_path(arm7_bios)?))
+    } else if let Some(blowfish_key) = blowfish_key {
+        Ok(Some(BlowfishKey::from_key_path(blowfish_key)?))
+    } else {
+        Ok(BlowfishKey::from_env()?)
+    }
+}
+
 pub fn print_hex(data: &[u8], raw: bool, base: u32) -> Result<()> {
     if raw {
         std::io::stdout().write_all(data)?;
@@ -0,0 +1,12 @@
+#![no_main]
+
+use ds_rom::compress::lz77::Lz77;
+use libfuzzer_sys::fuzz_target;
+
+fuzz_target!(|data: &[u8]| {
+    let lz77 = Lz77 {};
+    let _ = lz77.parse_tokens(data);
+    if let Ok(decompressed) = lz77.decompress(data) {
+        assert_eq!(decompressed.len(), lz77.decompressed_size(data).unwrap());
+    }
+});
@@ -0,0 +1,64 @@
+use std::hint::black_box;
+
+use criterion::{criterion_group, criterion_main, Criterion, Throughput};
+use ds_rom::compress::lz77::Lz77;
+
+/// Path to an ARM9 program to benchmark with, such as `arm9/arm9.bin` from an extracted ROM. If not set, a synthetic
+/// program is generated instead.
+const ARM9_ENV: &str = "DSROM_BENCH_ARM9";
+/// Size of the synthetic ARM9 program.
+const SYNTHETIC_SIZE: usize = 0x100000;
+/// Size of the secure area, which is not compressed.
+const SECURE_AREA_SIZE: usize = 0x4000;
+
+/// Generates data resembling an ARM9 program: instructions from a limited set, literal pools, zero-padded tables, strings
+/// and repeated code sequences.
+fn synthetic_arm9() -> Vec<u8> {
+    let mut seed = 0x2545f4914f6cdd1du64;
+    let mut next = || {
+        seed ^= seed << 13;
+        seed ^= seed >> 7;
+        seed ^= seed << 17;
+        seed
+    };
+
+    let instructions: Vec<u32> = (0..512).map(|_| 0xe0000000 | (next() as u32 & 0x0fffffff)).collect();
+    let mut data = Vec::with_capacity(SYNTHETIC_SIZE);
+    while data.len() < SYNTHETIC_SIZE {
+        let value = next();
+        match value % 16 {
+            0..=9 => data.extend(instructions[(value >> 8) as usize % instructions.len()].to_le_bytes()),
+            10 | 11 => data.extend((0x02000000 | (next() as u32 & 0x3fffff)).to_le_bytes()),
+            12 => data.extend([0; 16]),
+            13 => data.extend(b"assets/file.bin\0"),
+            _ => {
+                let distance = 4 + (value >> 12) as usize % 0x1000;
+                let length = 8 + (value >> 32) as usize % 64;
+                let start = data.len().saturating_sub(distance);
+                for i in start..(start + length).min(data.len()) {
+                    data.push(data[i]);
+                }
+            }
+        }
+    }
+    data.truncate(SYNTHETIC_SIZE);
+    data
+}
+
+fn compress_arm9(c: &mut Criterion) {
+    let arm9 = match std::env::var(ARM9_ENV) {
+        Ok(path) => std::fs::read(path).expect("failed to read ARM9 program"),
+        Err(_) => synthetic_arm9(),
+    };
+
+    let mut group = c.benchmark_group("lz77");
+    group.sample_size(10);
+    group.throughput(Throughput::Bytes(arm9.len() as u64));
+    group.bench_function("compress_arm9", |b| {
+        b.iter(|| Lz77 {}.compress(black_box(&arm9), SECURE_AREA_SIZE.min(arm9.len())).unwrap())
+    });
+    group.finish();
+}
+
+criterion_group!(benches, compress_arm9);
+criterion_main!(benches);
@@ -0,0 +1,354 @@
+use std::fmt::Display;
+
+use snafu::{Backtrace, Snafu};
+
+use crate::{
+    crypto::{
+        blowfish::{Blowfish, BlowfishKey, BlowfishLevel},
+        key2::Key2,
+    },
+    rom::raw::{self, RawArm9Error, RawHeaderError},
+};
+
+/// Size of a header read.
+const HEADER_READ_SIZE: usize = 0x200;
+/// Size of a dummy read.
+const DUMMY_READ_SIZE: usize = 0x2000;
+/// Size of a secure area block read.
+const SECURE_AREA_BLOCK_SIZE: usize = 0x1000;
+/// Size of a KEY2 data read.
+const DATA_READ_SIZE: usize = 0x200;
+/// Start of the secure area.
+const SECURE_AREA_START: usize = 0x4000;
+/// End of the secure area.
+const SECURE_AREA_END: usize = 0x8000;
+/// Reads cross no boundaries of this size, they wrap around to the start of the page instead.
+const PAGE_SIZE: usize = 0x1000;
+
+/// Emulates an NTR game card, answering card commands from a [`raw::Rom`] like a real cartridge would.
+///
+/// Commands and responses are passed as they appear on the bus. In [`CardMode::Key1`], commands are KEY1-encrypted, and
+/// once KEY2 has been activated, all responses and commands in [`CardMode::Key2`] are KEY2-encrypted.
+pub struct Card<'a> {
+    rom: &'a raw::Rom<'a>,
+    secure_area: Vec<u8>,
+    key1: Blowfish,
+    key2: Option<Key2>,
+    mode: CardMode,
+    chip_id: u32,
+}
+
+/// Protocol mode of a [`Card`].
+#[derive(Clone, Copy, PartialEq, Eq, Debug)]
+pub enum CardMode {
+    /// Unencrypted mode after reset, accepts header and chip ID reads.
+    Raw,
+    /// KEY1-encrypted mode, used to read the secure area.
+    Key1,
+    /// KEY2-encrypted main data mode.
+    Key2,
+}
+
+/// Errors related to [`Card`].
+#[derive(Debug, Snafu)]
+pub enum CardError {
+    /// See [`RawHeaderError`].
+    #[snafu(transparent)]
+    RawHeader {
+        /// Source error.
+        source: RawHeaderError,
+    },
+    /// See [`RawArm9Error`].
+    #[snafu(transparent)]
+    RawArm9 {
+        /// Source error.
+        source: RawArm9Error,
+    },
+    /// Occurs when a command is not known in the current mode.
+    #[snafu(display("unknown card command {command:016x} in {mode} mode:\n{backtrace}"))]
+    UnknownCommand {
+        /// Decrypted command.
+        command: u64,
+        /// Current mode.
+        mode: CardMode,
+        /// Backtrace to the source of the error.
+        backtrace: Backtrace,
+    },
+    /// Occurs when a secure area block outside of the secure area is requested.
+    #[snafu(display("secure area block {address:#x} is outside of the secure area:\n{backtrace}"))]
+    InvalidSecureAreaBlock {
+        /// Requested address.
+        address: usize,
+        /// Backtrace to the source of the error.
+        backtrace: Backtrace,
+    },
+}
+
+impl<'a> Card<'a> {
+    /// Creates a new [`Card`] in [`CardMode::Raw`]. The secure area is encrypted with `key` if it isn't already, as a real
+    /// cartridge always stores it encrypted.
+    ///
+    /// # Errors
+    ///
+    /// This function will return an error if the header or ARM9 program could not be read.
+    pub fn new(rom: &'a raw::Rom<'a>, key: &BlowfishKey) -> Result<Self, CardError> {
+        let header = rom.header()?;
+        let gamecode = header.gamecode.to_le_u32();
+
+        let data = rom.data();
+        let mut secure_area: Vec<u8> =
+            (SECURE_AREA_START..SECURE_AREA_END).map(|address| data.get(address).copied().unwrap_or(0xff)).collect();
+        if header.arm9.offset as usize == SECURE_AREA_START && header.arm9.size as usize >= secure_area.len() {
+            let arm9 = rom.arm9()?;
+            secure_area.copy_from_slice(&arm9.encrypted_secure_area(key, gamecode));
+        }
+
+        let key1 = Blowfish::new(key, gamecode, BlowfishLevel::Level2);
+        let chip_id = Self::default_chip_id(data.len());
+
+        Ok(Self { rom, secure_area, key1, key2: None, mode: CardMode::Raw, chip_id })
+    }
+
+    /// Returns the chip ID of a Macronix ROM chip large enough to hold `rom_size` bytes. The chip size byte is the size in
+    /// MiB minus one up to 128 MiB, and `0x100` minus the size in units of 256 MiB above that.
+    pub fn default_chip_id(rom_size: usize) -> u32 {
+        let megabytes = rom_size.div_ceil(0x100000).max(1).next_power_of_two();
+        let size_byte = if megabytes <= 0x80 { megabytes - 1 } else { 0x100 - (megabytes / 0x100).min(0x10) } as u32;
+        0xc2 | (size_byte << 8)
+    }
+
+    /// Returns the current protocol mode.
+    pub fn mode(&self) -> CardMode {
+        self.mode
+    }
+
+    /// Returns the chip ID.
+    pub fn chip_id(&self) -> u32 {
+        self.chip_id
+    }
+
+    /// Sets the chip ID.
+    pub fn set_chip_id(&mut self, chip_id: u32) {
+        self.chip_id = chip_id;
+    }
+
+    /// Resets the card to [`CardMode::Raw`].
+    pub fn reset(&mut self) {
+        self.key2 = None;
+        self.mode = CardMode::Raw;
+    }
+
+    /// Encrypts a KEY1 command, i.e. the opposite of what the card does to commands in [`CardMode::Key1`].
+    pub fn encrypt_key1_command(&self, command: [u8; 8]) -> [u8; 8] {
+        let mut block = command;
+        block.reverse();
+        self.key1.encrypt(&mut block).unwrap();
+        block.reverse();
+        block
+    }
+
+    fn decrypt_key1_command(&self, command: [u8; 8]) -> [u8; 8] {
+        let mut block = command;
+        block.reverse();
+        self.key1.decrypt(&mut block).unwrap();
+        block.reverse();
+        block
+    }
+
+    /// Sends a command to the card and returns its response.
+    ///
+    /// # Errors
+    ///
+    /// This function will return an error if the command is unknown in the current mode, or if it requests data outside of the
+    /// secure area.
+    pub fn command(&mut self, command: [u8; 8]) -> Result<Vec<u8>, CardError> {
+        match self.mode {
+            CardMode::Raw => self.raw_command(u64::from_be_bytes(command)),
+            CardMode::Key1 => {
+                let command = u64::from_be_bytes(self.decrypt_key1_command(command));
+                let mut response = self.key1_command(command)?;
+                if let Some(key2) = &mut self.key2 {
+                    key2.apply(&mut response);
+                }
+                Ok(response)
+            }
+            CardMode::Key2 => {
+                let mut command = command;
+                if let Some(key2) = &mut self.key2 {
+                    key2.apply(&mut command);
+                }
+                let mut response = self.key2_command(u64::from_be_bytes(command))?;
+                if let Some(key2) = &mut self.key2 {
+                    key2.apply(&mut response);
+                }
+                Ok(response)
+            }
+        }
+    }
+
+    fn raw_command(&mut self, command: u64) -> Result<Vec<u8>, CardError> {
+        match command >> 56 {
+            // Get header
+            0x00 => Ok(self.read(0, HEADER_READ_SIZE)),
+            // Get chip ID
+            0x90 => Ok(self.chip_id_response()),
+            // Dummy
+            0x9f => Ok(vec![0xff; DUMMY_READ_SIZE]),
+            // Activate KEY1 encryption mode
+            0x3c => {
+                self.mode = CardMode::Key1;
+                Ok(vec![])
+            }
+            _ => UnknownCommandSnafu { command, mode: self.mode }.fail(),
+        }
+    }
+
+    fn key1_command(&mut self, command: u64) -> Result<Vec<u8>, CardError> {
+        match command >> 60 {
+            // Activate KEY2 encryption mode
+            0x4 => {
+                let seed = ((command >> 20) & 0xffffff) as u32;
+                self.key2 = Some(Key2::new(seed, self.rom.header()?.seed_select));
+                Ok(vec![])
+            }
+            // Get chip ID
+            0x1 => Ok(self.chip_id_response()),
+            // Get secure area block
+            0x2 => {
+                let address = ((command >> 44) & 0xffff) as usize * SECURE_AREA_BLOCK_SIZE;
+                if !(SECURE_AREA_START..SECURE_AREA_END).contains(&address) {
+                    return InvalidSecureAreaBlockSnafu { address }.fail();
+                }
+                let start = address - SECURE_AREA_START;
+                Ok(self.secure_area[start..start + SECURE_AREA_BLOCK_SIZE].to_vec())
+            }
+            // Disable KEY2
+            0x6 => {
+                self.key2 = None;
+                Ok(vec![])
+            }
+            // Enter main data mode
+            0xa => {
+                self.mode = CardMode::Key2;
+                Ok(vec![])
+            }
+            _ => UnknownCommandSnafu { command, mode: self.mode }.fail(),
+        }
+    }
+
+    fn key2_command(&mut self, command: u64) -> Result<Vec<u8>, CardError> {
+        match command >> 56 {
+            // Get data
+            0xb7 => {
+                let mut address = ((command >> 24) & 0xffffffff) as usize;
+                if address < SECURE_AREA_END {
+                    // The secure area can't be read in main data mode
+                    address = SECURE_AREA_END + (address & (DATA_READ_SIZE - 1));
+                }
+                Ok(self.read(address, DATA_READ_SIZE))
+            }
+            // Get chip ID
+            0xb8 => Ok(self.chip_id_response()),
+            _ => UnknownCommandSnafu { command, mode: self.mode }.fail(),
+        }
+    }
+
+    fn chip_id_response(&self) -> Vec<u8> {
+        self.chip_id.to_le_bytes().to_vec()
+    }
+
+    fn read(&self, address: usize, size: usize) -> Vec<u8> {
+        let data = self.rom.data();
+        let page = address & !(PAGE_SIZE - 1);
+        (0..size)
+            .map(|i| {
+                let address = page + ((address + i) & (PAGE_SIZE - 1));
+                data.get(address).copied().unwrap_or(0xff)
+            })
+            .collect()
+    }
+}
+
+impl Display for CardMode {
+    fn fmt(&self, f: &mut std::fmt::Formatter<'_>) -> std::fmt::Result {
+        match self {
+            CardMode::Raw => write!(f, "raw"),
+            CardMode::Key1 => write!(f, "KEY1"),
+            CardMode::Key2 => write!(f, "KEY2"),
+        }
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use std::mem::size_of;
+
+    use bytemuck::Zeroable;
+
+    use super::*;
+    use crate::str::AsciiArray;
+
+    const SEED: u64 = 0x123456;
+    const SEED_SELECT: u8 = 2;
+
+    /// Returns the words of a 64 KB ROM whose header has a gamecode and KEY2 seed select, and no secure area.
+    fn build_rom() -> Vec<u32> {
+        let mut header = raw::Header::zeroed();
+        header.gamecode = AsciiArray(*b"ABCE");
+        header.seed_select = SEED_SELECT;
+        header.arm9.offset = 0x200;
+        raw::test_fixtures::build_rom(0x10000, &header)
+    }
+
+    #[test]
+    fn key1_to_key2_commands() {
+        let words = build_rom();
+        let data: &[u8] = bytemuck::cast_slice(&words);
+        let rom = raw::Rom::new(data);
+        let key = BlowfishKey::try_from([0x5a; size_of::<BlowfishKey>()].as_slice()).unwrap();
+        let mut card = Card::new(&rom, &key).unwrap();
+        let chip_id = [0xc2, 0x00, 0x00, 0x00];
+
+        // Raw mode
+        assert_eq!(card.command([0x00; 8]).unwrap(), &data[..HEADER_READ_SIZE]);
+        assert_eq!(card.command([0x90, 0, 0, 0, 0, 0, 0, 0]).unwrap(), chip_id);
+        assert!(card.command([0x3c, 0, 0, 0, 0, 0, 0, 0]).unwrap().is_empty());
+        assert_eq!(card.mode(), CardMode::Key1);
+
+        // KEY1 mode, activate KEY2 with the seed mmmnnn
+        let key1_command = |card: &Card, command: u64| card.encrypt_key1_command(command.to_be_bytes());
+        assert!(card.command(key1_command(&card, 0x4 << 60 | SEED << 20)).unwrap().is_empty());
+        let response = card.command(key1_command(&card, 0x1 << 60)).unwrap();
+        assert_eq!(response, [0x53, 0x0b, 0x75, 0x96], "chip ID encrypted with the first KEY2 bytes");
+        let mut key2 = Key2::new(SEED as u32, SEED_SELECT);
+        let mut response = response;
+        key2.apply(&mut response);
+        assert_eq!(response, chip_id);
+        assert!(card.command(key1_command(&card, 0xa << 60)).unwrap().is_empty());
+        assert_eq!(card.mode(), CardMode::Key2);
+
+        // KEY2 mode
+        let mut key2_command = |card: &mut Card, command: u64| {
+            let mut command = command.to_be_bytes();
+            key2.apply(&mut command);
+            let mut response = card.command(command).unwrap();
+            key2.apply(&mut response);
+            response
+        };
+        assert_eq!(key2_command(&mut card, 0xb8 << 56), chip_id);
+        assert_eq!(key2_command(&mut card, 0xb7 << 56 | 0x9000 << 24), &data[0x9000..0x9000 + DATA_READ_SIZE]);
+        assert_eq!(key2_command(&mut card, 0xb8 << 56), chip_id);
+    }
+
+    #[test]
+    fn default_chip_id_sizes() {
+        assert_eq!(Card::default_chip_id(0x10000), 0x00c2);
+        assert_eq!(Card::default_chip_id(0x1000000), 0x0fc2);
+        assert_eq!(Card::default_chip_id(0x1000001), 0x1fc2);
+        assert_eq!(Card::default_chip_id(0x8000000), 0x7fc2);
+        assert_eq!(Card::default_chip_id(0x8000001), 0xffc2);
+        assert_eq!(Card::default_chip_id(0x10000000), 0xffc2);
+        assert_eq!(Card::default_chip_id(0x20000000), 0xfec2);
+        assert_eq!(Card::default_chip_id(0x40000000), 0xfcc2);
+    }
+}
@@ -0,0 +1,314 @@
+use std::{backtrace::Backtrace, fmt::Display};
+
+use serde::{Deserialize, Serialize};
+use snafu::Snafu;
+
+use super::{diff::Diff, huffman_bios::HuffmanBios, lz10::Lz10, lz11::Lz11, rle::Rle};
+
+/// Largest decompressed size which fits in the 24-bit size field of a BIOS compression header.
+const MAX_SHORT_SIZE: usize = 0xffffff;
+/// Smallest distance of an LZ length-distance pair which the BIOS VRAM decompressors can decode.
+pub(crate) const VRAM_MIN_DISTANCE: usize = 2;
+
+/// Kinds of compression supported by the BIOS decompression functions, identified by the first byte of the header.
+#[derive(Clone, Copy, PartialEq, Eq, Hash, Debug, Serialize, Deserialize)]
+#[serde(rename_all = "snake_case")]
+pub enum BiosCompressionType {
+    /// LZ77 with 2-byte length-distance pairs, see [`Lz10`].
+    Lz10,
+    /// LZ77 with variable-size length-distance pairs, see [`Lz11`].
+    Lz11,
+    /// Run-length encoding, see [`Rle`].
+    Rle,
+    /// Huffman coding of 4-bit symbols, see [`HuffmanBios`].
+    Huffman4,
+    /// Huffman coding of 8-bit symbols, see [`HuffmanBios`].
+    Huffman8,
+    /// Difference filter of 8-bit units, see [`Diff`].
+    Diff8,
+    /// Difference filter of 16-bit units, see [`Diff`].
+    Diff16,
+}
+
+/// Header at the start of BIOS-compressed data.
+#[derive(Clone, Copy, PartialEq, Eq, Debug)]
+pub struct BiosHeader {
+    /// Compression type.
+    pub kind: BiosCompressionType,
+    /// Size of the data after decompression.
+    pub decompressed_size: usize,
+    /// Size of the header itself, 4 normally or 8 if the decompressed size didn't fit in 24 bits.
+    pub header_size: usize,
+}
+
+/// Errors related to decompressing BIOS-compressed data.
+#[derive(Debug, Snafu)]
+#[snafu(visibility(pub(crate)))]
+pub enum BiosDecompressError {
+    /// Occurs when the data doesn't start with a valid BIOS compression header.
+    #[snafu(display("data does not start with a valid BIOS compression header:\n{backtrace}"))]
+    InvalidHeader {
+        /// Backtrace to the source of the error.
+        backtrace: Backtrace,
+    },
+    /// Occurs when the header has a different compression type than the one being decompressed.
+    #[snafu(display("expected {expected} compression but found {actual}:\n{backtrace}"))]
+    WrongType {
+        /// Expected compression type.
+        expected: BiosCompressionType,
+        /// Compression type in the header.
+        actual: BiosCompressionType,
+        /// Backtrace to the source of the error.
+        backtrace: Backtrace,
+    },
+    /// Occurs when the compressed data ends before the decompressed size has been reached.
+    #[snafu(display("compressed data ended at offset {offset:#x} before the end of the decompressed data:\n{backtrace}"))]
+    UnexpectedEnd {
+        /// Offset where more data was expected.
+        offset: usize,
+        /// Backtrace to the source of the error.
+        backtrace: Backtrace,
+    },
+    /// Occurs when a length-distance pair points before the start of the decompressed data.
+    #[snafu(display(
+        "length-distance pair at offset {offset:#x} has distance {distance:#x} but only {position:#x} bytes are decompressed:\n{backtrace}"
+    ))]
+    InvalidDistance {
+        /// Offset of the length-distance pair.
+        offset: usize,
+        /// Distance of the length-distance pair.
+        distance: usize,
+        /// Number of bytes decompressed so far.
+        position: usize,
+        /// Backtrace to the source of the error.
+        backtrace: Backtrace,
+    },
+    /// Occurs when a Huffman tree node points outside of the tree table, or is a symbol which doesn't fit in the symbol size.
+    #[snafu(display(
+        "Huffman tree node at offset {offset:#x} points outside of the tree or is not a valid symbol:\n{backtrace}"
+    ))]
+    InvalidTree {
+        /// Offset of the tree node.
+        offset: usize,
+        /// Backtrace to the source of the error.
+        backtrace: Backtrace,
+    },
+}
+
+/// Errors related to compressing data into a BIOS compression format.
+#[derive(Debug, Snafu)]
+#[snafu(visibility(pub(crate)))]
+pub enum BiosCompressError {
+    /// Occurs when the data size is not a multiple of the unit size of the compression type.
+    #[snafu(display("{kind} compression requires a multiple of {unit} bytes but got {size:#x} bytes:\n{backtrace}"))]
+    MisalignedSize {
+        /// Compression type.
+        kind: BiosCompressionType,
+        /// Unit size in bytes.
+        unit: usize,
+        /// Size of the data.
+        size: usize,
+        /// Backtrace to the source of the error.
+        backtrace: Backtrace,
+    },
+    /// Occurs when a Huffman tree is too wide to be serialized, as a node in the tree table can only point 63 pairs ahead.
+    #[snafu(display("Huffman tree is too wide to be serialized into a tree table:\n{backtrace}"))]
+    TreeTooWide {
+        /// Backtrace to the source of the error.
+        backtrace: Backtrace,
+    },
+    /// Occurs when the data is too large to be described by a header.
+    #[snafu(display("data of size {size:#x} is too large for a BIOS compression header:\n{backtrace}"))]
+    TooLarge {
+        /// Size of the data.
+        size: usize,
+        /// Backtrace to the source of the error.
+        backtrace: Backtrace,
+    },
+}
+
+impl BiosCompressionType {
+    /// All compression types.
+    pub const ALL: [Self; 7] = [Self::Lz10, Self::Lz11, Self::Rle, Self::Huffman4, Self::Huffman8, Self::Diff8, Self::Diff16];
+
+    /// Returns the first byte of the header for this compression type.
+    pub fn header_byte(self) -> u8 {
+        match self {
+            Self::Lz10 => 0x10,
+            Self::Lz11 => 0x11,
+            Self::Rle => 0x30,
+            Self::Huffman4 => 0x24,
+            Self::Huffman8 => 0x28,
+            Self::Diff8 => 0x81,
+            Self::Diff16 => 0x82,
+        }
+    }
+
+    /// Returns the compression type of a header byte, or `None` if it's not a known compression type.
+    pub fn from_header_byte(byte: u8) -> Option<Self> {
+        Self::ALL.into_iter().find(|kind| kind.header_byte() == byte)
+    }
+
+    /// Detects the compression type of `data` from its header, or `None` if it doesn't start with a valid header.
+    pub fn detect(data: &[u8]) -> Option<Self> {
+        BiosHeader::parse(data).map(|header| header.kind)
+    }
+
+    /// Returns the size in bytes of the units this compression type operates on.
+    pub fn unit_size(self) -> usize {
+        match self {
+            Self::Diff16 => 2,
+            _ => 1,
+        }
+    }
+
+    /// Decompresses `data` which must be compressed with this compression type.
+    ///
+    /// # Errors
+    ///
+    /// This function will return an error if the header doesn't match this compression type or the data is malformed.
+    pub fn decompress(self, data: &[u8]) -> Result<Box<[u8]>, BiosDecompressError> {
+        match self {
+            Self::Lz10 => Lz10 {}.decompress(data),
+            Self::Lz11 => Lz11 {}.decompress(data),
+            Self::Rle => Rle {}.decompress(data),
+            Self::Huffman4 => HuffmanBios::Bits4.decompress(data),
+            Self::Huffman8 => HuffmanBios::Bits8.decompress(data),
+            Self::Diff8 => Diff::Bits8.decompress(data),
+            Self::Diff16 => Diff::Bits16.decompress(data),
+        }
+    }
+
+    /// Decompresses `data` like [`Self::decompress`], and also returns the offset to the end of the compressed stream.
+    pub(crate) fn decompress_stream(self, data: &[u8]) -> Result<(Box<[u8]>, usize), BiosDecompressError> {
+        match self {
+            Self::Lz10 => Lz10 {}.decompress_stream(data),
+            Self::Lz11 => Lz11 {}.decompress_stream(data),
+            Self::Rle => Rle {}.decompress_stream(data),
+            Self::Huffman4 => HuffmanBios::Bits4.decompress_stream(data),
+            Self::Huffman8 => HuffmanBios::Bits8.decompress_stream(data),
+            Self::Diff8 => Diff::Bits8.decompress_stream(data),
+            Self::Diff16 => Diff::Bits16.decompress_stream(data),
+        }
+    }
+
+    /// Compresses `data` with this compression type.
+    ///
+    /// # Errors
+    ///
+    /// This function will return an error if the data is too large, or if its size is not a multiple of
+    /// [`Self::unit_size`].
+    pub fn compress(self, data: &[u8]) -> Result<Box<[u8]>, BiosCompressError> {
+        match self {
+            Self::Lz10 => Lz10 {}.compress(data),
+            Self::Lz11 => Lz11 {}.compress(data),
+            Self::Rle => Rle {}.compress(data),
+            Self::Huffman4 => HuffmanBios::Bits4.compress(data),
+            Self::Huffman8 => HuffmanBios::Bits8.compress(data),
+            Self::Diff8 => Diff::Bits8.compress(data),
+            Self::Diff16 => Diff::Bits16.compress(data),
+        }
+    }
+
+    /// Compresses `data` like [`Self::compress`], but so that the BIOS VRAM decompressors can decode it. Only LZ10 and LZ11
+    /// differ, as they avoid length-distance pairs of distance 1.
+    ///
+    /// # Errors
+    ///
+    /// See [`Self::compress`].
+    pub fn compress_vram_safe(self, data: &[u8]) -> Result<Box<[u8]>, BiosCompressError> {
+        match self {
+            Self::Lz10 => Lz10 {}.compress_vram_safe(data),
+            Self::Lz11 => Lz11 {}.compress_vram_safe(data),
+            _ => self.compress(data),
+        }
+    }
+
+    /// Returns whether [`Self::compress_vram_safe`] differs from [`Self::compress`] for this compression type.
+    pub fn has_vram_safe_mode(self) -> bool {
+        matches!(self, Self::Lz10 | Self::Lz11)
+    }
+}
+
+impl Display for BiosCompressionType {
+    fn fmt(&self, f: &mut std::fmt::Formatter<'_>) -> std::fmt::Result {
+        match self {
+            Self::Lz10 => write!(f, "LZ10"),
+            Self::Lz11 => write!(f, "LZ11"),
+            Self::Rle => write!(f, "RLE"),
+            Self::Huffman4 => write!(f, "4-bit Huffman"),
+            Self::Huffman8 => write!(f, "8-bit Huffman"),
+            Self::Diff8 => write!(f, "8-bit diff"),
+            Self::Diff16 => write!(f, "16-bit diff"),
+        }
+    }
+}
+
+impl BiosHeader {
+    /// Parses the header at the start of `data`, or returns `None` if there is no valid header.
+    pub fn parse(data: &[u8]) -> Option<Self> {
+        let word = u32::from_le_bytes(data.get(0..4)?.try_into().unwrap());
+        let kind = BiosCompressionType::from_header_byte(word as u8)?;
+        let (decompressed_size, header_size) = match (word >> 8) as usize {
+            0 => (u32::from_le_bytes(data.get(4..8)?.try_into().unwrap()) as usize, 8),
+            size => (size, 4),
+        };
+        (decompressed_size > 0 || header_size == 8).then_some(Self { kind, decompressed_size, header_size })
+    }
+
+    /// Parses the header at the start of `data` and checks that it has the compression type `expected`.
+    ///
+    /// # Errors
+    ///
+    /// This function will return an error if there is no valid header or it has a different compression type.
+    pub fn parse_expected(data: &[u8], expected: BiosCompressionType) -> Result<Self, BiosDecompressError> {
+        let Some(header) = Self::parse(data) else {
+            return InvalidHeaderSnafu {}.fail();
+        };
+        if header.kind != expected {
+            return WrongTypeSnafu { expected, actual: header.kind }.fail();
+        }
+        Ok(header)
+    }
+
+    /// Creates a new header for compressing `size` bytes with the compression type `kind`.
+    ///
+    /// # Errors
+    ///
+    /// This function will return an error if the size doesn't fit in 32 bits.
+    pub fn new(kind: BiosCompressionType, size: usize) -> Result<Self, BiosCompressError> {
+        if size > u32::MAX as usize {
+            return TooLargeSnafu { size }.fail();
+        }
+        let header_size = if size == 0 || size > MAX_SHORT_SIZE { 8 } else { 4 };
+        Ok(Self { kind, decompressed_size: size, header_size })
+    }
+
+    /// Appends this header to `out`.
+    pub fn write(&self, out: &mut Vec<u8>) {
+        let byte = self.kind.header_byte() as u32;
+        if self.header_size == 8 {
+            out.extend(byte.to_le_bytes());
+            out.extend((self.decompressed_size as u32).to_le_bytes());
+        } else {
+            out.extend((byte | ((self.decompressed_size as u32) << 8)).to_le_bytes());
+        }
+    }
+}
+
+/// Detects the compression type of `data` and decompresses it.
+///
+/// # Errors
+///
+/// This function will return an error if `data` doesn't start with a valid header or is malformed.
+pub fn decompress(data: &[u8]) -> Result<Box<[u8]>, BiosDecompressError> {
+    let Some(kind) = BiosCompressionType::detect(data) else {
+        return InvalidHeaderSnafu {}.fail();
+    };
+    kind.decompress(data)
+}
+
+/// Pads compressed data with zeros to a multiple of 4 bytes, as the BIOS functions read whole words.
+pub(crate) fn pad_to_word(out: &mut Vec<u8>) {
+    out.resize(out.len().next_multiple_of(4), 0);
+}
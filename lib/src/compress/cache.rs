@@ -0,0 +1,150 @@
+use std::{
+    fs, io,
+    path::{Path, PathBuf},
+    process,
+    sync::atomic::{AtomicUsize, Ordering},
+};
+
+use sha1::{Digest, Sha1};
+
+use super::lz77::{Lz77, Lz77Strategy};
+use crate::io::{create_dir_all, write_file, FileError};
+
+const LZ77: Lz77 = Lz77 {};
+
+/// Size of the SHA1 checksum at the start of each entry.
+const CHECKSUM_SIZE: usize = 20;
+
+/// Counter to give temporary files unique names, so that concurrent writes of the same entry don't interfere.
+static TEMP_COUNTER: AtomicUsize = AtomicUsize::new(0);
+
+/// On-disk cache of compression results, keyed by a hash of the plain bytes and the compression parameters. It lets
+/// repeated builds skip compressing code modules which haven't changed.
+///
+/// Each entry starts with a SHA1 checksum of its data, so a corrupted entry is recompressed instead of used. Failing to write
+/// an entry is logged as a warning and does not fail the compression. Entries are never evicted, since each one is about the
+/// size of a compressed code module, so use [`Self::clear`] to remove outdated entries.
+#[derive(Clone, Debug)]
+pub struct CompressionCache {
+    dir: PathBuf,
+}
+
+impl CompressionCache {
+    /// Creates a new [`CompressionCache`] which stores its entries in `dir`. The directory is created when the first entry is
+    /// written.
+    pub fn new<P: AsRef<Path>>(dir: P) -> Self {
+        Self { dir: dir.as_ref().to_path_buf() }
+    }
+
+    /// Returns the directory of this cache.
+    pub fn dir(&self) -> &Path {
+        &self.dir
+    }
+
+    /// Removes all entries by deleting the directory of this cache. Does nothing if the directory doesn't exist.
+    ///
+    /// # Errors
+    ///
+    /// This function will return an error if the directory could not be removed.
+    pub fn clear(&self) -> Result<(), io::Error> {
+        match fs::remove_dir_all(&self.dir) {
+            Err(e) if e.kind() != io::ErrorKind::NotFound => Err(e),
+            _ => Ok(()),
+        }
+    }
+
+    /// Compresses `bytes` like [`Lz77::compress_with_strategy`], but returns the cached result if these bytes have been
+    /// compressed with the same parameters before.
+    ///
+    /// # Errors
+    ///
+    /// See [`Lz77::compress_with_strategy`].
+    pub fn lz77(&self, bytes: &[u8], start: usize, strategy: Lz77Strategy) -> Result<Box<[u8]>, io::Error> {
+        let key = Self::key(&format!("lz77 {start} {strategy:?}"), bytes);
+        let path = self.dir.join(format!("{key}.lz77"));
+
+        if let Ok(entry) = fs::read(&path) {
+            if let Some(cached) = Self::verify_entry(&entry) {
+                log::debug!("Using cached compression result {}", path.display());
+                return Ok(cached.into());
+            }
+            log::warn!("Discarding corrupted compression cache entry {}", path.display());
+        }
+
+        let compressed = LZ77.compress_with_strategy(bytes, start, strategy)?;
+        if let Err(e) = self.store(&path, &compressed) {
+            log::warn!("Failed to write compression cache entry {}: {e}", path.display());
+        }
+        Ok(compressed)
+    }
+
+    /// Returns the key of an entry. The library version is included since the output of a compressor may change between
+    /// versions.
+    fn key(parameters: &str, bytes: &[u8]) -> String {
+        let mut sha1 = Sha1::new();
+        sha1.update(env!("CARGO_PKG_VERSION"));
+        sha1.update([0]);
+        sha1.update(parameters);
+        sha1.update([0]);
+        sha1.update(bytes);
+        sha1.finalize().iter().map(|byte| format!("{byte:02x}")).collect()
+    }
+
+    /// Returns the data of an entry if its checksum is correct.
+    fn verify_entry(entry: &[u8]) -> Option<&[u8]> {
+        let (checksum, data) = entry.split_at_checked(CHECKSUM_SIZE)?;
+        (*Sha1::digest(data) == *checksum).then_some(data)
+    }
+
+    fn store(&self, path: &Path, data: &[u8]) -> Result<(), FileError> {
+        create_dir_all(&self.dir)?;
+        let mut entry = Sha1::digest(data).to_vec();
+        entry.extend_from_slice(data);
+
+        // Write to a temporary file first, so that other builds never read a partially written entry
+        let counter = TEMP_COUNTER.fetch_add(1, Ordering::Relaxed);
+        let temp_path = path.with_extension(format!("tmp{}-{counter}", process::id()));
+        write_file(&temp_path, entry)?;
+        if let Err(e) = fs::rename(&temp_path, path) {
+            fs::remove_file(&temp_path).ok();
+            return Err(e.into());
+        }
+        Ok(())
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn reuse_and_repair_entries() {
+        let cache = CompressionCache::new(std::env::temp_dir().join(format!("ds-rom-{}-cache", process::id())));
+        let bytes = (0..0x1000u32).map(|i| (i % 61) as u8 ^ (i / 97) as u8).collect::<Vec<_>>();
+        let strategy = Lz77Strategy::default();
+        let expected = LZ77.compress_with_strategy(&bytes, 0, strategy).unwrap();
+
+        assert_eq!(cache.lz77(&bytes, 0, strategy).unwrap(), expected);
+        let entries = fs::read_dir(cache.dir()).unwrap().map(|entry| entry.unwrap().path()).collect::<Vec<_>>();
+        let [entry_path] = entries.as_slice() else { panic!("expected one entry, found {entries:?}") };
+
+        // A valid entry is returned as is, even if it differs from what the compressor would produce
+        let mut entry = fs::read(entry_path).unwrap();
+        entry.truncate(CHECKSUM_SIZE);
+        entry.extend_from_slice(b"cached");
+        entry.splice(..CHECKSUM_SIZE, Sha1::digest(b"cached"));
+        fs::write(entry_path, &entry).unwrap();
+        assert_eq!(&*cache.lz77(&bytes, 0, strategy).unwrap(), b"cached");
+
+        // A corrupted entry is recompressed and replaced
+        let last = entry.len() - 1;
+        entry[last] ^= 0xff;
+        fs::write(entry_path, &entry).unwrap();
+        assert_eq!(cache.lz77(&bytes, 0, strategy).unwrap(), expected);
+        assert_eq!(CompressionCache::verify_entry(&fs::read(entry_path).unwrap()), Some(&*expected));
+
+        cache.clear().unwrap();
+        assert!(!cache.dir().exists());
+        cache.clear().unwrap();
+    }
+}
@@ -0,0 +1,156 @@
+use super::bios::{
+    pad_to_word, BiosCompressError, BiosCompressionType, BiosDecompressError, BiosHeader, MisalignedSizeSnafu,
+    UnexpectedEndSnafu,
+};
+
+/// De/compresses data using the BIOS difference filter with header byte `0x81` for 8-bit units or `0x82` for 16-bit units.
+/// Each unit is stored as the difference to the previous unit, which doesn't reduce the size by itself but makes smooth data
+/// like audio or gradients easier to compress with other formats.
+#[derive(Clone, Copy, PartialEq, Eq, Debug)]
+pub enum Diff {
+    /// 8-bit units, see [`BiosCompressionType::Diff8`].
+    Bits8,
+    /// 16-bit units, see [`BiosCompressionType::Diff16`].
+    Bits16,
+}
+
+impl Diff {
+    /// Returns the compression type of this diff filter.
+    pub fn kind(self) -> BiosCompressionType {
+        match self {
+            Self::Bits8 => BiosCompressionType::Diff8,
+            Self::Bits16 => BiosCompressionType::Diff16,
+        }
+    }
+
+    /// Returns whether `data` starts with a header of this diff filter.
+    pub fn is_compressed(&self, data: &[u8]) -> bool {
+        BiosCompressionType::detect(data) == Some(self.kind())
+    }
+
+    /// Unfilters `data` and returns the result.
+    ///
+    /// # Errors
+    ///
+    /// This function will return an error if `data` doesn't have a header of this diff filter or ends too early.
+    pub fn decompress(&self, data: &[u8]) -> Result<Box<[u8]>, BiosDecompressError> {
+        Ok(self.decompress_stream(data)?.0)
+    }
+
+    /// Decompresses `data` like [`Self::decompress`], and also returns the offset to the end of the compressed stream.
+    ///
+    /// # Errors
+    ///
+    /// See [`Self::decompress`].
+    pub(crate) fn decompress_stream(&self, data: &[u8]) -> Result<(Box<[u8]>, usize), BiosDecompressError> {
+        let header = BiosHeader::parse_expected(data, self.kind())?;
+        let start = header.header_size;
+        let end = start + header.decompressed_size;
+        let Some(filtered) = data.get(start..end) else {
+            return UnexpectedEndSnafu { offset: data.len() }.fail();
+        };
+
+        let mut out = filtered.to_vec();
+        self.unfilter(&mut out);
+        Ok((out.into_boxed_slice(), end))
+    }
+
+    /// Filters `bytes` and returns the result.
+    ///
+    /// # Errors
+    ///
+    /// This function will return an error if `bytes` is too large for a header, or if its size is not a multiple of the unit
+    /// size.
+    pub fn compress(&self, bytes: &[u8]) -> Result<Box<[u8]>, BiosCompressError> {
+        let kind = self.kind();
+        let unit = kind.unit_size();
+        if bytes.len() % unit != 0 {
+            return MisalignedSizeSnafu { kind, unit, size: bytes.len() }.fail();
+        }
+
+        let mut out = vec![];
+        BiosHeader::new(kind, bytes.len())?.write(&mut out);
+        let start = out.len();
+        out.extend_from_slice(bytes);
+        self.filter(&mut out[start..]);
+
+        pad_to_word(&mut out);
+        Ok(out.into_boxed_slice())
+    }
+
+    /// Replaces each unit in `data` with its difference to the previous unit, without adding a header. This can be used as a
+    /// pre-filter for other compression formats, like the header logo does before Huffman coding. A trailing partial unit is
+    /// left as is.
+    pub fn filter(&self, data: &mut [u8]) {
+        match self {
+            Self::Bits16 => {
+                let mut previous = 0u16;
+                for unit in data.chunks_exact_mut(2) {
+                    let value = u16::from_le_bytes([unit[0], unit[1]]);
+                    unit.copy_from_slice(&value.wrapping_sub(previous).to_le_bytes());
+                    previous = value;
+                }
+            }
+            Self::Bits8 => {
+                let mut previous = 0u8;
+                for byte in data.iter_mut() {
+                    let value = *byte;
+                    *byte = value.wrapping_sub(previous);
+                    previous = value;
+                }
+            }
+        }
+    }
+
+    /// Does the opposite of [`Self::filter`], i.e. replaces each unit in `data` with the sum of it and all previous units.
+    pub fn unfilter(&self, data: &mut [u8]) {
+        match self {
+            Self::Bits16 => {
+                let mut previous = 0u16;
+                for unit in data.chunks_exact_mut(2) {
+                    previous = previous.wrapping_add(u16::from_le_bytes([unit[0], unit[1]]));
+                    unit.copy_from_slice(&previous.to_le_bytes());
+                }
+            }
+            Self::Bits8 => {
+                let mut previous = 0u8;
+                for byte in data.iter_mut() {
+                    previous = previous.wrapping_add(*byte);
+                    *byte = previous;
+                }
+            }
+        }
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn roundtrip(diff: Diff, bytes: &[u8]) {
+        let compressed = diff.compress(bytes).unwrap();
+        assert!(diff.is_compressed(&compressed));
+        assert_eq!(compressed.len() % 4, 0);
+        assert_eq!(&*diff.decompress(&compressed).unwrap(), bytes);
+    }
+
+    #[test]
+    fn roundtrip_edge_cases() {
+        for diff in [Diff::Bits8, Diff::Bits16] {
+            roundtrip(diff, &[]);
+            roundtrip(diff, &[0x81, 0x82]);
+            roundtrip(diff, &(0..0x200u32).map(|i| (i * i / 7) as u8).collect::<Vec<_>>());
+        }
+        roundtrip(Diff::Bits8, &[0x81]);
+        assert!(matches!(Diff::Bits16.compress(&[0x82]), Err(BiosCompressError::MisalignedSize { .. })));
+    }
+
+    #[test]
+    fn known_vector() {
+        let compressed = [0x81, 0x04, 0x00, 0x00, 0x01, 0x01, 0x01, 0x01];
+        assert_eq!(&*Diff::Bits8.decompress(&compressed).unwrap(), &[1, 2, 3, 4]);
+
+        let compressed = [0x82, 0x04, 0x00, 0x00, 0x00, 0x01, 0x00, 0x02];
+        assert_eq!(&*Diff::Bits16.decompress(&compressed).unwrap(), &[0x00, 0x01, 0x00, 0x03]);
+    }
+}
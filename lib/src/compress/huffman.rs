@@ -1,6 +1,20 @@
+use std::{cmp::Reverse, collections::BinaryHeap};
+
 use bitreader::BitReader;
 use rust_bitwriter::BitWriter;
 
+use super::{
+    bios::{BiosCompressError, BiosDecompressError, InvalidTreeSnafu, TreeTooWideSnafu, UnexpectedEndSnafu},
+    diff::Diff,
+};
+
+/// Maximum offset from a tree node to its children, in pairs of nodes.
+const MAX_NODE_OFFSET: usize = 0x3f;
+/// Flag of a tree node telling that its first child is a symbol.
+const CHILD0_IS_DATA: u8 = 0x80;
+/// Flag of a tree node telling that its second child is a symbol.
+const CHILD1_IS_DATA: u8 = 0x40;
+
 /// De/compresses data with [Huffman coding](https://en.wikipedia.org/wiki/Huffman_coding), one nibble at a time. This struct
 /// is not represented as a tree (like it is formally) but instead the Huffman codes are found in an array of length 16, one
 /// for each possible nibble value (2^4).
@@ -73,7 +87,7 @@ impl NibbleHuffman {
         out[..len].copy_from_slice(&data[..len]);
     }
 
-    /// Does the opposite of [Self::data_to_diff16]. If `data` consists of 16-bit integers that look like A, B-A, C-B and so
+    /// Does the opposite of [Self::data_to_diff16]. See also [`Diff::unfilter`]. If `data` consists of 16-bit integers that look like A, B-A, C-B and so
     /// on, this function will recover the original data A, B, C.
     ///
     /// # Panics
@@ -81,16 +95,10 @@ impl NibbleHuffman {
     /// Panics if `data.len()` is not a multiple of 2.
     pub fn diff16_to_data(&self, data: &mut [u8]) {
         assert!(data.len() % 2 == 0);
-        let mut prev = 0;
-        for i in (0..data.len()).step_by(2) {
-            let curr = u16::from_le_bytes([data[i], data[i + 1]]);
-            let value = curr.wrapping_add(prev);
-            data[i..i + 2].copy_from_slice(&value.to_le_bytes());
-            prev = value;
-        }
+        Diff::Bits16.unfilter(data);
     }
 
-    /// Differentiates every 16-bit integer in `data`. For example, if the 16-bit integers are called A, B, C and so on, then
+    /// Differentiates every 16-bit integer in `data`, see also [`Diff::filter`]. For example, if the 16-bit integers are called A, B, C and so on, then
     /// they will be differentiated to A, B-A, C-B and so on.
     ///
     /// If `data` has a lot of repeating values, this will result in plenty of zeros. This benefits Huffman compression, as it
@@ -101,12 +109,388 @@ impl NibbleHuffman {
     /// Panics if `data.len()` is not a multiple of 2.
     pub fn data_to_diff16(&self, data: &mut [u8]) {
         assert!(data.len() % 2 == 0);
-        let mut prev = 0;
-        for i in (0..data.len()).step_by(2) {
-            let curr = u16::from_le_bytes([data[i], data[i + 1]]);
-            let value = curr.wrapping_sub(prev);
-            data[i..i + 2].copy_from_slice(&value.to_le_bytes());
-            prev = curr;
+        Diff::Bits16.filter(data);
+    }
+}
+
+/// Size of the symbols of a [`HuffmanTree`].
+#[derive(Clone, Copy, PartialEq, Eq, Debug)]
+pub enum HuffmanSymbolSize {
+    /// 4-bit symbols, stored in the order low nibble, high nibble.
+    Bits4,
+    /// 8-bit symbols.
+    Bits8,
+}
+
+impl HuffmanSymbolSize {
+    /// Returns the number of bits per symbol.
+    pub fn bits(self) -> u32 {
+        match self {
+            Self::Bits4 => 4,
+            Self::Bits8 => 8,
+        }
+    }
+
+    /// Returns the number of possible symbols.
+    pub fn num_symbols(self) -> usize {
+        1 << self.bits()
+    }
+
+    fn symbols(self, bytes: &[u8]) -> impl Iterator<Item = u8> + '_ {
+        let nibbles = self == Self::Bits4;
+        bytes
+            .iter()
+            .flat_map(move |&byte| if nibbles { [Some(byte & 0xf), Some(byte >> 4)] } else { [Some(byte), None] })
+            .flatten()
+    }
+}
+
+/// A Huffman code of a symbol in a [`HuffmanTree`].
+#[derive(Clone, Copy, PartialEq, Eq, Debug)]
+pub struct HuffmanCode {
+    /// The number of bits in [`Self::bits`].
+    pub length: u8,
+    /// The code, starting from the most significant of the lower [`Self::length`] bits.
+    pub bits: u64,
+}
+
+/// A binary tree of [Huffman codes](https://en.wikipedia.org/wiki/Huffman_coding) for 4-bit or 8-bit symbols. It can be
+/// built with optimal codes from symbol frequencies, and serialized to and from the tree table of the BIOS Huffman format,
+/// see [`HuffmanBios`](super::huffman_bios::HuffmanBios).
+///
+/// The tree table has one byte per node. The first byte is the table size divided by two minus one, the second is the root
+/// node. The lower 6 bits of an internal node are the offset to its pair of children, and the upper 2 bits tell whether the
+/// children are symbols.
+#[derive(Clone, PartialEq, Eq, Debug)]
+pub struct HuffmanTree {
+    symbol_size: HuffmanSymbolSize,
+    nodes: Vec<Node>,
+    root: usize,
+}
+
+#[derive(Clone, Copy, PartialEq, Eq, Debug)]
+enum Node {
+    Leaf(u8),
+    Internal([usize; 2]),
+}
+
+impl HuffmanTree {
+    /// Builds a tree with optimal codes for the given symbol frequencies, where `frequencies[n]` is the frequency of symbol
+    /// `n`. Symbols without a frequency are left out of the tree, except that the tree always has at least two symbols, since
+    /// the root must have two children.
+    pub fn from_frequencies(symbol_size: HuffmanSymbolSize, frequencies: &[u64]) -> Self {
+        let num_symbols = symbol_size.num_symbols();
+        let frequency = |symbol: usize| frequencies.get(symbol).copied().unwrap_or(0);
+        let mut used: Vec<usize> = (0..num_symbols).filter(|&symbol| frequency(symbol) > 0).collect();
+        for symbol in 0..num_symbols {
+            if used.len() >= 2 {
+                break;
+            }
+            if !used.contains(&symbol) {
+                used.push(symbol);
+            }
+        }
+        used.sort();
+
+        let mut nodes = vec![];
+        let mut heap = BinaryHeap::new();
+        for symbol in used {
+            heap.push(Reverse((frequency(symbol), nodes.len())));
+            nodes.push(Node::Leaf(symbol as u8));
+        }
+        while heap.len() > 1 {
+            let Reverse((frequency0, node0)) = heap.pop().unwrap();
+            let Reverse((frequency1, node1)) = heap.pop().unwrap();
+            heap.push(Reverse((frequency0.saturating_add(frequency1), nodes.len())));
+            nodes.push(Node::Internal([node0, node1]));
+        }
+        let root = nodes.len() - 1;
+        Self { symbol_size, nodes, root }
+    }
+
+    /// Builds a tree with optimal codes for the symbols in `bytes`.
+    pub fn from_data(symbol_size: HuffmanSymbolSize, bytes: &[u8]) -> Self {
+        let mut frequencies = vec![0u64; symbol_size.num_symbols()];
+        for symbol in symbol_size.symbols(bytes) {
+            frequencies[symbol as usize] += 1;
+        }
+        Self::from_frequencies(symbol_size, &frequencies)
+    }
+
+    /// Returns the size of the symbols in this tree.
+    pub fn symbol_size(&self) -> HuffmanSymbolSize {
+        self.symbol_size
+    }
+
+    /// Returns the code of each symbol, i.e. `codes[n]` is the code of symbol `n`, or `None` if the symbol is not in this tree.
+    pub fn codes(&self) -> Vec<Option<HuffmanCode>> {
+        let mut codes = vec![None; self.symbol_size.num_symbols()];
+        let mut visited = vec![false; self.nodes.len()];
+        self.assign_codes(self.root, HuffmanCode { length: 0, bits: 0 }, &mut codes, &mut visited);
+        codes
+    }
+
+    fn assign_codes(&self, index: usize, code: HuffmanCode, codes: &mut [Option<HuffmanCode>], visited: &mut [bool]) {
+        // A parsed table may share nodes, in which case the first code found is used
+        if std::mem::replace(&mut visited[index], true) {
+            return;
+        }
+        match self.nodes[index] {
+            Node::Leaf(symbol) => codes[symbol as usize] = Some(code),
+            Node::Internal(children) => {
+                for (bit, child) in children.into_iter().enumerate() {
+                    let code = HuffmanCode { length: code.length + 1, bits: (code.bits << 1) | bit as u64 };
+                    self.assign_codes(child, code, codes, visited);
+                }
+            }
+        }
+    }
+
+    /// Parses the tree table at `data[start..]`, and returns the tree along with the offset where the table ends.
+    ///
+    /// # Errors
+    ///
+    /// This function will return an error if the table ends too early, or if a node points outside of the table or to a
+    /// symbol which doesn't fit in the symbol size.
+    pub fn parse_table(
+        symbol_size: HuffmanSymbolSize,
+        data: &[u8],
+        start: usize,
+    ) -> Result<(Self, usize), BiosDecompressError> {
+        let Some(&table_size) = data.get(start) else {
+            return UnexpectedEndSnafu { offset: start }.fail();
+        };
+        let end = start + (table_size as usize + 1) * 2;
+        let Some(table) = data.get(start..end) else {
+            return UnexpectedEndSnafu { offset: data.len() }.fail();
+        };
+
+        let mut tree = Self { symbol_size, nodes: vec![], root: 0 };
+        let mut parsed = vec![None; table.len() * 2];
+        tree.root = tree.parse_node(table, start, 1, false, &mut parsed)?;
+        Ok((tree, end))
+    }
+
+    /// Parses the node at `table[index]`. Nodes may point to the same pair of children, so `parsed` maps each table index
+    /// and kind of node to the node parsed from it, to not expand shared pairs more than once.
+    fn parse_node(
+        &mut self,
+        table: &[u8],
+        start: usize,
+        index: usize,
+        is_data: bool,
+        parsed: &mut [Option<usize>],
+    ) -> Result<usize, BiosDecompressError> {
+        let key = index * 2 + is_data as usize;
+        if let Some(node) = parsed[key] {
+            return Ok(node);
+        }
+
+        let value = table[index];
+        let node = if is_data {
+            if value as usize >= self.symbol_size.num_symbols() {
+                return InvalidTreeSnafu { offset: start + index }.fail();
+            }
+            Node::Leaf(value)
+        } else {
+            // Children always come after their parent, so this can't loop forever
+            let pair = (index & !1) + (value & 0x3f) as usize * 2 + 2;
+            if pair + 1 >= table.len() {
+                return InvalidTreeSnafu { offset: start + index }.fail();
+            }
+            let child0 = self.parse_node(table, start, pair, value & CHILD0_IS_DATA != 0, parsed)?;
+            let child1 = self.parse_node(table, start, pair + 1, value & CHILD1_IS_DATA != 0, parsed)?;
+            Node::Internal([child0, child1])
+        };
+        self.nodes.push(node);
+        parsed[key] = Some(self.nodes.len() - 1);
+        Ok(self.nodes.len() - 1)
+    }
+
+    /// Serializes this tree into a tree table. Since a node can only point 63 pairs ahead, nodes are laid out depth-first to
+    /// keep subtrees close together, except when a pending node would otherwise end up too far from its children. The table
+    /// is padded to a whole number of words.
+    ///
+    /// # Errors
+    ///
+    /// This function will return an error if the tree is too wide for any node to reach its children.
+    pub fn table(&self) -> Result<Vec<u8>, BiosCompressError> {
+        let num_internal = self.nodes.iter().filter(|node| matches!(node, Node::Internal(_))).count();
+        // Pad to a whole number of words so the bitstream stays aligned
+        let num_pairs = (num_internal + 1).next_multiple_of(2);
+        if num_pairs > 0x100 {
+            return TreeTooWideSnafu {}.fail();
+        }
+        let mut table = vec![0u8; num_pairs * 2];
+        table[0] = (num_pairs - 1) as u8;
+
+        // Internal nodes whose children haven't been placed yet, as (table index, node index)
+        let mut pending = vec![(1, self.root)];
+        let mut next_pair = 1;
+        while !pending.is_empty() {
+            let deadline = |index: usize| index / 2 + 1 + MAX_NODE_OFFSET;
+            let mut by_deadline: Vec<usize> = pending.iter().map(|&(index, _)| deadline(index)).collect();
+            by_deadline.sort();
+            let has_slack = by_deadline.iter().enumerate().all(|(i, &deadline)| next_pair + i < deadline);
+            let chosen = if has_slack { pending.len() - 1 } else { (0..pending.len()).min_by_key(|&i| pending[i].0).unwrap() };
+            let (index, node) = pending.remove(chosen);
+
+            let Node::Internal(children) = self.nodes[node] else { unreachable!() };
+            if next_pair >= num_pairs {
+                // Only happens for parsed trees with shared nodes, which are written out once per parent
+                return TreeTooWideSnafu {}.fail();
+            }
+            let pair_index = next_pair * 2;
+            next_pair += 1;
+            let offset = (pair_index - (index & !1) - 2) / 2;
+            if offset > MAX_NODE_OFFSET {
+                return TreeTooWideSnafu {}.fail();
+            }
+
+            let mut value = offset as u8;
+            for (i, child) in children.into_iter().enumerate() {
+                match self.nodes[child] {
+                    Node::Leaf(symbol) => {
+                        table[pair_index + i] = symbol;
+                        value |= if i == 0 { CHILD0_IS_DATA } else { CHILD1_IS_DATA };
+                    }
+                    Node::Internal(_) => pending.push((pair_index + i, child)),
+                }
+            }
+            table[index] = value;
+        }
+
+        Ok(table)
+    }
+
+    /// Encodes `bytes` and appends the bitstream to `out`, as 32-bit little-endian words which are filled from the most
+    /// significant bit.
+    ///
+    /// # Panics
+    ///
+    /// Panics if `bytes` contains a symbol which is not in this tree.
+    pub fn encode(&self, bytes: &[u8], out: &mut Vec<u8>) {
+        let codes = self.codes();
+        let mut word = 0u32;
+        let mut num_bits = 0;
+        for symbol in self.symbol_size.symbols(bytes) {
+            let code = codes[symbol as usize].expect("symbol should be in the Huffman tree");
+            for bit in (0..code.length).rev() {
+                word = (word << 1) | ((code.bits >> bit) & 1) as u32;
+                num_bits += 1;
+                if num_bits == 32 {
+                    out.extend(word.to_le_bytes());
+                    word = 0;
+                    num_bits = 0;
+                }
+            }
+        }
+        if num_bits > 0 {
+            out.extend((word << (32 - num_bits)).to_le_bytes());
+        }
+    }
+
+    /// Decodes `size` bytes from the bitstream at `data[start..]`, see [`Self::encode`].
+    ///
+    /// # Errors
+    ///
+    /// This function will return an error if the bitstream ends before `size` bytes are decoded.
+    pub fn decode(&self, data: &[u8], start: usize, size: usize) -> Result<Vec<u8>, BiosDecompressError> {
+        Ok(self.decode_stream(data, start, size)?.0)
+    }
+
+    /// Decodes `size` bytes like [`Self::decode`], and also returns the offset to the end of the bitstream.
+    ///
+    /// # Errors
+    ///
+    /// See [`Self::decode`].
+    pub(crate) fn decode_stream(
+        &self,
+        data: &[u8],
+        start: usize,
+        size: usize,
+    ) -> Result<(Vec<u8>, usize), BiosDecompressError> {
+        let symbol_bits = self.symbol_size.bits();
+        let mut out = Vec::with_capacity(size);
+        let mut symbol_buffer = 0u8;
+        let mut num_buffered_bits = 0;
+        let mut node = self.root;
+        let mut offset = start;
+        'words: while out.len() < size {
+            let Some(word) = data.get(offset..offset + 4) else {
+                return UnexpectedEndSnafu { offset }.fail();
+            };
+            let word = u32::from_le_bytes(word.try_into().unwrap());
+            offset += 4;
+
+            for bit in (0..32).rev() {
+                let Node::Internal(children) = self.nodes[node] else { unreachable!() };
+                node = children[((word >> bit) & 1) as usize];
+                let Node::Leaf(symbol) = self.nodes[node] else {
+                    continue;
+                };
+
+                node = self.root;
+                symbol_buffer |= symbol << num_buffered_bits;
+                num_buffered_bits += symbol_bits;
+                if num_buffered_bits == 8 {
+                    out.push(symbol_buffer);
+                    symbol_buffer = 0;
+                    num_buffered_bits = 0;
+                    if out.len() >= size {
+                        break 'words;
+                    }
+                }
+            }
+        }
+
+        Ok((out, offset))
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    /// Returns a tree table where both nodes of every pair point to the next pair, so the tree has 2^depth paths.
+    fn self_sharing_table(num_pairs: usize) -> Vec<u8> {
+        let mut table = vec![0u8; num_pairs * 2];
+        table[0] = (num_pairs - 1) as u8;
+        // Every internal node has offset 0, i.e. points to the next pair
+        let last_internal_pair = num_pairs - 2;
+        table[last_internal_pair * 2] = CHILD0_IS_DATA | CHILD1_IS_DATA;
+        table[last_internal_pair * 2 + 1] = CHILD0_IS_DATA | CHILD1_IS_DATA;
+        table[num_pairs * 2 - 2] = b'a';
+        table[num_pairs * 2 - 1] = b'b';
+        table
+    }
+
+    #[test]
+    fn parse_self_sharing_table() {
+        let table = self_sharing_table(256);
+        let (tree, end) = HuffmanTree::parse_table(HuffmanSymbolSize::Bits8, &table, 0).unwrap();
+        assert_eq!(end, table.len());
+        assert!(tree.nodes.len() <= table.len() * 2);
+
+        // The path to each symbol is 255 bits long, with the last bit choosing the symbol
+        let mut data = table.clone();
+        data.extend([0; 28]);
+        data.extend(1u32.to_le_bytes());
+        assert_eq!(tree.decode(&data, table.len(), 1).unwrap(), vec![b'a']);
+
+        let codes = tree.codes();
+        assert_eq!(codes[b'a' as usize].as_ref().map(|code| code.length), Some(255));
+        assert!(tree.table().is_err());
+    }
+
+    #[test]
+    fn roundtrip_table() {
+        for symbol_size in [HuffmanSymbolSize::Bits4, HuffmanSymbolSize::Bits8] {
+            let tree = HuffmanTree::from_data(symbol_size, b"abracadabra, the quick brown fox");
+            let table = tree.table().unwrap();
+            let (parsed, end) = HuffmanTree::parse_table(symbol_size, &table, 0).unwrap();
+            assert_eq!(end, table.len());
+            assert_eq!(parsed.codes(), tree.codes());
         }
     }
 }
@@ -0,0 +1,140 @@
+use super::{
+    bios::{BiosCompressError, BiosCompressionType, BiosDecompressError, BiosHeader},
+    diff::Diff,
+    huffman::{HuffmanSymbolSize, HuffmanTree},
+};
+
+/// De/compresses data using the BIOS Huffman format with header byte `0x24` for 4-bit symbols or `0x28` for 8-bit symbols.
+/// The header is followed by a tree table and a bitstream of 32-bit little-endian words which are read from the most
+/// significant bit. 4-bit symbols are stored in the order low nibble, high nibble. See [`HuffmanTree`] for the tree table.
+#[derive(Clone, Copy, PartialEq, Eq, Debug)]
+pub enum HuffmanBios {
+    /// 4-bit symbols, see [`BiosCompressionType::Huffman4`].
+    Bits4,
+    /// 8-bit symbols, see [`BiosCompressionType::Huffman8`].
+    Bits8,
+}
+
+impl HuffmanBios {
+    /// Returns the compression type of this Huffman format.
+    pub fn kind(self) -> BiosCompressionType {
+        match self {
+            Self::Bits4 => BiosCompressionType::Huffman4,
+            Self::Bits8 => BiosCompressionType::Huffman8,
+        }
+    }
+
+    /// Returns the number of bits per symbol.
+    pub fn symbol_bits(self) -> u32 {
+        self.symbol_size().bits()
+    }
+
+    /// Returns the size of the symbols of this Huffman format.
+    pub fn symbol_size(self) -> HuffmanSymbolSize {
+        match self {
+            Self::Bits4 => HuffmanSymbolSize::Bits4,
+            Self::Bits8 => HuffmanSymbolSize::Bits8,
+        }
+    }
+
+    /// Returns whether `data` starts with a header of this Huffman format.
+    pub fn is_compressed(&self, data: &[u8]) -> bool {
+        BiosCompressionType::detect(data) == Some(self.kind())
+    }
+
+    /// Decompresses `data` and returns the result.
+    ///
+    /// # Errors
+    ///
+    /// This function will return an error if `data` doesn't have a header of this Huffman format, ends too early or has an
+    /// invalid tree table.
+    pub fn decompress(&self, data: &[u8]) -> Result<Box<[u8]>, BiosDecompressError> {
+        Ok(self.decompress_stream(data)?.0)
+    }
+
+    /// Decompresses `data` like [`Self::decompress`], and also returns the offset to the end of the compressed stream.
+    ///
+    /// # Errors
+    ///
+    /// See [`Self::decompress`].
+    pub(crate) fn decompress_stream(&self, data: &[u8]) -> Result<(Box<[u8]>, usize), BiosDecompressError> {
+        let header = BiosHeader::parse_expected(data, self.kind())?;
+        let (tree, tree_end) = HuffmanTree::parse_table(self.symbol_size(), data, header.header_size)?;
+        let (out, end) = tree.decode_stream(data, tree_end, header.decompressed_size)?;
+        Ok((out.into_boxed_slice(), end))
+    }
+
+    /// Decompresses `data` like [`Self::decompress`], and then reverses the pre-filter `filter` which was applied by
+    /// [`Self::compress_filtered`].
+    ///
+    /// # Errors
+    ///
+    /// See [`Self::decompress`].
+    pub fn decompress_filtered(&self, data: &[u8], filter: Diff) -> Result<Box<[u8]>, BiosDecompressError> {
+        let mut out = self.decompress(data)?;
+        filter.unfilter(&mut out);
+        Ok(out)
+    }
+
+    /// Compresses `bytes` using an optimal Huffman tree for its symbols and returns the result.
+    ///
+    /// # Errors
+    ///
+    /// This function will return an error if `bytes` is too large for a header.
+    pub fn compress(&self, bytes: &[u8]) -> Result<Box<[u8]>, BiosCompressError> {
+        let tree = HuffmanTree::from_data(self.symbol_size(), bytes);
+
+        let mut out = vec![];
+        BiosHeader::new(self.kind(), bytes.len())?.write(&mut out);
+        out.extend(tree.table()?);
+        tree.encode(bytes, &mut out);
+
+        Ok(out.into_boxed_slice())
+    }
+
+    /// Applies the pre-filter `filter` to `bytes` and then compresses them like [`Self::compress`]. Filtering smooth data
+    /// first, like the header logo does, leaves many repeated symbols which compress better.
+    ///
+    /// # Errors
+    ///
+    /// See [`Self::compress`].
+    pub fn compress_filtered(&self, bytes: &[u8], filter: Diff) -> Result<Box<[u8]>, BiosCompressError> {
+        let mut filtered = bytes.to_vec();
+        filter.filter(&mut filtered);
+        self.compress(&filtered)
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn roundtrip(huffman: HuffmanBios, bytes: &[u8]) {
+        let compressed = huffman.compress(bytes).unwrap();
+        assert!(huffman.is_compressed(&compressed));
+        assert_eq!(compressed.len() % 4, 0);
+        assert_eq!(&*huffman.decompress(&compressed).unwrap(), bytes);
+    }
+
+    #[test]
+    fn roundtrip_edge_cases() {
+        for huffman in [HuffmanBios::Bits4, HuffmanBios::Bits8] {
+            roundtrip(huffman, &[]);
+            roundtrip(huffman, &[0x28]);
+            roundtrip(huffman, &[0x28; 0x40]);
+            roundtrip(huffman, b"abracadabra, the quick brown fox");
+            roundtrip(huffman, &(0..0x3000u32).map(|i| (i * i / 7) as u8).collect::<Vec<_>>());
+        }
+    }
+
+    #[test]
+    fn known_vector() {
+        // The root has the data children 'a' and 'b', followed by the bits 0 and 1
+        let compressed = [0x28, 0x02, 0x00, 0x00, 0x01, 0xc0, b'a', b'b', 0x00, 0x00, 0x00, 0x40];
+        assert_eq!(&*HuffmanBios::Bits8.decompress(&compressed).unwrap(), b"ab");
+
+        // The same tree with the nibbles of 0x21, low nibble first
+        let compressed = [0x24, 0x01, 0x00, 0x00, 0x01, 0xc0, 0x01, 0x02, 0x00, 0x00, 0x00, 0x40];
+        assert_eq!(&*HuffmanBios::Bits4.decompress(&compressed).unwrap(), &[0x21]);
+    }
+}
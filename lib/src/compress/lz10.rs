@@ -0,0 +1,170 @@
+use super::{
+    bios::{
+        pad_to_word, BiosCompressError, BiosCompressionType, BiosDecompressError, BiosHeader, InvalidDistanceSnafu,
+        UnexpectedEndSnafu, VRAM_MIN_DISTANCE,
+    },
+    match_finder::{MatchFinder, MIN_MATCH},
+};
+
+/// Maximum length of a length-distance pair.
+const MAX_LENGTH: usize = MIN_MATCH + 0xf;
+/// Maximum distance of a length-distance pair.
+const MAX_DISTANCE: usize = 0x1000;
+
+/// De/compresses data using the BIOS LZ77 format with header byte `0x10`. Every block starts with a flag byte, where each
+/// bit from most to least significant tells whether the next token is a literal byte (0) or a 2-byte length-distance pair
+/// (1). A pair has a 4-bit length of 3 to 18 bytes and a 12-bit distance of 1 to 4096 bytes.
+pub struct Lz10 {}
+
+impl Lz10 {
+    /// Returns whether `data` starts with an LZ10 header.
+    pub fn is_compressed(&self, data: &[u8]) -> bool {
+        BiosCompressionType::detect(data) == Some(BiosCompressionType::Lz10)
+    }
+
+    /// Decompresses `data` and returns the result.
+    ///
+    /// # Errors
+    ///
+    /// This function will return an error if `data` doesn't have an LZ10 header, ends too early or has a length-distance
+    /// pair pointing before the start of the decompressed data.
+    pub fn decompress(&self, data: &[u8]) -> Result<Box<[u8]>, BiosDecompressError> {
+        Ok(self.decompress_stream(data)?.0)
+    }
+
+    /// Decompresses `data` like [`Self::decompress`], and also returns the offset to the end of the compressed stream.
+    ///
+    /// # Errors
+    ///
+    /// See [`Self::decompress`].
+    pub(crate) fn decompress_stream(&self, data: &[u8]) -> Result<(Box<[u8]>, usize), BiosDecompressError> {
+        let header = BiosHeader::parse_expected(data, BiosCompressionType::Lz10)?;
+        let size = header.decompressed_size;
+        let mut out = Vec::with_capacity(size);
+        let mut offset = header.header_size;
+        let byte_at = |offset: usize| data.get(offset).copied().ok_or_else(|| UnexpectedEndSnafu { offset }.build());
+
+        while out.len() < size {
+            let flags = byte_at(offset)?;
+            offset += 1;
+            for bit in (0..8).rev() {
+                if out.len() >= size {
+                    break;
+                }
+                if flags & (1 << bit) == 0 {
+                    out.push(byte_at(offset)?);
+                    offset += 1;
+                } else {
+                    let b0 = byte_at(offset)? as usize;
+                    let b1 = byte_at(offset + 1)? as usize;
+                    let length = (b0 >> 4) + MIN_MATCH;
+                    let distance = ((b0 & 0xf) << 8 | b1) + 1;
+                    if distance > out.len() {
+                        return InvalidDistanceSnafu { offset, distance, position: out.len() }.fail();
+                    }
+                    offset += 2;
+                    for _ in 0..length.min(size - out.len()) {
+                        out.push(out[out.len() - distance]);
+                    }
+                }
+            }
+        }
+
+        Ok((out.into_boxed_slice(), offset))
+    }
+
+    /// Compresses `bytes` greedily and returns the result.
+    ///
+    /// # Errors
+    ///
+    /// This function will return an error if `bytes` is too large for a header.
+    pub fn compress(&self, bytes: &[u8]) -> Result<Box<[u8]>, BiosCompressError> {
+        self.compress_with_min_distance(bytes, 1)
+    }
+
+    /// Compresses `bytes` like [`Self::compress`], but without pairs of distance 1, which the BIOS VRAM decompressor can't
+    /// decode as it writes 16 bits at a time.
+    ///
+    /// # Errors
+    ///
+    /// See [`Self::compress`].
+    pub fn compress_vram_safe(&self, bytes: &[u8]) -> Result<Box<[u8]>, BiosCompressError> {
+        self.compress_with_min_distance(bytes, VRAM_MIN_DISTANCE)
+    }
+
+    fn compress_with_min_distance(&self, bytes: &[u8], min_distance: usize) -> Result<Box<[u8]>, BiosCompressError> {
+        let mut out = vec![];
+        BiosHeader::new(BiosCompressionType::Lz10, bytes.len())?.write(&mut out);
+
+        let mut finder = MatchFinder::new(bytes, MAX_DISTANCE);
+        let mut pos = 0;
+        while pos < bytes.len() {
+            let flags_offset = out.len();
+            out.push(0);
+            for bit in (0..8).rev() {
+                if pos >= bytes.len() {
+                    break;
+                }
+                match finder.find(pos, MAX_LENGTH, min_distance) {
+                    Some(found) => {
+                        let value = ((found.length - MIN_MATCH) << 12) | (found.distance - 1);
+                        out.extend((value as u16).to_be_bytes());
+                        out[flags_offset] |= 1 << bit;
+                        pos += found.length;
+                    }
+                    None => {
+                        out.push(bytes[pos]);
+                        pos += 1;
+                    }
+                }
+            }
+        }
+
+        pad_to_word(&mut out);
+        Ok(out.into_boxed_slice())
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn roundtrip(bytes: &[u8]) {
+        let compressed = Lz10 {}.compress(bytes).unwrap();
+        assert!(Lz10 {}.is_compressed(&compressed));
+        assert_eq!(compressed.len() % 4, 0);
+        assert_eq!(&*Lz10 {}.decompress(&compressed).unwrap(), bytes);
+    }
+
+    #[test]
+    fn roundtrip_edge_cases() {
+        roundtrip(&[]);
+        roundtrip(&[0x10]);
+        roundtrip(&[0; 0x12]);
+        roundtrip(&[0; 0x13]);
+        roundtrip(b"abracadabra, abracadabra");
+        roundtrip(&(0..0x3000u32).map(|i| (i * i / 7) as u8).collect::<Vec<_>>());
+    }
+
+    #[test]
+    fn vram_safe_avoids_distance_1() {
+        let bytes = [b'A'; 0x20];
+        // One literal and then pairs with distance 1
+        assert_eq!(
+            &*Lz10 {}.compress(&bytes).unwrap(),
+            [0x10, 0x20, 0x00, 0x00, 0x60, b'A', 0xf0, 0x00, 0xa0, 0x00, 0x00, 0x00]
+        );
+        // Two literals and then pairs with distance 2
+        let compressed = Lz10 {}.compress_vram_safe(&bytes).unwrap();
+        assert_eq!(&*compressed, [0x10, 0x20, 0x00, 0x00, 0x30, b'A', b'A', 0xf0, 0x01, 0x90, 0x01, 0x00]);
+        assert_eq!(&*Lz10 {}.decompress(&compressed).unwrap(), bytes);
+    }
+
+    #[test]
+    fn known_vector() {
+        // Two literals followed by a pair with length 6 and distance 2
+        let compressed = [0x10, 0x08, 0x00, 0x00, 0x20, b'A', b'B', 0x30, 0x01, 0x00, 0x00, 0x00];
+        assert_eq!(&*Lz10 {}.decompress(&compressed).unwrap(), b"ABABABAB");
+        assert_eq!(&*Lz10 {}.compress(b"ABABABAB").unwrap(), &compressed);
+    }
+}
@@ -0,0 +1,215 @@
+use super::{
+    bios::{
+        pad_to_word, BiosCompressError, BiosCompressionType, BiosDecompressError, BiosHeader, InvalidDistanceSnafu,
+        UnexpectedEndSnafu, VRAM_MIN_DISTANCE,
+    },
+    match_finder::MatchFinder,
+};
+
+/// Minimum length of a 3-byte length-distance pair.
+const MIN_LENGTH_3: usize = 0x11;
+/// Minimum length of a 4-byte length-distance pair.
+const MIN_LENGTH_4: usize = 0x111;
+/// Maximum length of a length-distance pair.
+const MAX_LENGTH: usize = MIN_LENGTH_4 + 0xffff;
+/// Maximum distance of a length-distance pair.
+const MAX_DISTANCE: usize = 0x1000;
+
+/// De/compresses data using the extended BIOS LZ77 format with header byte `0x11`. It works like [`Lz10`](super::lz10::Lz10),
+/// but the first nibble of a length-distance pair selects its size:
+/// - `0`: 3-byte pair with an 8-bit length of 17 to 272 bytes.
+/// - `1`: 4-byte pair with a 16-bit length of 273 to 65808 bytes.
+/// - Otherwise: 2-byte pair where the nibble is the length minus one.
+///
+/// All pairs end with a 12-bit distance of 1 to 4096 bytes.
+pub struct Lz11 {}
+
+impl Lz11 {
+    /// Returns whether `data` starts with an LZ11 header.
+    pub fn is_compressed(&self, data: &[u8]) -> bool {
+        BiosCompressionType::detect(data) == Some(BiosCompressionType::Lz11)
+    }
+
+    /// Decompresses `data` and returns the result.
+    ///
+    /// # Errors
+    ///
+    /// This function will return an error if `data` doesn't have an LZ11 header, ends too early or has a length-distance
+    /// pair pointing before the start of the decompressed data.
+    pub fn decompress(&self, data: &[u8]) -> Result<Box<[u8]>, BiosDecompressError> {
+        Ok(self.decompress_stream(data)?.0)
+    }
+
+    /// Decompresses `data` like [`Self::decompress`], and also returns the offset to the end of the compressed stream.
+    ///
+    /// # Errors
+    ///
+    /// See [`Self::decompress`].
+    pub(crate) fn decompress_stream(&self, data: &[u8]) -> Result<(Box<[u8]>, usize), BiosDecompressError> {
+        let header = BiosHeader::parse_expected(data, BiosCompressionType::Lz11)?;
+        let size = header.decompressed_size;
+        let mut out = Vec::with_capacity(size);
+        let mut offset = header.header_size;
+        let byte_at =
+            |offset: usize| data.get(offset).copied().map(usize::from).ok_or_else(|| UnexpectedEndSnafu { offset }.build());
+
+        while out.len() < size {
+            let flags = byte_at(offset)?;
+            offset += 1;
+            for bit in (0..8).rev() {
+                if out.len() >= size {
+                    break;
+                }
+                if flags & (1 << bit) == 0 {
+                    out.push(byte_at(offset)? as u8);
+                    offset += 1;
+                    continue;
+                }
+
+                let pair_offset = offset;
+                let b0 = byte_at(offset)?;
+                let (length, distance_high) = match b0 >> 4 {
+                    0 => {
+                        let b1 = byte_at(offset + 1)?;
+                        offset += 2;
+                        ((((b0 & 0xf) << 4) | (b1 >> 4)) + MIN_LENGTH_3, b1 & 0xf)
+                    }
+                    1 => {
+                        let b1 = byte_at(offset + 1)?;
+                        let b2 = byte_at(offset + 2)?;
+                        offset += 3;
+                        ((((b0 & 0xf) << 12) | (b1 << 4) | (b2 >> 4)) + MIN_LENGTH_4, b2 & 0xf)
+                    }
+                    nibble => {
+                        offset += 1;
+                        (nibble + 1, b0 & 0xf)
+                    }
+                };
+                let distance = ((distance_high << 8) | byte_at(offset)?) + 1;
+                offset += 1;
+                if distance > out.len() {
+                    return InvalidDistanceSnafu { offset: pair_offset, distance, position: out.len() }.fail();
+                }
+                for _ in 0..length.min(size - out.len()) {
+                    out.push(out[out.len() - distance]);
+                }
+            }
+        }
+
+        Ok((out.into_boxed_slice(), offset))
+    }
+
+    /// Compresses `bytes` greedily and returns the result.
+    ///
+    /// # Errors
+    ///
+    /// This function will return an error if `bytes` is too large for a header.
+    pub fn compress(&self, bytes: &[u8]) -> Result<Box<[u8]>, BiosCompressError> {
+        self.compress_with_min_distance(bytes, 1)
+    }
+
+    /// Compresses `bytes` like [`Self::compress`], but without pairs of distance 1, which the BIOS VRAM decompressor can't
+    /// decode as it writes 16 bits at a time.
+    ///
+    /// # Errors
+    ///
+    /// See [`Self::compress`].
+    pub fn compress_vram_safe(&self, bytes: &[u8]) -> Result<Box<[u8]>, BiosCompressError> {
+        self.compress_with_min_distance(bytes, VRAM_MIN_DISTANCE)
+    }
+
+    fn compress_with_min_distance(&self, bytes: &[u8], min_distance: usize) -> Result<Box<[u8]>, BiosCompressError> {
+        let mut out = vec![];
+        BiosHeader::new(BiosCompressionType::Lz11, bytes.len())?.write(&mut out);
+
+        let mut finder = MatchFinder::new(bytes, MAX_DISTANCE);
+        let mut pos = 0;
+        while pos < bytes.len() {
+            let flags_offset = out.len();
+            out.push(0);
+            for bit in (0..8).rev() {
+                if pos >= bytes.len() {
+                    break;
+                }
+                match finder.find(pos, MAX_LENGTH, min_distance) {
+                    Some(found) => {
+                        Self::write_pair(&mut out, found.length, found.distance);
+                        out[flags_offset] |= 1 << bit;
+                        pos += found.length;
+                    }
+                    None => {
+                        out.push(bytes[pos]);
+                        pos += 1;
+                    }
+                }
+            }
+        }
+
+        pad_to_word(&mut out);
+        Ok(out.into_boxed_slice())
+    }
+
+    fn write_pair(out: &mut Vec<u8>, length: usize, distance: usize) {
+        let distance = distance - 1;
+        if length >= MIN_LENGTH_4 {
+            let length = length - MIN_LENGTH_4;
+            out.push(0x10 | (length >> 12) as u8);
+            out.push((length >> 4) as u8);
+            out.push(((length & 0xf) << 4 | distance >> 8) as u8);
+        } else if length >= MIN_LENGTH_3 {
+            let length = length - MIN_LENGTH_3;
+            out.push((length >> 4) as u8);
+            out.push(((length & 0xf) << 4 | distance >> 8) as u8);
+        } else {
+            out.push(((length - 1) << 4 | distance >> 8) as u8);
+        }
+        out.push(distance as u8);
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn roundtrip(bytes: &[u8]) -> Box<[u8]> {
+        let compressed = Lz11 {}.compress(bytes).unwrap();
+        assert!(Lz11 {}.is_compressed(&compressed));
+        assert_eq!(compressed.len() % 4, 0);
+        assert_eq!(&*Lz11 {}.decompress(&compressed).unwrap(), bytes);
+        compressed
+    }
+
+    #[test]
+    fn roundtrip_edge_cases() {
+        roundtrip(&[]);
+        roundtrip(&[0x11]);
+        roundtrip(b"abracadabra, abracadabra");
+        roundtrip(&(0..0x3000u32).map(|i| (i * i / 7) as u8).collect::<Vec<_>>());
+        roundtrip(&[0; MAX_LENGTH + 2]);
+    }
+
+    #[test]
+    fn length_boundaries() {
+        // One literal followed by a pair with distance 1, whose size depends on the length
+        let pair = |length: usize| roundtrip(&vec![b'A'; length + 1])[6..].to_vec();
+        assert_eq!(pair(MIN_LENGTH_3 - 1), [0xf0, 0x00]);
+        assert_eq!(pair(MIN_LENGTH_3), [0x00, 0x00, 0x00, 0x00, 0x00, 0x00]);
+        assert_eq!(pair(MIN_LENGTH_4 - 1), [0x0f, 0xf0, 0x00, 0x00, 0x00, 0x00]);
+        assert_eq!(pair(MIN_LENGTH_4), [0x10, 0x00, 0x00, 0x00, 0x00, 0x00]);
+    }
+
+    #[test]
+    fn vram_safe_avoids_distance_1() {
+        let bytes = [b'A'; 0x20];
+        // Two literals and then a 3-byte pair with length 30 and distance 2
+        let compressed = Lz11 {}.compress_vram_safe(&bytes).unwrap();
+        assert_eq!(&*compressed, [0x11, 0x20, 0x00, 0x00, 0x20, b'A', b'A', 0x00, 0xd0, 0x01, 0x00, 0x00]);
+        assert_eq!(&*Lz11 {}.decompress(&compressed).unwrap(), bytes);
+    }
+
+    #[test]
+    fn known_vector() {
+        let compressed = [0x11, 0x12, 0x00, 0x00, 0x40, b'A', 0x00, 0x00, 0x00, 0x00, 0x00, 0x00];
+        assert_eq!(&*Lz11 {}.decompress(&compressed).unwrap(), &[b'A'; 0x12]);
+    }
+}
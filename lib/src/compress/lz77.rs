@@ -5,12 +5,26 @@ use std::{
     io::{self, Write},
 };
 
+use serde::{Deserialize, Serialize};
 use snafu::Snafu;
 
+use super::match_finder::MatchFinder;
+
 /// De/compresses data using a backwards [LZ77])(https://en.wikipedia.org/wiki/LZ77_and_LZ78#LZ77) algorithm. "Backwards"
 /// refers to starting the de/compression from the end of the file and moving towards the beginning.
 pub struct Lz77 {}
 
+/// Strategy for choosing LZ77 tokens when compressing.
+#[derive(Clone, Copy, PartialEq, Eq, Debug, Default)]
+pub enum Lz77Strategy {
+    /// Always takes the longest match, like the retail compressor. Required for built ROMs to match the originals.
+    #[default]
+    Greedy,
+    /// Chooses the tokens which minimize the compressed size, at the cost of compression speed. The output is still
+    /// decodable by the retail decompressor.
+    Optimal,
+}
+
 const LENGTH_BITS: usize = 4;
 const DISTANCE_BITS: usize = 12;
 const MIN_SUBSEQUENCE: usize = 3;
@@ -19,8 +33,8 @@ const LENGTH_MASK: usize = (1 << LENGTH_BITS) - 1;
 const DISTANCE_MASK: usize = (1 << DISTANCE_BITS) - 1;
 
 const MAX_SUBSEQUENCE: usize = MIN_SUBSEQUENCE + LENGTH_MASK;
-const LOOKAHEAD: usize = 1 << DISTANCE_BITS;
 const MAX_DISTANCE: usize = DISTANCE_MASK + MIN_SUBSEQUENCE;
+const MAX_EXPANSION: usize = 9;
 
 /// Length-distance pair
 #[derive(Clone, Copy, Debug)]
@@ -66,6 +80,54 @@ impl Display for Pair {
     }
 }
 
+/// Footer at the end of LZ77-compressed data, which tells the decompressor where the compressed stream is and how large the
+/// decompressed data is.
+#[derive(Clone, Copy, PartialEq, Eq, Debug)]
+pub struct Lz77Footer {
+    /// Size of the compressed stream including this footer and its padding, counted from the end of the compressed data.
+    /// All bytes before it are stored uncompressed. Only the lower 24 bits are used.
+    pub total_size: u32,
+    /// Size of this footer and its padding, i.e. the offset from the end of the compressed data to the first byte to read.
+    pub read_offset: u8,
+    /// How many bytes larger the decompressed data is than the compressed data.
+    pub write_offset: u32,
+}
+
+impl Lz77Footer {
+    /// Size of the footer in bytes, not including padding.
+    pub const SIZE: usize = 8;
+
+    /// Decodes a footer from its bytes.
+    pub fn from_bytes(bytes: [u8; Self::SIZE]) -> Self {
+        let total_size = u32::from_le_bytes([bytes[0], bytes[1], bytes[2], 0]);
+        let read_offset = bytes[3];
+        let write_offset = u32::from_le_bytes([bytes[4], bytes[5], bytes[6], bytes[7]]);
+        Self { total_size, read_offset, write_offset }
+    }
+
+    /// Encodes this footer into bytes.
+    pub fn to_bytes(&self) -> [u8; Self::SIZE] {
+        let total_size = self.total_size.to_le_bytes();
+        let write_offset = self.write_offset.to_le_bytes();
+        [
+            total_size[0],
+            total_size[1],
+            total_size[2],
+            self.read_offset,
+            write_offset[0],
+            write_offset[1],
+            write_offset[2],
+            write_offset[3],
+        ]
+    }
+
+    /// Returns the size of the decompressed data, given the size of the compressed data ending with this footer. Like on
+    /// hardware, the write offset is added as a 32-bit address, so it may also shrink the size.
+    pub fn decompressed_size(&self, compressed_size: usize) -> usize {
+        (compressed_size as u32).wrapping_add(self.write_offset) as usize
+    }
+}
+
 /// Errors related to [`Lz77::decompress`].
 #[derive(Debug, Snafu)]
 pub enum Lz77DecompressError {
@@ -81,12 +143,44 @@ pub enum Lz77DecompressError {
         /// Source error.
         source: io::Error,
     },
+    /// Occurs when the output buffer of [`Lz77::decompress_into`] doesn't have the decompressed size.
+    #[snafu(display("output buffer has size {actual:#x} but the decompressed size is {expected:#x}:\n{backtrace}"))]
+    BufferSize {
+        /// Decompressed size according to the footer.
+        expected: usize,
+        /// Size of the output buffer.
+        actual: usize,
+        /// Backtrace to the source of the error.
+        backtrace: Backtrace,
+    },
+    /// Occurs when a token would be written past the start of the compressed stream.
+    #[snafu(display("token at offset {offset:#x} decompresses to more data than the footer allows:\n{backtrace}"))]
+    OutputFull {
+        /// Offset of the token.
+        offset: usize,
+        /// Backtrace to the source of the error.
+        backtrace: Backtrace,
+    },
+    /// Occurs when the compressed stream ends before filling the decompressed size.
+    #[snafu(display("compressed stream ended with {remaining:#x} bytes left to decompress:\n{backtrace}"))]
+    Incomplete {
+        /// Number of bytes which weren't decompressed.
+        remaining: usize,
+        /// Backtrace to the source of the error.
+        backtrace: Backtrace,
+    },
 }
 
 impl Lz77 {
-    fn compress_bytes(&self, bytes: &[u8], compressed: &mut Vec<u8>) -> Result<usize, io::Error> {
-        let mut tokens = Tokens::compress(bytes);
-        tokens.drop_wasteful_tokens()?;
+    fn compress_bytes(&self, bytes: &[u8], compressed: &mut Vec<u8>, strategy: Lz77Strategy) -> Result<usize, io::Error> {
+        let tokens = match strategy {
+            Lz77Strategy::Greedy => {
+                let mut tokens = Tokens::compress(bytes);
+                tokens.drop_wasteful_tokens()?;
+                tokens
+            }
+            Lz77Strategy::Optimal => Tokens::compress_optimal(bytes),
+        };
         tokens.write(compressed)
     }
 
@@ -101,14 +195,13 @@ impl Lz77 {
         for _ in 0..padding {
             compressed.push(0xff);
         }
-        let total_size = compressed.len() + 8;
-        let read_offset = padding + 8;
-        let write_offset: u32 = (bytes.len() - total_size) as u32;
-        let total_size = total_size - num_identical - start;
-        let total_size_bytes = total_size.to_le_bytes();
-        compressed.write_all(&[total_size_bytes[0], total_size_bytes[1], total_size_bytes[2]])?;
-        compressed.push(read_offset);
-        compressed.write_all(&write_offset.to_le_bytes())?;
+        let total_size = compressed.len() + Lz77Footer::SIZE;
+        let footer = Lz77Footer {
+            total_size: (total_size - num_identical - start) as u32,
+            read_offset: padding + Lz77Footer::SIZE as u8,
+            write_offset: (bytes.len() as u32).wrapping_sub(total_size as u32),
+        };
+        compressed.write_all(&footer.to_bytes())?;
         Ok(())
     }
 
@@ -119,57 +212,180 @@ impl Lz77 {
     ///
     /// This function will return an error if an I/O operation fails.
     pub fn compress(&self, bytes: &[u8], start: usize) -> Result<Box<[u8]>, io::Error> {
+        self.compress_with_strategy(bytes, start, Lz77Strategy::Greedy)
+    }
+
+    /// Compresses `bytes[start..]` like [`Self::compress`], but chooses tokens according to `strategy`.
+    ///
+    /// # Errors
+    ///
+    /// This function will return an error if an I/O operation fails.
+    pub fn compress_with_strategy(&self, bytes: &[u8], start: usize, strategy: Lz77Strategy) -> Result<Box<[u8]>, io::Error> {
         let mut compressed = Vec::with_capacity(bytes.len());
-        let num_identical = self.compress_bytes(&bytes[start..], &mut compressed)?;
-        for i in (0..start).rev() {
-            compressed.push(bytes[i]);
-        }
-        compressed.reverse();
+        self.compress_into(bytes, start, strategy, &mut compressed)?;
+        Ok(compressed.into_boxed_slice())
+    }
 
-        self.write_footer(&mut compressed, bytes, start, num_identical)?;
+    /// Compresses `bytes[start..]` like [`Self::compress_with_strategy`], but writes the result to `compressed` instead of a
+    /// new buffer. `compressed` is cleared first, so it can be reused across calls to avoid reallocating.
+    ///
+    /// # Errors
+    ///
+    /// This function will return an error if an I/O operation fails.
+    pub fn compress_into(
+        &self,
+        bytes: &[u8],
+        start: usize,
+        strategy: Lz77Strategy,
+        compressed: &mut Vec<u8>,
+    ) -> Result<(), io::Error> {
+        compressed.clear();
+        compressed.extend_from_slice(&bytes[..start]);
+        // Tokens are written backwards, so only the compressed stream has to be reversed
+        let num_identical = self.compress_bytes(&bytes[start..], compressed, strategy)?;
+        compressed[start..].reverse();
 
-        Ok(compressed.into_boxed_slice())
+        self.write_footer(compressed, bytes, start, num_identical)
     }
 
-    fn read_footer(&self, bytes: &[u8]) -> (usize, usize, usize) {
-        let length = bytes.len();
-        let total_size = {
-            let mut buf = [0u8; 3];
-            buf.copy_from_slice(&bytes[length - 8..length - 5]);
-            u32::from_le_bytes([buf[0], buf[1], buf[2], 0]) as usize
-        };
-        let read_offset = bytes[length - 5] as usize;
-        let write_offset = {
-            let mut buf = [0u8; 4];
-            buf.copy_from_slice(&bytes[length - 4..length]);
-            u32::from_le_bytes(buf) as usize
+    /// Reads and validates the footer at the end of `bytes`.
+    ///
+    /// # Errors
+    ///
+    /// This function will return an error if `bytes` is too short to contain a footer, or if the footer doesn't describe a
+    /// compressed stream within `bytes`.
+    pub fn read_footer(&self, bytes: &[u8]) -> Result<Lz77Footer, Lz77ParseError> {
+        let size = bytes.len();
+        let Some(footer) = bytes.last_chunk::<{ Lz77Footer::SIZE }>() else {
+            return TruncatedFooterSnafu { size }.fail();
         };
-        (total_size, read_offset, write_offset)
+        let footer = Lz77Footer::from_bytes(*footer);
+
+        let total_size = footer.total_size as usize;
+        let read_offset = footer.read_offset as usize;
+        if total_size > size {
+            return SizeTooLargeSnafu { total_size, size }.fail();
+        }
+        if read_offset < Lz77Footer::SIZE || read_offset > total_size {
+            return InvalidReadOffsetSnafu { read_offset, total_size }.fail();
+        }
+
+        // A flag byte and 8 maximum length pairs decompress to 8 * 18 bytes, so no stream expands more than 9 times
+        let num_identical = size - total_size;
+        let stream_size = total_size - read_offset;
+        let decompressed_size = footer.decompressed_size(size);
+        if decompressed_size < num_identical || decompressed_size - num_identical > stream_size * MAX_EXPANSION {
+            return InvalidWriteOffsetSnafu { write_offset: footer.write_offset, decompressed_size }.fail();
+        }
+
+        Ok(footer)
+    }
+
+    /// Returns the size of `bytes` once decompressed, according to its footer.
+    ///
+    /// # Errors
+    ///
+    /// See [`Self::read_footer`].
+    pub fn decompressed_size(&self, bytes: &[u8]) -> Result<usize, Lz77ParseError> {
+        Ok(self.read_footer(bytes)?.decompressed_size(bytes.len()))
     }
 
     /// Parses the LZ77 tokens in the `bytes` slice.
+    ///
+    /// # Errors
+    ///
+    /// This function will return an error if the footer or compressed stream is invalid.
     pub fn parse_tokens<'a>(&self, bytes: &'a [u8]) -> Result<Tokens<'a>, Lz77ParseError> {
-        let (total_size, read_offset, write_offset) = self.read_footer(bytes);
-        let num_identical = bytes.len() - total_size;
-        let mut decompressed = Vec::with_capacity(bytes.len() + write_offset);
-        let tokens = Tokens::decompress(&bytes[..num_identical + total_size - read_offset], num_identical, &mut decompressed)?;
+        let footer = self.read_footer(bytes)?;
+        let num_identical = bytes.len() - footer.total_size as usize;
+        let end = bytes.len() - footer.read_offset as usize;
+        let mut decompressed = Vec::with_capacity(footer.decompressed_size(bytes.len()));
+        let mut tokens = Tokens::decompress(&bytes[..end], num_identical, &mut decompressed)?;
+        tokens.compressed_end = end;
+        tokens.decompressed_end = num_identical + decompressed.len();
 
         Ok(tokens)
     }
 
     /// Decompresses `bytes` and returns the result.
+    ///
+    /// # Errors
+    ///
+    /// See [`Self::decompress_into`].
     pub fn decompress(&self, bytes: &[u8]) -> Result<Box<[u8]>, Lz77DecompressError> {
-        let (total_size, read_offset, write_offset) = self.read_footer(bytes);
-        let num_identical = bytes.len() - total_size;
-        let mut decompressed = Vec::with_capacity(bytes.len() + write_offset);
-        let _ = Tokens::decompress(&bytes[..num_identical + total_size - read_offset], num_identical, &mut decompressed)?;
+        let mut decompressed = vec![0; self.decompressed_size(bytes)?];
+        self.decompress_into(bytes, &mut decompressed)?;
+        Ok(decompressed.into_boxed_slice())
+    }
 
-        for i in (0..num_identical).rev() {
-            decompressed.push(bytes[i]);
+    /// Decompresses `bytes` into `decompressed`, which must have the size returned by [`Self::decompressed_size`].
+    ///
+    /// # Errors
+    ///
+    /// This function will return an error if the footer is invalid, if `decompressed` has the wrong size, or if the
+    /// compressed stream is invalid or doesn't decompress to exactly that size.
+    pub fn decompress_into(&self, bytes: &[u8], decompressed: &mut [u8]) -> Result<(), Lz77DecompressError> {
+        let footer = self.read_footer(bytes)?;
+        let size = footer.decompressed_size(bytes.len());
+        if decompressed.len() != size {
+            return BufferSizeSnafu { expected: size, actual: decompressed.len() }.fail();
         }
-        decompressed.reverse();
 
-        Ok(decompressed.into_boxed_slice())
+        let num_identical = bytes.len() - footer.total_size as usize;
+        decompressed[..num_identical].copy_from_slice(&bytes[..num_identical]);
+
+        // Like the retail decompressor, read and write from the end towards the start
+        let mut read = bytes.len() - footer.read_offset as usize;
+        let mut write = size;
+        while read > num_identical {
+            read -= 1;
+            let offset = read;
+            let mut flags = bytes[read];
+            for _ in 0..8 {
+                if (flags & 0x80) == 0 {
+                    if read == num_identical {
+                        return Err(NoLiteralSnafu { offset, flags }.build().into());
+                    }
+                    if write == num_identical {
+                        return OutputFullSnafu { offset: read - 1 }.fail();
+                    }
+                    read -= 1;
+                    write -= 1;
+                    decompressed[write] = bytes[read];
+                } else {
+                    if read == num_identical {
+                        return Err(NoPairSnafu { offset, flags }.build().into());
+                    }
+                    read -= 1;
+                    let offset = read;
+                    if read == num_identical {
+                        return Err(IncompletePairSnafu { offset }.build().into());
+                    }
+                    read -= 1;
+                    let pair = Pair::from_be_bytes([bytes[offset], bytes[read]]);
+
+                    if pair.distance > size - write || pair.length > pair.distance {
+                        return Err(OutOfBoundsSnafu { pair, offset }.build().into());
+                    }
+                    if write - num_identical < pair.length {
+                        return OutputFullSnafu { offset }.fail();
+                    }
+                    for _ in 0..pair.length {
+                        write -= 1;
+                        decompressed[write] = decompressed[write + pair.distance];
+                    }
+                }
+                if read == num_identical {
+                    break;
+                }
+                flags <<= 1;
+            }
+        }
+
+        if write != num_identical {
+            return IncompleteSnafu { remaining: write - num_identical }.fail();
+        }
+        Ok(())
     }
 }
 
@@ -186,6 +402,35 @@ impl Token<'_> {
             Token::Pair((pair, _)) => pair.bytes_saved() as isize,
         }
     }
+
+    fn length(&self) -> usize {
+        match self {
+            Token::Literal(_) => 1,
+            Token::Pair((pair, _)) => pair.length,
+        }
+    }
+
+    fn compressed_size(&self) -> usize {
+        match self {
+            Token::Literal(_) => 1,
+            Token::Pair(_) => 2,
+        }
+    }
+
+    fn distance(&self) -> Option<usize> {
+        match self {
+            Token::Literal(_) => None,
+            Token::Pair((pair, _)) => Some(pair.distance),
+        }
+    }
+
+    fn same_as(&self, other: &Token) -> bool {
+        match (self, other) {
+            (Token::Literal(a), Token::Literal(b)) => a == b,
+            (Token::Pair((a, _)), Token::Pair((b, _))) => a.length == b.length && a.distance == b.distance,
+            _ => false,
+        }
+    }
 }
 
 impl Display for Token<'_> {
@@ -202,9 +447,83 @@ pub struct Tokens<'a> {
     tokens: Vec<Token<'a>>,
     bytes_saved: isize,
     dropped_tokens: usize,
+    /// Offset in the compressed data where the first token is read from, plus one. Only known for parsed tokens.
+    compressed_end: usize,
+    /// Offset in the decompressed data where the first token is written to, plus one. Only known for parsed tokens.
+    decompressed_end: usize,
+}
+
+/// A token of a [`Tokens`] stream, along with its position.
+#[derive(Serialize, Deserialize, Clone, PartialEq, Eq, Debug)]
+pub struct TokenReport {
+    /// Index of this token in the stream.
+    pub index: usize,
+    /// Offset of this token in the compressed data.
+    pub compressed_offset: usize,
+    /// Offset of the bytes this token decompresses to.
+    pub decompressed_offset: usize,
+    /// Number of bytes this token decompresses to.
+    pub length: usize,
+    /// Distance of a length-distance pair, or `None` if this token is a literal.
+    pub distance: Option<usize>,
+    /// Bytes saved by the stream up to and including this token.
+    pub bytes_saved: isize,
+}
+
+/// Bytes saved by the tokens which decompress to a region of data, see [`Tokens::stats`].
+#[derive(Serialize, Deserialize, Clone, PartialEq, Eq, Debug)]
+pub struct RegionSavings {
+    /// Start offset of the region in the decompressed data.
+    pub start: usize,
+    /// End offset of the region in the decompressed data.
+    pub end: usize,
+    /// Bytes saved by tokens starting in this region, including their flag bytes.
+    pub bytes_saved: isize,
+}
+
+/// Statistics of a [`Tokens`] stream.
+#[derive(Serialize, Deserialize, Clone, PartialEq, Eq, Debug)]
+pub struct TokensStats {
+    /// Number of tokens in the compressed stream.
+    pub num_tokens: usize,
+    /// Number of literal tokens.
+    pub num_literals: usize,
+    /// Number of length-distance pairs.
+    pub num_pairs: usize,
+    /// Total bytes saved by the compressed tokens.
+    pub bytes_saved: isize,
+    /// Size of the data before the compressed stream, which is stored uncompressed. This includes both the data before the
+    /// start offset given to [`Lz77::compress`] and the tokens which the compressor dropped because they didn't save any
+    /// bytes, which the footer doesn't tell apart. Comparing this boundary between two streams shows where the compressors
+    /// stopped compressing.
+    pub uncompressed_size: usize,
+    /// Bytes saved per region of the decompressed data. Regions without tokens are left out.
+    pub regions: Vec<RegionSavings>,
+}
+
+/// The first difference between two [`Tokens`] streams, see [`Tokens::diverge`].
+#[derive(Serialize, Deserialize, Clone, PartialEq, Eq, Debug)]
+pub struct TokensDivergence {
+    /// Index of the first differing token.
+    pub index: usize,
+    /// The differing token of the first stream, or `None` if the stream ended before it.
+    pub left: Option<TokenReport>,
+    /// The differing token of the second stream, or `None` if the stream ended before it.
+    pub right: Option<TokenReport>,
+}
+
+/// Comparison of two [`Tokens`] streams, see [`Tokens::diff`].
+#[derive(Serialize, Deserialize, Clone, PartialEq, Eq, Debug)]
+pub struct TokensDiff {
+    /// Statistics of the first stream.
+    pub left: TokensStats,
+    /// Statistics of the second stream.
+    pub right: TokensStats,
+    /// The first difference between the streams, or `None` if they are equal.
+    pub divergence: Option<TokensDivergence>,
 }
 
-/// Errors related to [`Tokens::decompress`].
+/// Errors related to [`Lz77::read_footer`] and [`Lz77::parse_tokens`].
 #[derive(Debug, Snafu)]
 pub enum Lz77ParseError {
     /// Occurs when a byte literal is expected directly after a flag byte, but there are no more bytes to read.
@@ -235,6 +554,48 @@ pub enum Lz77ParseError {
         /// Backtrace to the source of the error.
         backtrace: Backtrace,
     },
+    /// Occurs when the data is too short to contain a footer.
+    #[snafu(display("LZ77 data of size {size:#x} is too short to contain a footer:\n{backtrace}"))]
+    TruncatedFooter {
+        /// Size of the data.
+        size: usize,
+        /// Backtrace to the source of the error.
+        backtrace: Backtrace,
+    },
+    /// Occurs when the compressed stream in the footer is larger than the data.
+    #[snafu(display("LZ77 footer total size {total_size:#x} is larger than the data size {size:#x}:\n{backtrace}"))]
+    SizeTooLarge {
+        /// Total size in the footer.
+        total_size: usize,
+        /// Size of the data.
+        size: usize,
+        /// Backtrace to the source of the error.
+        backtrace: Backtrace,
+    },
+    /// Occurs when the read offset in the footer is not within the compressed stream.
+    #[snafu(display(
+        "LZ77 footer read offset {read_offset:#x} is outside of the compressed stream of size {total_size:#x}:\n{backtrace}"
+    ))]
+    InvalidReadOffset {
+        /// Read offset in the footer.
+        read_offset: usize,
+        /// Total size in the footer.
+        total_size: usize,
+        /// Backtrace to the source of the error.
+        backtrace: Backtrace,
+    },
+    /// Occurs when the write offset in the footer results in a decompressed size which the compressed stream can't have.
+    #[snafu(display(
+        "LZ77 footer write offset {write_offset:#x} results in impossible decompressed size {decompressed_size:#x}:\n{backtrace}"
+    ))]
+    InvalidWriteOffset {
+        /// Write offset in the footer.
+        write_offset: u32,
+        /// Decompressed size resulting from the write offset.
+        decompressed_size: usize,
+        /// Backtrace to the source of the error.
+        backtrace: Backtrace,
+    },
     /// Occurs when a length-distance pair would point to data that is not within the decompressed stream.
     #[snafu(display(
         "length-distance pair {pair} at offset {offset:#x} points outside of decompressed stream:\n{backtrace}"
@@ -250,35 +611,18 @@ pub enum Lz77ParseError {
 }
 
 impl<'a> Tokens<'a> {
-    fn find_match(bytes: &[u8], pos: usize) -> Option<Pair> {
-        let max_lookahead = (LOOKAHEAD + MAX_SUBSEQUENCE).min(bytes.len() - pos - 1);
-        (MIN_SUBSEQUENCE - 1..max_lookahead)
-            .fold(None, |best_pair, i| {
-                let needle = pos;
-                let haystack = pos + 1 + i;
-                if bytes[needle] != bytes[haystack] {
-                    return best_pair;
-                }
-                let mut length = 0;
-                while needle >= length
-                    && bytes[needle - length] == bytes[haystack - length]
-                    && haystack > pos + length
-                    && length < MAX_SUBSEQUENCE
-                {
-                    length += 1;
-                }
-                let distance = haystack - needle;
-                if length > best_pair.map_or(0, |p: Pair| p.length) && distance <= MAX_DISTANCE {
-                    Some(Pair { length, distance })
-                } else {
-                    best_pair
-                }
-            })
-            .and_then(|p| (p.length >= MIN_SUBSEQUENCE).then_some(p))
+    fn find_match(finder: &mut MatchFinder, pos: usize) -> Option<Pair> {
+        // The finder searches the reversed bytes, so a match ending at `pos` starts at the mirrored position
+        let reversed_pos = finder.len() - 1 - pos;
+        finder
+            .find(reversed_pos, MAX_SUBSEQUENCE, MIN_SUBSEQUENCE)
+            .map(|found| Pair { length: found.length, distance: found.distance })
     }
 
     fn compress(bytes: &'a [u8]) -> Self {
         let mut tokens = vec![];
+        let reversed = bytes.iter().rev().copied().collect::<Vec<_>>();
+        let mut finder = MatchFinder::non_overlapping(&reversed, MAX_DISTANCE);
 
         let mut read = bytes.len();
         let mut bytes_saved = 0;
@@ -286,7 +630,7 @@ impl<'a> Tokens<'a> {
             if (tokens.len() % 8) == 0 {
                 bytes_saved -= 1;
             }
-            if let Some(pair) = Self::find_match(bytes, read - 1) {
+            if let Some(pair) = Self::find_match(&mut finder, read - 1) {
                 read -= pair.length;
                 bytes_saved += pair.bytes_saved() as isize;
                 tokens.push(Token::Pair((pair, Cow::Borrowed(&bytes[read..read + pair.length]))));
@@ -296,7 +640,85 @@ impl<'a> Tokens<'a> {
             }
         }
 
-        Self { tokens, bytes_saved, dropped_tokens: 0 }
+        Self { tokens, bytes_saved, dropped_tokens: 0, compressed_end: 0, decompressed_end: 0 }
+    }
+
+    /// Finds the tokens which minimize the compressed size using dynamic programming. Each state is the number of bytes left
+    /// to compress and the number of tokens since the last flag byte, since a new flag byte is needed every 8 tokens. At any
+    /// state, the remaining bytes can also be left uncompressed, which replaces [`Self::drop_wasteful_tokens`].
+    ///
+    /// Since no prefix of the chosen tokens saves more bytes than all of them (or else stopping after it would be better),
+    /// the decompressor never overwrites compressed data which it hasn't read yet.
+    fn compress_optimal(bytes: &'a [u8]) -> Self {
+        const FLAG_GROUP: usize = 8;
+        const UNCOMPRESSED: u8 = 0;
+        const LITERAL: u8 = 1;
+
+        let reversed = bytes.iter().rev().copied().collect::<Vec<_>>();
+        let mut finder = MatchFinder::non_overlapping(&reversed, MAX_DISTANCE);
+        let mut matches = vec![None; bytes.len() + 1];
+        for read in (1..=bytes.len()).rev() {
+            matches[read] = Self::find_match(&mut finder, read - 1);
+        }
+
+        // costs[read % COSTS_LEN][group] is the smallest size of the remaining `read` bytes, with `group` tokens in the current
+        // flag group. Only the costs of the previous MAX_SUBSEQUENCE reads are needed at a time.
+        // choices[read][group] is the token which achieves it, either a literal, a pair length or uncompressed bytes.
+        const COSTS_LEN: usize = MAX_SUBSEQUENCE + 1;
+        let mut costs = [[0usize; FLAG_GROUP]; COSTS_LEN];
+        let mut choices = vec![[UNCOMPRESSED; FLAG_GROUP]; bytes.len() + 1];
+        for read in 1..=bytes.len() {
+            for group in 0..FLAG_GROUP {
+                let flag_cost = (group == 0) as usize;
+                let next_group = (group + 1) % FLAG_GROUP;
+
+                // The first token must be compressed, as the compressed stream can't be empty
+                let mut best = if read == bytes.len() { (usize::MAX, UNCOMPRESSED) } else { (read, UNCOMPRESSED) };
+                let literal_cost = flag_cost + 1 + costs[(read - 1) % COSTS_LEN][next_group];
+                if literal_cost < best.0 {
+                    best = (literal_cost, LITERAL);
+                }
+                if let Some(pair) = matches[read] {
+                    for length in MIN_SUBSEQUENCE..=pair.length {
+                        let pair_cost = flag_cost + 2 + costs[(read - length) % COSTS_LEN][next_group];
+                        if pair_cost < best.0 {
+                            best = (pair_cost, length as u8);
+                        }
+                    }
+                }
+                costs[read % COSTS_LEN][group] = best.0;
+                choices[read][group] = best.1;
+            }
+        }
+
+        let mut tokens = vec![];
+        let mut bytes_saved = 0;
+        let mut read = bytes.len();
+        let mut group = 0;
+        while read > 0 && choices[read][group] != UNCOMPRESSED {
+            if (tokens.len() % 8) == 0 {
+                bytes_saved -= 1;
+            }
+            match choices[read][group] {
+                LITERAL => {
+                    read -= 1;
+                    tokens.push(Token::Literal(bytes[read]));
+                }
+                length => {
+                    let pair = Pair { length: length as usize, distance: matches[read].unwrap().distance };
+                    read -= pair.length;
+                    bytes_saved += pair.bytes_saved() as isize;
+                    tokens.push(Token::Pair((pair, Cow::Borrowed(&bytes[read..read + pair.length]))));
+                }
+            }
+            group = (group + 1) % FLAG_GROUP;
+        }
+
+        // Leave the remaining bytes uncompressed
+        let dropped_tokens = read;
+        tokens.extend(bytes[..read].iter().rev().map(|&byte| Token::Literal(byte)));
+
+        Self { tokens, bytes_saved, dropped_tokens, compressed_end: 0, decompressed_end: 0 }
     }
 
     fn drop_wasteful_tokens(&mut self) -> Result<(), io::Error> {
@@ -415,7 +837,80 @@ impl<'a> Tokens<'a> {
             }
         }
 
-        Ok(Self { tokens, bytes_saved, dropped_tokens: 0 })
+        Ok(Self { tokens, bytes_saved, dropped_tokens: 0, compressed_end: 0, decompressed_end: 0 })
+    }
+}
+
+impl Tokens<'_> {
+    /// Returns the tokens along with their positions, in the order they are decompressed. Offsets are only meaningful for
+    /// tokens returned by [`Lz77::parse_tokens`].
+    pub fn reports(&self) -> impl Iterator<Item = TokenReport> + '_ {
+        let mut compressed_offset = self.compressed_end;
+        let mut decompressed_offset = self.decompressed_end;
+        let mut bytes_saved = 0;
+        self.tokens.iter().enumerate().map(move |(index, token)| {
+            if index % 8 == 0 {
+                compressed_offset = compressed_offset.saturating_sub(1);
+                bytes_saved -= 1;
+            }
+            compressed_offset = compressed_offset.saturating_sub(token.compressed_size());
+            decompressed_offset = decompressed_offset.saturating_sub(token.length());
+            bytes_saved += token.bytes_saved();
+            TokenReport {
+                index,
+                compressed_offset,
+                decompressed_offset,
+                length: token.length(),
+                distance: token.distance(),
+                bytes_saved,
+            }
+        })
+    }
+
+    /// Returns statistics of these tokens, with the savings split into regions of `region_size` bytes of decompressed data.
+    pub fn stats(&self, region_size: usize) -> TokensStats {
+        let region_size = region_size.max(1);
+        let num_pairs = self.tokens.iter().filter(|token| matches!(token, Token::Pair(_))).count();
+        let decompressed_size: usize = self.tokens.iter().map(Token::length).sum();
+
+        let mut regions: Vec<RegionSavings> = vec![];
+        let mut last_saved = 0;
+        for report in self.reports() {
+            let start = report.decompressed_offset / region_size * region_size;
+            let saved = report.bytes_saved - last_saved;
+            last_saved = report.bytes_saved;
+            // Tokens are decompressed from the end, so regions are visited in descending order
+            match regions.last_mut() {
+                Some(region) if region.start == start => region.bytes_saved += saved,
+                _ => regions.push(RegionSavings { start, end: start + region_size, bytes_saved: saved }),
+            }
+        }
+        regions.reverse();
+
+        TokensStats {
+            num_tokens: self.tokens.len(),
+            num_literals: self.tokens.len() - num_pairs,
+            num_pairs,
+            bytes_saved: self.bytes_saved,
+            uncompressed_size: self.decompressed_end.saturating_sub(decompressed_size),
+            regions,
+        }
+    }
+
+    /// Returns the first token which differs between these tokens and `other`, or `None` if they are equal.
+    pub fn diverge(&self, other: &Tokens) -> Option<TokensDivergence> {
+        let index = self.tokens.iter().zip(other.tokens.iter()).position(|(a, b)| !a.same_as(b));
+        let index = match index {
+            Some(index) => index,
+            None if self.tokens.len() == other.tokens.len() => return None,
+            None => self.tokens.len().min(other.tokens.len()),
+        };
+        Some(TokensDivergence { index, left: self.reports().nth(index), right: other.reports().nth(index) })
+    }
+
+    /// Compares these tokens to `other`, see [`Self::stats`] and [`Self::diverge`].
+    pub fn diff(&self, other: &Tokens, region_size: usize) -> TokensDiff {
+        TokensDiff { left: self.stats(region_size), right: other.stats(region_size), divergence: self.diverge(other) }
     }
 }
 
@@ -435,3 +930,205 @@ impl Display for Tokens<'_> {
         Ok(())
     }
 }
+
+#[cfg(test)]
+mod tests {
+    use sha1::{Digest, Sha1};
+
+    use super::*;
+
+    fn tokens(pairs: &[(usize, usize)]) -> Tokens<'static> {
+        let tokens = pairs
+            .iter()
+            .map(|&(length, distance)| match distance {
+                0 => Token::Literal(length as u8),
+                _ => Token::Pair((Pair { length, distance }, Cow::Owned(vec![0; length]))),
+            })
+            .collect();
+        Tokens { tokens, bytes_saved: 0, dropped_tokens: 0, compressed_end: 0, decompressed_end: 0 }
+    }
+
+    /// Returns `len` bytes from a small alphabet with repeated runs, so that matches of many lengths and distances are found.
+    fn sample(len: usize) -> Vec<u8> {
+        let mut state = 0x2468ace1u32;
+        let mut bytes = Vec::with_capacity(len);
+        while bytes.len() < len {
+            state = state.wrapping_mul(1103515245).wrapping_add(12345);
+            let value = (state >> 16) as usize;
+            if value % 3 == 0 && bytes.len() > 0x20 {
+                let start = bytes.len() - 1 - (value >> 2) % 0x20;
+                let length = ((value >> 8) % 0x14).min(len - bytes.len());
+                for i in 0..length {
+                    bytes.push(bytes[start + i]);
+                }
+            } else {
+                bytes.push(b"abcdefgh"[(value >> 4) % 8]);
+            }
+        }
+        bytes
+    }
+
+    #[test]
+    fn diverge_at_token_index() {
+        // Literals have distance 0 and use the length as their value
+        let left = tokens(&[(1, 0), (2, 0), (3, 2), (4, 1), (5, 0)]);
+        let right = tokens(&[(1, 0), (2, 0), (3, 2), (4, 2), (5, 0)]);
+        let divergence = left.diverge(&right).unwrap();
+        assert_eq!(divergence.index, 3);
+        assert_eq!(divergence.left.unwrap().distance, Some(1));
+        assert_eq!(divergence.right.unwrap().distance, Some(2));
+
+        let right = tokens(&[(1, 0), (2, 0), (3, 2)]);
+        let divergence = left.diverge(&right).unwrap();
+        assert_eq!(divergence.index, 3);
+        assert!(divergence.left.is_some());
+        assert!(divergence.right.is_none());
+
+        assert!(left.diverge(&left).is_none());
+    }
+
+    #[test]
+    fn stats_uncompressed_size() {
+        let mut bytes = (0..0x40u32).map(|i| (i * i / 3) as u8).collect::<Vec<_>>();
+        bytes.extend(b"abcabcabcabcabcabcabcabcabcabcabcabcabcabcabcabc");
+        let compressed = Lz77 {}.compress(&bytes, 0x10).unwrap();
+
+        let tokens = Lz77 {}.parse_tokens(&compressed).unwrap();
+        let stats = tokens.stats(0x100);
+        let stream_size: usize = tokens.tokens.iter().map(Token::length).sum();
+        assert!(stats.uncompressed_size >= 0x10);
+        assert_eq!(stats.uncompressed_size + stream_size, bytes.len());
+        assert_eq!(stats.num_tokens, tokens.tokens.len());
+        assert_eq!(tokens.reports().last().unwrap().decompressed_offset, stats.uncompressed_size);
+    }
+
+    #[test]
+    fn optimal_roundtrip() {
+        for (len, start) in [(1, 0), (0x10, 4), (0x123, 0), (0x1000, 0x100), (0x2345, 0x400)] {
+            let bytes = sample(len);
+            let optimal = Lz77 {}.compress_with_strategy(&bytes, start, Lz77Strategy::Optimal).unwrap();
+            let greedy = Lz77 {}.compress_with_strategy(&bytes, start, Lz77Strategy::Greedy).unwrap();
+            assert_eq!(*Lz77 {}.decompress(&optimal).unwrap(), *bytes, "length {len:#x}");
+            assert!(
+                optimal.len() <= greedy.len(),
+                "length {len:#x}: optimal {:#x} > greedy {:#x}",
+                optimal.len(),
+                greedy.len()
+            );
+        }
+    }
+
+    #[test]
+    fn optimal_in_place() {
+        // Decompressing in place starts writing `write_offset + read_offset` bytes after the last compressed byte, so if any
+        // prefix of the tokens saved more than that, it would overwrite compressed data which hasn't been read yet
+        for (len, start) in [(0x123, 0), (0x1000, 0x100), (0x2345, 0x400)] {
+            let bytes = sample(len);
+            let compressed = Lz77 {}.compress_with_strategy(&bytes, start, Lz77Strategy::Optimal).unwrap();
+            let footer = Lz77 {}.read_footer(&compressed).unwrap();
+            let gap = footer.write_offset as i32 as isize + footer.read_offset as isize;
+            let tokens = Lz77 {}.parse_tokens(&compressed).unwrap();
+            for report in tokens.reports() {
+                assert!(
+                    report.bytes_saved <= gap,
+                    "length {len:#x}: token {} saves {} > {gap}",
+                    report.index,
+                    report.bytes_saved
+                );
+            }
+        }
+    }
+
+    #[test]
+    fn greedy_matches_window_scan() {
+        // Output of the compressor which scanned the whole window at every position, before matches were found with hash chains
+        let bytes = b"the quick brown fox jumps over the lazy dog; the quick brown cat jumps over the lazy fox. \
+            aaaaaaaaaaaaaaaaaaaaabababababababab the lazy dog jumps over the quick brown fox!";
+        let expected: &[u8] = &[
+            0x74, 0x68, 0x65, 0x20, 0x71, 0x75, 0x69, 0x63, 0x6b, 0x20, 0x62, 0x72, 0x6f, 0x77, 0x6e, 0x20, 0x42, 0x00, 0x80,
+            0x2a, 0x80, 0x5d, 0xa0, 0x3b, 0x67, 0xe0, 0x63, 0x61, 0x74, 0x48, 0x80, 0x8b, 0x30, 0x60, 0x4f, 0x10, 0x2e, 0x20,
+            0x06, 0x60, 0x03, 0x30, 0x00, 0x00, 0x61, 0x73, 0x61, 0x05, 0x50, 0x01, 0x10, 0x61, 0x62, 0x61, 0x62, 0x15, 0x20,
+            0x86, 0x6c, 0x61, 0x7a, 0x79, 0x20, 0x64, 0x6f, 0x67, 0x00, 0x20, 0x6a, 0x75, 0x6d, 0x70, 0x73, 0x20, 0x6f, 0x00,
+            0x76, 0x65, 0x72, 0x20, 0x74, 0x68, 0x65, 0x20, 0x00, 0x71, 0x75, 0x69, 0x63, 0x6b, 0x20, 0x62, 0x72, 0x00, 0x6f,
+            0x77, 0x6e, 0x20, 0x66, 0x6f, 0x78, 0x21, 0x00, 0xff, 0x60, 0x00, 0x00, 0x09, 0x3b, 0x00, 0x00, 0x00,
+        ];
+        assert_eq!(*Lz77 {}.compress(bytes, 0x10).unwrap(), *expected);
+
+        // Longer input with matches across the whole window, compared by size and SHA1 of the output
+        let compressed = Lz77 {}.compress(&sample(0x3000), 0).unwrap();
+        let sha1: [u8; 0x14] = Sha1::digest(&compressed).into();
+        assert_eq!(compressed.len(), 0x1234);
+        assert_eq!(
+            sha1,
+            [
+                0x15, 0x69, 0xf6, 0xa8, 0xef, 0xa6, 0xc7, 0x12, 0xec, 0x5b, 0x0a, 0xb4, 0x2e, 0xc4, 0xc2, 0x85, 0x26, 0x46,
+                0x3b, 0x4b
+            ]
+        );
+    }
+
+    fn with_footer(bytes: &[u8], footer: Lz77Footer) -> Vec<u8> {
+        let mut bytes = bytes.to_vec();
+        let end = bytes.len() - Lz77Footer::SIZE;
+        bytes[end..].copy_from_slice(&footer.to_bytes());
+        bytes
+    }
+
+    #[test]
+    fn footer_errors() {
+        let lz77 = Lz77 {};
+        assert!(matches!(lz77.read_footer(&[0; 7]), Err(Lz77ParseError::TruncatedFooter { size: 7, .. })));
+
+        let compressed = lz77.compress(&sample(0x123), 0x10).unwrap();
+        let footer = lz77.read_footer(&compressed).unwrap();
+        let size = compressed.len();
+
+        let bytes = with_footer(&compressed, Lz77Footer { total_size: size as u32 + 1, ..footer });
+        assert!(matches!(lz77.read_footer(&bytes), Err(Lz77ParseError::SizeTooLarge { .. })));
+
+        let bytes = with_footer(&compressed, Lz77Footer { read_offset: Lz77Footer::SIZE as u8 - 1, ..footer });
+        assert!(matches!(lz77.read_footer(&bytes), Err(Lz77ParseError::InvalidReadOffset { .. })));
+        let bytes = with_footer(&compressed, Lz77Footer { total_size: 0xa, read_offset: 0xc, ..footer });
+        assert!(matches!(lz77.read_footer(&bytes), Err(Lz77ParseError::InvalidReadOffset { .. })));
+
+        // Larger than the stream could ever expand to, and smaller than the uncompressed bytes before the stream
+        let bytes = with_footer(&compressed, Lz77Footer { write_offset: 0x100000, ..footer });
+        assert!(matches!(lz77.read_footer(&bytes), Err(Lz77ParseError::InvalidWriteOffset { .. })));
+        let bytes = with_footer(&compressed, Lz77Footer { write_offset: (-(size as i32)) as u32, ..footer });
+        assert!(matches!(lz77.read_footer(&bytes), Err(Lz77ParseError::InvalidWriteOffset { .. })));
+    }
+
+    #[test]
+    fn decompress_size_errors() {
+        let lz77 = Lz77 {};
+        let compressed = lz77.compress(&sample(0x123), 0x10).unwrap();
+        let footer = lz77.read_footer(&compressed).unwrap();
+
+        // The last token doesn't fit if the decompressed size is one byte short
+        let bytes = with_footer(&compressed, Lz77Footer { write_offset: footer.write_offset - 1, ..footer });
+        assert!(matches!(lz77.decompress(&bytes), Err(Lz77DecompressError::OutputFull { .. })));
+
+        let bytes = with_footer(&compressed, Lz77Footer { write_offset: footer.write_offset + 1, ..footer });
+        assert!(matches!(lz77.decompress(&bytes), Err(Lz77DecompressError::Incomplete { remaining: 1, .. })));
+    }
+
+    #[test]
+    fn reuse_buffers() {
+        let lz77 = Lz77 {};
+        let mut compressed = vec![];
+        let mut decompressed = vec![];
+        for (len, start) in [(0x1000, 0x100), (0x123, 0x10), (0x2345, 0)] {
+            let bytes = sample(len);
+            lz77.compress_into(&bytes, start, Lz77Strategy::Greedy, &mut compressed).unwrap();
+            assert_eq!(*compressed, *lz77.compress(&bytes, start).unwrap(), "length {len:#x}");
+
+            decompressed.resize(lz77.decompressed_size(&compressed).unwrap(), 0);
+            lz77.decompress_into(&compressed, &mut decompressed).unwrap();
+            assert_eq!(decompressed, bytes, "length {len:#x}");
+        }
+
+        let mut decompressed = vec![0; decompressed.len() + 1];
+        let result = lz77.decompress_into(&compressed, &mut decompressed);
+        assert!(matches!(result, Err(Lz77DecompressError::BufferSize { expected: 0x2345, actual: 0x2346, .. })));
+    }
+}
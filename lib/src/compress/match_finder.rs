@@ -0,0 +1,91 @@
+/// Number of bits in the hash of the first [`MIN_MATCH`] bytes at a position.
+const HASH_BITS: u32 = 15;
+/// Minimum match length which can be found, and the number of bytes hashed.
+pub(crate) const MIN_MATCH: usize = 3;
+/// Marks the end of a hash chain.
+const NONE: u32 = u32::MAX;
+
+/// Finds the longest earlier occurrence of the bytes at a position, using hash chains of the positions with the same first
+/// [`MIN_MATCH`] bytes. Positions must be inserted in increasing order.
+pub(crate) struct MatchFinder<'a> {
+    data: &'a [u8],
+    window: usize,
+    overlap: bool,
+    head: Vec<u32>,
+    prev: Vec<u32>,
+    inserted: usize,
+}
+
+/// A match found by [`MatchFinder`].
+#[derive(Clone, Copy)]
+pub(crate) struct Match {
+    pub length: usize,
+    pub distance: usize,
+}
+
+impl<'a> MatchFinder<'a> {
+    /// Creates a new [`MatchFinder`] which finds matches at most `window` bytes back.
+    pub fn new(data: &'a [u8], window: usize) -> Self {
+        Self { data, window, overlap: true, head: vec![NONE; 1 << HASH_BITS], prev: vec![NONE; data.len()], inserted: 0 }
+    }
+
+    /// Creates a new [`MatchFinder`] like [`Self::new`], but whose matches are never longer than their distance.
+    pub fn non_overlapping(data: &'a [u8], window: usize) -> Self {
+        Self { overlap: false, ..Self::new(data, window) }
+    }
+
+    /// Returns the length of the data being searched.
+    pub fn len(&self) -> usize {
+        self.data.len()
+    }
+
+    fn hash(&self, pos: usize) -> usize {
+        let bytes = &self.data[pos..pos + MIN_MATCH];
+        let value = (bytes[0] as u32) << 16 | (bytes[1] as u32) << 8 | bytes[2] as u32;
+        (value.wrapping_mul(0x9e3779b1) >> (32 - HASH_BITS)) as usize
+    }
+
+    /// Inserts all positions before `pos` which haven't been inserted yet.
+    pub fn insert_until(&mut self, pos: usize) {
+        let end = pos.min(self.data.len().saturating_sub(MIN_MATCH - 1));
+        while self.inserted < end {
+            let hash = self.hash(self.inserted);
+            self.prev[self.inserted] = self.head[hash];
+            self.head[hash] = self.inserted as u32;
+            self.inserted += 1;
+        }
+        self.inserted = self.inserted.max(pos);
+    }
+
+    /// Returns the longest match of at most `max_length` bytes at `pos` which is at least `min_distance` bytes back, or
+    /// `None` if there is no match of at least [`MIN_MATCH`] bytes. Ties are broken by the shortest distance.
+    pub fn find(&mut self, pos: usize, max_length: usize, min_distance: usize) -> Option<Match> {
+        self.insert_until(pos);
+        let max_length = max_length.min(self.data.len() - pos);
+        if max_length < MIN_MATCH {
+            return None;
+        }
+
+        let mut best: Option<Match> = None;
+        let mut candidate = self.head[self.hash(pos)];
+        while candidate != NONE {
+            let start = candidate as usize;
+            let distance = pos - start;
+            if distance > self.window {
+                break;
+            }
+            if distance >= min_distance {
+                let limit = if self.overlap { max_length } else { max_length.min(distance) };
+                let length = self.data[start..].iter().zip(&self.data[pos..pos + limit]).take_while(|(a, b)| a == b).count();
+                if length >= MIN_MATCH && best.map_or(true, |best| length > best.length) {
+                    best = Some(Match { length, distance });
+                    if length == max_length {
+                        break;
+                    }
+                }
+            }
+            candidate = self.prev[start];
+        }
+        best
+    }
+}
@@ -1,4 +1,19 @@
+/// Header detection and dispatch for the BIOS compression formats.
+pub mod bios;
+/// On-disk cache of compression results.
+pub mod cache;
+/// Difference filters of the BIOS.
+pub mod diff;
 /// De/compression using Huffman coding.
 pub mod huffman;
+/// De/compression using the BIOS Huffman format.
+pub mod huffman_bios;
+/// De/compression using the BIOS LZ77 format.
+pub mod lz10;
+/// De/compression using the extended BIOS LZ77 format.
+pub mod lz11;
 /// De/compression using backwards LZ77.
 pub mod lz77;
+mod match_finder;
+/// De/compression using the BIOS run-length encoding format.
+pub mod rle;
@@ -0,0 +1,128 @@
+use super::bios::{pad_to_word, BiosCompressError, BiosCompressionType, BiosDecompressError, BiosHeader, UnexpectedEndSnafu};
+
+/// Minimum length of a run.
+const MIN_RUN: usize = 3;
+/// Maximum length of a run.
+const MAX_RUN: usize = MIN_RUN + 0x7f;
+/// Maximum number of literal bytes after a flag byte.
+const MAX_LITERALS: usize = 0x80;
+
+/// De/compresses data using the BIOS run-length encoding format with header byte `0x30`. Every block starts with a flag
+/// byte, where bit 7 tells whether it is followed by one byte repeated 3 to 130 times (1), or by 1 to 128 literal bytes (0).
+pub struct Rle {}
+
+impl Rle {
+    /// Returns whether `data` starts with an RLE header.
+    pub fn is_compressed(&self, data: &[u8]) -> bool {
+        BiosCompressionType::detect(data) == Some(BiosCompressionType::Rle)
+    }
+
+    /// Decompresses `data` and returns the result.
+    ///
+    /// # Errors
+    ///
+    /// This function will return an error if `data` doesn't have an RLE header or ends too early.
+    pub fn decompress(&self, data: &[u8]) -> Result<Box<[u8]>, BiosDecompressError> {
+        Ok(self.decompress_stream(data)?.0)
+    }
+
+    /// Decompresses `data` like [`Self::decompress`], and also returns the offset to the end of the compressed stream.
+    ///
+    /// # Errors
+    ///
+    /// See [`Self::decompress`].
+    pub(crate) fn decompress_stream(&self, data: &[u8]) -> Result<(Box<[u8]>, usize), BiosDecompressError> {
+        let header = BiosHeader::parse_expected(data, BiosCompressionType::Rle)?;
+        let size = header.decompressed_size;
+        let mut out = Vec::with_capacity(size);
+        let mut offset = header.header_size;
+        let byte_at = |offset: usize| data.get(offset).copied().ok_or_else(|| UnexpectedEndSnafu { offset }.build());
+
+        while out.len() < size {
+            let flag = byte_at(offset)? as usize;
+            offset += 1;
+            if flag & 0x80 != 0 {
+                let length = (flag & 0x7f) + MIN_RUN;
+                let byte = byte_at(offset)?;
+                offset += 1;
+                out.resize(out.len() + length.min(size - out.len()), byte);
+            } else {
+                let length = ((flag & 0x7f) + 1).min(size - out.len());
+                let Some(literals) = data.get(offset..offset + length) else {
+                    return UnexpectedEndSnafu { offset: data.len() }.fail();
+                };
+                out.extend_from_slice(literals);
+                offset += length;
+            }
+        }
+
+        Ok((out.into_boxed_slice(), offset))
+    }
+
+    /// Compresses `bytes` and returns the result.
+    ///
+    /// # Errors
+    ///
+    /// This function will return an error if `bytes` is too large for a header.
+    pub fn compress(&self, bytes: &[u8]) -> Result<Box<[u8]>, BiosCompressError> {
+        let mut out = vec![];
+        BiosHeader::new(BiosCompressionType::Rle, bytes.len())?.write(&mut out);
+
+        let mut literals_start = 0;
+        let mut pos = 0;
+        while pos < bytes.len() {
+            let run = bytes[pos..].iter().take(MAX_RUN).take_while(|&&b| b == bytes[pos]).count();
+            if run < MIN_RUN {
+                pos += run;
+                continue;
+            }
+            Self::write_literals(&mut out, &bytes[literals_start..pos]);
+            out.push(0x80 | (run - MIN_RUN) as u8);
+            out.push(bytes[pos]);
+            pos += run;
+            literals_start = pos;
+        }
+        Self::write_literals(&mut out, &bytes[literals_start..]);
+
+        pad_to_word(&mut out);
+        Ok(out.into_boxed_slice())
+    }
+
+    fn write_literals(out: &mut Vec<u8>, literals: &[u8]) {
+        for chunk in literals.chunks(MAX_LITERALS) {
+            out.push((chunk.len() - 1) as u8);
+            out.extend_from_slice(chunk);
+        }
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn roundtrip(bytes: &[u8]) {
+        let compressed = Rle {}.compress(bytes).unwrap();
+        assert!(Rle {}.is_compressed(&compressed));
+        assert_eq!(compressed.len() % 4, 0);
+        assert_eq!(&*Rle {}.decompress(&compressed).unwrap(), bytes);
+    }
+
+    #[test]
+    fn roundtrip_edge_cases() {
+        roundtrip(&[]);
+        roundtrip(&[0x30]);
+        roundtrip(b"aab");
+        roundtrip(&[7; MAX_RUN]);
+        roundtrip(&[7; MAX_RUN + 1]);
+        roundtrip(&[7; MAX_RUN + MIN_RUN]);
+        roundtrip(&(0..MAX_LITERALS * 2 + 1).map(|i| i as u8).collect::<Vec<_>>());
+    }
+
+    #[test]
+    fn known_vector() {
+        // Two literals followed by a run of 5
+        let compressed = [0x30, 0x07, 0x00, 0x00, 0x01, b'a', b'b', 0x82, b'x', 0x00, 0x00, 0x00];
+        assert_eq!(&*Rle {}.decompress(&compressed).unwrap(), b"abxxxxx");
+        assert_eq!(&*Rle {}.compress(b"abxxxxx").unwrap(), &compressed);
+    }
+}
@@ -1,13 +1,27 @@
 use std::{
-    io::{self, Read, Seek, SeekFrom},
+    env,
+    io::{self, Read},
     mem::size_of,
     path::Path,
 };
 
 use bytemuck::{Pod, Zeroable};
+use sha1::{Digest, Sha1};
 use snafu::{Backtrace, Snafu};
 
-use crate::io::{open_file, FileError};
+use crate::io::{read_file, FileError};
+
+/// Environment variable containing a path to a raw Blowfish key file or an ARM7 BIOS, see [`BlowfishKey::from_env`].
+pub const BLOWFISH_KEY_ENV: &str = "DSROM_BLOWFISH_KEY";
+
+/// SHA1 hashes of known Nintendo DS ARM7 BIOS dumps, i.e. a dump made on a DS device and the 0x4000-byte retail BIOS.
+pub const ARM7_BIOS_SHA1S: [[u8; 0x14]; 2] = [
+    [0x6e, 0xe8, 0x30, 0xc7, 0xf5, 0x52, 0xc5, 0xbf, 0x19, 0x4c, 0x20, 0xa2, 0xc1, 0x3d, 0x5b, 0xb4, 0x4b, 0xdb, 0x5c, 0x03],
+    [0x24, 0xf6, 0x7b, 0xde, 0xa1, 0x15, 0xa2, 0xc8, 0x47, 0xc8, 0x81, 0x3a, 0x26, 0x25, 0x02, 0xee, 0x16, 0x07, 0xb7, 0xdf],
+];
+
+/// Offset of the Blowfish key in the ARM7 BIOS.
+const ARM7_BIOS_KEY_OFFSET: usize = 0x30;
 
 /// De/encrypts data using the [Blowfish](https://en.wikipedia.org/wiki/Blowfish_(cipher)) block cipher.
 #[repr(C)]
@@ -191,6 +205,35 @@ pub enum BlowfishKeyError {
         /// Backtrace to the source of the error.
         backtrace: Backtrace,
     },
+    /// Occurs when a raw key has the wrong size.
+    #[snafu(display("expected Blowfish key to be {expected:#x} bytes long but got {actual:#x} bytes:\n{backtrace}"))]
+    InvalidKeySize {
+        /// Expected size.
+        expected: usize,
+        /// Actual input size.
+        actual: usize,
+        /// Backtrace to the source of the error.
+        backtrace: Backtrace,
+    },
+    /// Occurs when the ARM7 BIOS doesn't match any of [`ARM7_BIOS_SHA1S`], i.e. it is not a Nintendo DS ARM7 BIOS or it is
+    /// corrupted.
+    #[snafu(display(
+        "ARM7 BIOS has SHA1 {actual} but expected one of {expected}, make sure it is a Nintendo DS ARM7 BIOS dump:\n{backtrace}"
+    ))]
+    WrongArm7Bios {
+        /// Expected SHA1 hashes as comma-separated hex strings.
+        expected: String,
+        /// Actual SHA1 hash as a hex string.
+        actual: String,
+        /// Backtrace to the source of the error.
+        backtrace: Backtrace,
+    },
+    /// Occurs when the [`BLOWFISH_KEY_ENV`] environment variable is not valid unicode.
+    #[snafu(display("environment variable {BLOWFISH_KEY_ENV} is not valid unicode:\n{backtrace}"))]
+    InvalidEnv {
+        /// Backtrace to the source of the error.
+        backtrace: Backtrace,
+    },
 }
 
 impl BlowfishKey {
@@ -198,18 +241,122 @@ impl BlowfishKey {
     ///
     /// # Errors
     ///
-    /// This function will return an error if the file is too small to contain a Blowfish key.
+    /// See [`Self::from_arm7_bios`].
     pub fn from_arm7_bios_path<P: AsRef<Path>>(path: P) -> Result<Self, BlowfishKeyError> {
-        let mut file = open_file(path)?;
-        let size = file.metadata()?.len() as usize;
-        if size < 0x30 + size_of::<Self>() {
-            return TooSmallSnafu { expected: 0x30 + size_of::<Self>(), actual: size }.fail();
+        Self::from_arm7_bios(&read_file(path)?)
+    }
+
+    /// Extracts the base Blowfish key from the ARM7 BIOS. Use [`Self::try_from`] for a raw 0x1048-byte key.
+    ///
+    /// # Errors
+    ///
+    /// This function will return an error if the BIOS is too small to contain a Blowfish key, or if it doesn't match any of
+    /// [`ARM7_BIOS_SHA1S`].
+    pub fn from_arm7_bios(bios: &[u8]) -> Result<Self, BlowfishKeyError> {
+        let key_end = ARM7_BIOS_KEY_OFFSET + size_of::<Self>();
+        if bios.len() < key_end {
+            return TooSmallSnafu { expected: key_end, actual: bios.len() }.fail();
+        }
+
+        let sha1: [u8; 0x14] = Sha1::digest(bios).into();
+        if !ARM7_BIOS_SHA1S.contains(&sha1) {
+            let to_hex = |hash: &[u8]| hash.iter().map(|b| format!("{b:02x}")).collect::<String>();
+            let expected = ARM7_BIOS_SHA1S.iter().map(|hash| to_hex(hash)).collect::<Vec<_>>().join(", ");
+            return WrongArm7BiosSnafu { expected, actual: to_hex(&sha1) }.fail();
         }
 
+        Self::try_from(&bios[ARM7_BIOS_KEY_OFFSET..key_end])
+    }
+
+    /// Loads a raw 0x1048-byte Blowfish key from a file.
+    ///
+    /// # Errors
+    ///
+    /// This function will return an error if the file could not be read or has the wrong size.
+    pub fn from_key_path<P: AsRef<Path>>(path: P) -> Result<Self, BlowfishKeyError> {
+        Self::try_from(read_file(path)?.as_slice())
+    }
+
+    /// Reads a raw 0x1048-byte Blowfish key from a reader.
+    ///
+    /// # Errors
+    ///
+    /// This function will return an error if the reader fails or ends before the whole key was read.
+    pub fn from_reader<R: Read>(mut reader: R) -> Result<Self, BlowfishKeyError> {
         let mut key = [0; size_of::<Self>()];
-        file.seek(SeekFrom::Start(0x30))?;
-        file.read_exact(&mut key)?;
+        reader.read_exact(&mut key)?;
+        Ok(Self(key))
+    }
+
+    /// Loads the Blowfish key from the path in the [`BLOWFISH_KEY_ENV`] environment variable. The file can either be a raw
+    /// 0x1048-byte key or an ARM7 BIOS. Returns `None` if the variable is not set.
+    ///
+    /// # Errors
+    ///
+    /// See [`Self::from_key_path`] and [`Self::from_arm7_bios`].
+    pub fn from_env() -> Result<Option<Self>, BlowfishKeyError> {
+        let path = match env::var(BLOWFISH_KEY_ENV) {
+            Ok(path) => path,
+            Err(env::VarError::NotPresent) => return Ok(None),
+            Err(env::VarError::NotUnicode(_)) => return InvalidEnvSnafu {}.fail(),
+        };
+        let data = read_file(path)?;
+        if data.len() == size_of::<Self>() {
+            Ok(Some(Self::try_from(data.as_slice())?))
+        } else {
+            Ok(Some(Self::from_arm7_bios(&data)?))
+        }
+    }
+}
+
+impl TryFrom<&[u8]> for BlowfishKey {
+    type Error = BlowfishKeyError;
 
+    fn try_from(value: &[u8]) -> Result<Self, Self::Error> {
+        if value.len() != size_of::<Self>() {
+            return InvalidKeySizeSnafu { expected: size_of::<Self>(), actual: value.len() }.fail();
+        }
+        let mut key = [0; size_of::<Self>()];
+        key.copy_from_slice(value);
         Ok(Self(key))
     }
 }
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn to_hex(hash: &[u8]) -> String {
+        hash.iter().map(|b| format!("{b:02x}")).collect()
+    }
+
+    #[test]
+    fn arm7_bios_sha1s() {
+        assert_eq!(to_hex(&ARM7_BIOS_SHA1S[0]), "6ee830c7f552c5bf194c20a2c13d5bb44bdb5c03");
+        assert_eq!(to_hex(&ARM7_BIOS_SHA1S[1]), "24f67bdea115a2c847c8813a262502ee1607b7df");
+    }
+
+    #[test]
+    fn from_arm7_bios_rejects_raw_key() {
+        let key = (0..size_of::<BlowfishKey>()).map(|i| i as u8).collect::<Vec<_>>();
+        let result = BlowfishKey::from_arm7_bios(&key);
+        assert!(matches!(result, Err(BlowfishKeyError::TooSmall { .. })));
+        assert_eq!(BlowfishKey::try_from(key.as_slice()).unwrap().0.as_slice(), key.as_slice());
+    }
+
+    #[test]
+    fn from_arm7_bios_rejects_wrong_bios() {
+        let bios = vec![0; 0x4000];
+        let Err(BlowfishKeyError::WrongArm7Bios { expected, actual, .. }) = BlowfishKey::from_arm7_bios(&bios) else {
+            panic!("expected WrongArm7Bios error");
+        };
+        assert_eq!(expected, format!("{}, {}", to_hex(&ARM7_BIOS_SHA1S[0]), to_hex(&ARM7_BIOS_SHA1S[1])));
+        assert_eq!(actual, to_hex(&Sha1::digest(&bios)));
+    }
+
+    #[test]
+    fn from_arm7_bios_rejects_small_bios() {
+        let result = BlowfishKey::from_arm7_bios(&[0; 0x100]);
+        assert!(matches!(result, Err(BlowfishKeyError::TooSmall { expected: 0x1078, actual: 0x100, .. })));
+    }
+}
@@ -1,8 +1,10 @@
-use std::backtrace::Backtrace;
+use std::{backtrace::Backtrace, path::Path};
 
 use sha1::{Digest, Sha1};
 use snafu::Snafu;
 
+use crate::io::{read_file, FileError};
+
 /// Performs HMAC-SHA1 hashing to generate signatures.
 #[derive(Clone)]
 pub struct HmacSha1 {
@@ -20,6 +22,16 @@ impl HmacSha1 {
         Self { inner_pad, outer_pad }
     }
 
+    /// Loads a 64-byte HMAC-SHA1 key from a file.
+    ///
+    /// # Errors
+    ///
+    /// This function will return an error if the file could not be read or is not 64 bytes long.
+    pub fn from_key_file<P: AsRef<Path>>(path: P) -> Result<Self, HmacSha1KeyFileError> {
+        let key = read_file(path)?;
+        Ok(Self::try_from(key.as_slice())?)
+    }
+
     /// Computes the HMAC-SHA1 hash of the given data using the key.
     pub fn compute(&self, data: &[u8]) -> [u8; 20] {
         let mut sha1 = Sha1::new();
@@ -48,6 +60,23 @@ pub enum HmacSha1FromBytesError {
     },
 }
 
+/// Errors related to [`HmacSha1::from_key_file`].
+#[derive(Debug, Snafu)]
+pub enum HmacSha1KeyFileError {
+    /// See [`FileError`].
+    #[snafu(transparent)]
+    File {
+        /// Source error.
+        source: FileError,
+    },
+    /// See [`HmacSha1FromBytesError`].
+    #[snafu(transparent)]
+    FromBytes {
+        /// Source error.
+        source: HmacSha1FromBytesError,
+    },
+}
+
 impl TryFrom<&[u8]> for HmacSha1 {
     type Error = HmacSha1FromBytesError;
 
@@ -0,0 +1,106 @@
+/// Seed bytes selected by [`Header::seed_select`](crate::rom::raw::Header::seed_select).
+pub const KEY2_SEED_BYTES: [u8; 8] = [0xe8, 0x4d, 0x5a, 0xb1, 0x17, 0x8f, 0x99, 0xd5];
+
+/// Initial value of the second KEY2 register.
+const KEY2_SEED_Y: u64 = 0x5c879b9b05;
+
+/// Mask of the 39-bit KEY2 registers.
+const KEY2_MASK: u64 = 0x7fffffffff;
+
+/// KEY2 stream cipher, used to en/decrypt card commands and data after the KEY1 phase. It consists of two 39-bit linear
+/// feedback shift registers, and en/decryption are the same operation.
+#[derive(Clone, Copy)]
+pub struct Key2 {
+    x: u64,
+    y: u64,
+}
+
+impl Key2 {
+    /// Creates a new [`Key2`] instance from the 24-bit random seed `mmmnnn` of the KEY2 activation command, and the
+    /// `seed_select` value in the ROM header.
+    pub fn new(seed: u32, seed_select: u8) -> Self {
+        let seed_byte = KEY2_SEED_BYTES[(seed_select & 7) as usize] as u64;
+        let x = ((seed as u64 & 0xffffff) << 15) + 0x6000 + seed_byte;
+        Self::from_registers(x, KEY2_SEED_Y)
+    }
+
+    /// Creates a new [`Key2`] instance from the raw, not yet bit-reversed, register values.
+    pub fn from_registers(x: u64, y: u64) -> Self {
+        Self { x: Self::reverse_39(x), y: Self::reverse_39(y) }
+    }
+
+    fn reverse_39(value: u64) -> u64 {
+        (value & KEY2_MASK).reverse_bits() >> (64 - 39)
+    }
+
+    /// Returns the next byte of the keystream.
+    pub fn next_byte(&mut self) -> u8 {
+        let x = self.x;
+        let y = self.y;
+        self.x = ((((x >> 5) ^ (x >> 17) ^ (x >> 18) ^ (x >> 31)) & 0xff) + (x << 8)) & KEY2_MASK;
+        self.y = ((((y >> 5) ^ (y >> 23) ^ (y >> 18) ^ (y >> 31)) & 0xff) + (y << 8)) & KEY2_MASK;
+        (self.x ^ self.y) as u8
+    }
+
+    /// En/decrypts `data` in place, advancing the keystream by one byte per byte of data.
+    pub fn apply(&mut self, data: &mut [u8]) {
+        for byte in data {
+            *byte ^= self.next_byte();
+        }
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    /// Returns the first `N` bytes of the keystream.
+    fn keystream<const N: usize>(mut key2: Key2) -> [u8; N] {
+        let mut stream = [0; N];
+        key2.apply(&mut stream);
+        stream
+    }
+
+    #[test]
+    fn seed_registers() {
+        // GBATEK: X = mmmnnn shl 15 + 6000h + seedbyte, Y = 5C879B9B05h, both bit-reversed
+        let key2 = Key2::new(0x123456, 2);
+        assert_eq!(key2.x, Key2::reverse_39((0x123456 << 15) + 0x6000 + 0x5a));
+        assert_eq!(key2.y, Key2::reverse_39(0x5c879b9b05));
+        assert_eq!(Key2::reverse_39(1), 1 << 38);
+        assert_eq!(Key2::reverse_39(Key2::reverse_39(0x5c879b9b05)), 0x5c879b9b05);
+
+        // The seed is 24 bits and the seed select is 3 bits
+        assert_eq!(keystream::<16>(Key2::new(0x123456, 2)), keystream::<16>(Key2::new(0xff123456, 10)));
+    }
+
+    #[test]
+    fn known_keystream() {
+        // Computed with the GBATEK register update formulas
+        assert_eq!(
+            keystream(Key2::new(0, 0)),
+            [0x91, 0xd6, 0x91, 0x21, 0xc8, 0x71, 0xf9, 0x6a, 0x82, 0x54, 0x45, 0x54, 0xe5, 0x53, 0x39, 0x57]
+        );
+        assert_eq!(
+            keystream(Key2::new(0x123456, 2)),
+            [0x91, 0x0b, 0x75, 0x96, 0x24, 0x44, 0x55, 0x03, 0x99, 0x79, 0xd9, 0xab, 0x8d, 0x37, 0x61, 0x22]
+        );
+        assert_eq!(
+            keystream(Key2::new(0xffffff, 7)),
+            [0x92, 0x2f, 0x71, 0x50, 0x37, 0x71, 0xd6, 0x2b, 0x5b, 0xb8, 0xfa, 0xf5, 0x16, 0x9f, 0x46, 0xce]
+        );
+
+        // Zeroed registers stay zero
+        assert_eq!(keystream::<16>(Key2::from_registers(0, 0)), [0; 16]);
+    }
+
+    #[test]
+    fn apply_twice() {
+        let data = *b"KEY2 en/decrypts";
+        let mut encrypted = data;
+        Key2::new(0xabcdef, 5).apply(&mut encrypted);
+        assert_ne!(encrypted, data);
+        Key2::new(0xabcdef, 5).apply(&mut encrypted);
+        assert_eq!(encrypted, data);
+    }
+}
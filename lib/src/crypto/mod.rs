@@ -3,3 +3,12 @@ pub mod blowfish;
 
 /// Authentication using HMAC-SHA1.
 pub mod hmac_sha1;
+
+/// De/encryption of card commands and data using the KEY2 stream cipher.
+pub mod key2;
+
+/// De/encryption of DSi areas using AES-CTR.
+pub mod modcrypt;
+
+/// Signature verification using RSA-SHA1.
+pub mod rsa;
@@ -0,0 +1,145 @@
+use aes::{
+    cipher::{BlockEncrypt, KeyInit},
+    Aes128,
+};
+
+use crate::rom::raw::Header;
+
+/// Constant added by the DSi key scrambler when deriving a key from [`Modcrypt::scramble_key`].
+const KEY_SCRAMBLER_CONSTANT: u128 = 0xfffefb4e295902582a680f5f1a4f3e79;
+
+/// De/encrypts modcrypt areas in DSi titles using AES-CTR.
+///
+/// Keys, counters and data blocks are in the little-endian order used by the DSi AES engine, and are byte-reversed before
+/// being passed to the AES cipher.
+#[derive(Clone)]
+pub struct Modcrypt {
+    cipher: Aes128,
+}
+
+impl Modcrypt {
+    /// Creates a new [`Modcrypt`] instance with the given normal key.
+    pub fn new(key: [u8; 16]) -> Self {
+        let mut key = key;
+        key.reverse();
+        Self { cipher: Aes128::new(&key.into()) }
+    }
+
+    /// Derives the modcrypt key of a ROM from its header. Retail titles use a key scrambled from the gamecode and the ARM9i
+    /// SHA1-HMAC, while titles with the debug key flag use the first 16 bytes of the header.
+    pub fn from_header(header: &Header) -> Self {
+        if header.dsi_flags.modcrypt_debug_key() {
+            let mut key = [0u8; 16];
+            key[..12].copy_from_slice(&header.title.0);
+            key[12..].copy_from_slice(&header.gamecode.0);
+            return Self::new(key);
+        }
+
+        let gamecode = header.gamecode.0;
+        let mut key_x = [0u8; 16];
+        key_x[..8].copy_from_slice(b"Nintendo");
+        key_x[8..12].copy_from_slice(&gamecode);
+        key_x[12..].copy_from_slice(&[gamecode[3], gamecode[2], gamecode[1], gamecode[0]]);
+
+        let mut key_y = [0u8; 16];
+        key_y.copy_from_slice(&header.sha1_hmac_arm9i[..16]);
+
+        let key = Self::scramble_key(u128::from_le_bytes(key_x), u128::from_le_bytes(key_y));
+        Self::new(key.to_le_bytes())
+    }
+
+    /// Derives a normal key from a key X and key Y, like the key scrambler of the DSi AES engine.
+    pub fn scramble_key(key_x: u128, key_y: u128) -> u128 {
+        ((key_x ^ key_y).wrapping_add(KEY_SCRAMBLER_CONSTANT)).rotate_left(42)
+    }
+
+    /// Returns the initial counter of modcrypt area 1, taken from the SHA1-HMAC of the ARM9 program with its secure area.
+    pub fn area_1_counter(header: &Header) -> [u8; 16] {
+        let mut counter = [0u8; 16];
+        counter.copy_from_slice(&header.sha1_hmac_arm9_with_secure_area[..16]);
+        counter
+    }
+
+    /// Returns the initial counter of modcrypt area 2, taken from the SHA1-HMAC of the ARM7 program.
+    pub fn area_2_counter(header: &Header) -> [u8; 16] {
+        let mut counter = [0u8; 16];
+        counter.copy_from_slice(&header.sha1_hmac_arm7[..16]);
+        counter
+    }
+
+    /// De/encrypts `data` in place, starting at the given counter. Since this is a stream cipher, decryption and encryption
+    /// are the same operation.
+    pub fn crypt(&self, counter: [u8; 16], data: &mut [u8]) {
+        let mut counter = u128::from_le_bytes(counter);
+        for chunk in data.chunks_mut(16) {
+            let mut block = counter.to_be_bytes().into();
+            self.cipher.encrypt_block(&mut block);
+            chunk.iter_mut().zip(block.iter().rev()).for_each(|(d, k)| *d ^= *k);
+            counter = counter.wrapping_add(1);
+        }
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use bytemuck::Zeroable;
+
+    use super::*;
+    use crate::str::AsciiArray;
+
+    /// Returns a retail header with gamecode `ABCE` and distinct HMACs for the key and counters.
+    fn header() -> Header {
+        let mut header = Header::zeroed();
+        header.gamecode = AsciiArray(*b"ABCE");
+        header.sha1_hmac_arm9i[..16].copy_from_slice(&std::array::from_fn::<u8, 16, _>(|i| 0x10 + i as u8));
+        header.sha1_hmac_arm9_with_secure_area[..16].copy_from_slice(&std::array::from_fn::<u8, 16, _>(|i| 0x20 + i as u8));
+        header.sha1_hmac_arm7[..16].copy_from_slice(&std::array::from_fn::<u8, 16, _>(|i| 0x30 + i as u8));
+        header
+    }
+
+    #[test]
+    fn scramble_key() {
+        let key = Modcrypt::scramble_key(0x0123456789abcdeffedcba9876543210, 0x00112233445566778899aabbccddeeff);
+        assert_eq!(key, 0x5eb7c282b47e0b53646da004c58a8bdd);
+    }
+
+    #[test]
+    fn counters() {
+        let header = header();
+        assert_eq!(Modcrypt::area_1_counter(&header), std::array::from_fn(|i| 0x20 + i as u8));
+        assert_eq!(Modcrypt::area_2_counter(&header), std::array::from_fn(|i| 0x30 + i as u8));
+    }
+
+    #[test]
+    fn known_answer() {
+        // Key scrambled from "NintendoABCEECBA" and the ARM9i HMAC
+        let modcrypt = Modcrypt::from_header(&header());
+        let key =
+            Modcrypt::new([0x1e, 0x9e, 0x66, 0x6d, 0x79, 0x5d, 0xdb, 0x2e, 0x07, 0x42, 0x2b, 0x6a, 0x8b, 0xc6, 0x76, 0xc9]);
+
+        let expected = [
+            0xa7, 0xee, 0x98, 0x33, 0x51, 0x4d, 0xa4, 0x3a, 0x2f, 0xae, 0x58, 0x68, 0x18, 0x54, 0xe0, 0x02, 0xb7, 0x29, 0xd1,
+            0x54, 0x6c, 0x0d, 0x82, 0x1d, 0x66, 0xe6, 0x5b, 0xb0, 0xd9, 0x19, 0xd9, 0xe1, 0x3a, 0xa3, 0x61, 0x7f, 0x44, 0xbf,
+            0xdf, 0x3c,
+        ];
+        for modcrypt in [modcrypt, key] {
+            let mut data = std::array::from_fn::<u8, 40, _>(|i| i as u8);
+            modcrypt.crypt(Modcrypt::area_1_counter(&header()), &mut data);
+            assert_eq!(data, expected);
+        }
+    }
+
+    #[test]
+    fn counter_wraps() {
+        let modcrypt = Modcrypt::from_header(&header());
+        let mut data = [0; 32];
+        modcrypt.crypt([0xff; 16], &mut data);
+        assert_eq!(
+            data,
+            [
+                0x7e, 0x43, 0x37, 0x49, 0xa0, 0xc5, 0xa7, 0x1b, 0xe4, 0x3b, 0x3c, 0x78, 0xdd, 0xe4, 0x13, 0x9f, 0x71, 0xcf,
+                0x88, 0x68, 0xfa, 0xfb, 0x86, 0xe4, 0x12, 0xfb, 0x08, 0x82, 0xc2, 0xed, 0x46, 0x91
+            ]
+        );
+    }
+}
@@ -0,0 +1,205 @@
+use std::{backtrace::Backtrace, path::Path};
+
+use num_bigint::BigUint;
+use sha1::{Digest, Sha1};
+use snafu::Snafu;
+
+use crate::io::{read_file, FileError};
+
+/// Default public exponent of Nintendo's RSA keys.
+pub const DEFAULT_PUBLIC_EXPONENT: u32 = 0x10001;
+
+/// ASN.1 DigestInfo prefix of a SHA1 hash in a PKCS#1 v1.5 signature.
+const SHA1_DIGEST_INFO: [u8; 15] = [0x30, 0x21, 0x30, 0x09, 0x06, 0x05, 0x2b, 0x0e, 0x03, 0x02, 0x1a, 0x05, 0x00, 0x04, 0x14];
+
+/// Minimum number of 0xff padding bytes in a PKCS#1 v1.5 signature.
+const MIN_PADDING_SIZE: usize = 8;
+
+/// RSA public key, used to verify signatures. No keys are bundled with this crate, so Nintendo's public keys must be loaded
+/// from files, see [`Self::from_modulus_file`].
+#[derive(Clone)]
+pub struct RsaPublicKey {
+    modulus: BigUint,
+    exponent: BigUint,
+    size: usize,
+}
+
+/// Errors related to [`RsaPublicKey::from_modulus_file`].
+#[derive(Debug, Snafu)]
+pub enum RsaKeyFileError {
+    /// See [`FileError`].
+    #[snafu(transparent)]
+    File {
+        /// Source error.
+        source: FileError,
+    },
+    /// Occurs when the key file is empty.
+    #[snafu(display("RSA key file is empty:\n{backtrace}"))]
+    EmptyKey {
+        /// Backtrace to the source of the error.
+        backtrace: Backtrace,
+    },
+}
+
+impl RsaPublicKey {
+    /// Creates a new [`RsaPublicKey`] from a big-endian modulus and a public exponent.
+    pub fn new(modulus: &[u8], exponent: u32) -> Self {
+        Self { modulus: BigUint::from_bytes_be(modulus), exponent: BigUint::from(exponent), size: modulus.len() }
+    }
+
+    /// Loads a raw big-endian modulus from a file, using [`DEFAULT_PUBLIC_EXPONENT`] as the public exponent.
+    ///
+    /// # Errors
+    ///
+    /// This function will return an error if the file could not be read or is empty.
+    pub fn from_modulus_file<P: AsRef<Path>>(path: P) -> Result<Self, RsaKeyFileError> {
+        let modulus = read_file(path)?;
+        if modulus.is_empty() {
+            return EmptyKeySnafu {}.fail();
+        }
+        Ok(Self::new(&modulus, DEFAULT_PUBLIC_EXPONENT))
+    }
+
+    /// Returns the size of the modulus in bytes, which is also the signature size.
+    pub fn size(&self) -> usize {
+        self.size
+    }
+
+    /// Verifies a PKCS#1 v1.5 RSA-SHA1 signature of the given data. The decrypted signature must consist of at least 8 bytes of
+    /// 0xff padding, followed by the ASN.1 DigestInfo and the SHA1 hash.
+    pub fn verify_sha1(&self, data: &[u8], signature: &[u8]) -> bool {
+        if signature.len() != self.size {
+            return false;
+        }
+        let signature = BigUint::from_bytes_be(signature);
+        if signature >= self.modulus {
+            return false;
+        }
+        let Some(expected) = Self::encode_sha1(self.size, data) else {
+            return false;
+        };
+
+        let decrypted = signature.modpow(&self.exponent, &self.modulus).to_bytes_be();
+        let mut block = vec![0; self.size];
+        block[self.size - decrypted.len()..].copy_from_slice(&decrypted);
+        block == expected
+    }
+
+    /// Returns the PKCS#1 v1.5 encoding of the SHA1 hash of `data`, i.e. `0x00 0x01 0xff... 0x00 [DigestInfo] hash`, or
+    /// `None` if `size` is too small to fit 8 bytes of padding.
+    fn encode_sha1(size: usize, data: &[u8]) -> Option<Vec<u8>> {
+        let hash = Sha1::digest(data);
+        let payload_start = size.checked_sub(SHA1_DIGEST_INFO.len() + hash.len())?;
+        if payload_start < 3 + MIN_PADDING_SIZE {
+            return None;
+        }
+
+        let mut block = vec![0xff; size];
+        block[0] = 0x00;
+        block[1] = 0x01;
+        block[payload_start - 1] = 0x00;
+        block[payload_start..payload_start + SHA1_DIGEST_INFO.len()].copy_from_slice(&SHA1_DIGEST_INFO);
+        block[payload_start + SHA1_DIGEST_INFO.len()..].copy_from_slice(&hash);
+        Some(block)
+    }
+}
+
+/// A set of named RSA public keys to verify signatures against.
+#[derive(Clone, Default)]
+pub struct RsaVerifier {
+    keys: Vec<(String, RsaPublicKey)>,
+}
+
+impl RsaVerifier {
+    /// Creates a new empty [`RsaVerifier`].
+    pub fn new() -> Self {
+        Self::default()
+    }
+
+    /// Adds a named public key to this verifier.
+    pub fn add_key<S: Into<String>>(&mut self, name: S, key: RsaPublicKey) {
+        self.keys.push((name.into(), key));
+    }
+
+    /// Returns the names of the keys in this verifier.
+    pub fn key_names(&self) -> impl Iterator<Item = &str> {
+        self.keys.iter().map(|(name, _)| name.as_str())
+    }
+
+    /// Verifies an RSA-SHA1 signature of the given data against every key, and returns the name of the first key which
+    /// matches.
+    pub fn verify_sha1(&self, data: &[u8], signature: &[u8]) -> Option<&str> {
+        self.keys.iter().find(|(_, key)| key.verify_sha1(data, signature)).map(|(name, _)| name.as_str())
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    /// Modulus of a 1024-bit test key with the public exponent [`DEFAULT_PUBLIC_EXPONENT`].
+    const MODULUS: [u8; 0x80] = [
+        0xbe, 0x6e, 0x82, 0x41, 0x91, 0x79, 0xf5, 0x79, 0xba, 0x56, 0x79, 0xca, 0xae, 0x83, 0x68, 0xbb, 0xe0, 0x00, 0xff,
+        0xef, 0x1c, 0xd7, 0xf7, 0xa1, 0xfa, 0xe3, 0x08, 0xef, 0x81, 0x11, 0x8a, 0x9b, 0xd8, 0x93, 0xa9, 0x4f, 0xee, 0x0f,
+        0xb0, 0x83, 0x11, 0x30, 0x15, 0x4b, 0x65, 0x4c, 0x4c, 0xf5, 0xc2, 0xb7, 0xa2, 0xda, 0x2a, 0x19, 0x2c, 0xfa, 0x6b,
+        0x38, 0xcf, 0x31, 0xee, 0xba, 0x5a, 0x30, 0xbe, 0x1a, 0x6f, 0xe7, 0xa2, 0xbc, 0x8b, 0x22, 0xed, 0x41, 0x55, 0x0c,
+        0xcc, 0xd5, 0xf9, 0xa1, 0xb7, 0xfd, 0xb8, 0xee, 0x9f, 0x88, 0x37, 0x37, 0x5d, 0xd4, 0x64, 0x9f, 0xcb, 0xcf, 0xdf,
+        0x5b, 0xa2, 0xa4, 0x47, 0x97, 0x4c, 0xd8, 0xb8, 0x98, 0x32, 0xa0, 0x32, 0xc2, 0x96, 0x5d, 0x50, 0xa6, 0x4d, 0x2f,
+        0x73, 0xa6, 0x0e, 0x37, 0x9e, 0xe9, 0x39, 0xe0, 0xac, 0xf7, 0x98, 0x3e, 0x7c, 0xa9,
+    ];
+
+    /// Signature of [`DATA`] with the private key of [`MODULUS`].
+    const SIGNATURE: [u8; 0x80] = [
+        0x26, 0x5a, 0xfd, 0x5c, 0x61, 0x58, 0xb3, 0x12, 0xd3, 0x14, 0xba, 0x0d, 0x05, 0x0f, 0x14, 0x37, 0xad, 0xf5, 0x0e,
+        0xd6, 0x6a, 0xd2, 0xe2, 0x57, 0x5d, 0x3b, 0x34, 0x20, 0x95, 0x65, 0x96, 0xa6, 0x8d, 0x03, 0xa0, 0xeb, 0xbf, 0x05,
+        0x96, 0x14, 0x28, 0x35, 0xe6, 0x7f, 0x77, 0xee, 0x87, 0x07, 0x6b, 0xcd, 0x83, 0xa3, 0x93, 0x9a, 0xb7, 0x10, 0xa2,
+        0x4e, 0x28, 0xb0, 0x4a, 0x9e, 0x44, 0xd5, 0x38, 0xe2, 0x0a, 0xe1, 0x29, 0xde, 0x85, 0x5c, 0x60, 0x9a, 0x76, 0xa9,
+        0xe8, 0xff, 0xe9, 0x51, 0xf9, 0x66, 0xe8, 0xaf, 0xbc, 0xeb, 0x04, 0x08, 0xf7, 0x98, 0x2c, 0x1a, 0x04, 0xb7, 0x7a,
+        0x35, 0xed, 0x50, 0x65, 0x70, 0xbf, 0xf0, 0x22, 0x27, 0xad, 0x5a, 0x95, 0x85, 0x23, 0xac, 0xe3, 0x8d, 0xe6, 0xd5,
+        0x06, 0x3f, 0x30, 0xad, 0x70, 0x27, 0xc3, 0x37, 0x79, 0x19, 0xeb, 0x6f, 0x67, 0x67,
+    ];
+
+    /// Signature of [`DATA`] with a bare SHA1 hash instead of the DigestInfo.
+    const BARE_HASH_SIGNATURE: [u8; 0x80] = [
+        0x63, 0x6a, 0x63, 0x85, 0x6b, 0x33, 0xe8, 0xac, 0x6b, 0x12, 0x7d, 0xcd, 0x97, 0x8d, 0xd5, 0x93, 0x44, 0x78, 0x74,
+        0xf2, 0xe9, 0xf5, 0xe3, 0x46, 0x9a, 0x63, 0xda, 0xd4, 0xdf, 0x04, 0x15, 0x4e, 0xf7, 0x69, 0x9e, 0x88, 0xf8, 0xc6,
+        0xe6, 0x4e, 0xf3, 0xee, 0x60, 0xf6, 0x36, 0xeb, 0x8e, 0x55, 0x4c, 0x2f, 0x92, 0x1d, 0x14, 0xa5, 0xfe, 0x1c, 0x3c,
+        0x83, 0x68, 0x35, 0xf2, 0x25, 0xc2, 0xd9, 0x6d, 0x40, 0x30, 0x63, 0x35, 0xfe, 0x56, 0x96, 0x45, 0xb5, 0xef, 0x1a,
+        0x2d, 0x82, 0xb0, 0x3c, 0x5e, 0xe1, 0x1b, 0x25, 0xcc, 0x20, 0xdd, 0xc9, 0x3b, 0x60, 0x0b, 0x98, 0x7a, 0x4e, 0x8e,
+        0x1f, 0xfc, 0x7c, 0x8c, 0x81, 0x89, 0x10, 0x08, 0x91, 0x41, 0x5f, 0x80, 0x8b, 0x8f, 0xc2, 0x28, 0x88, 0x3e, 0x9e,
+        0x36, 0xa9, 0x62, 0xce, 0x64, 0xd7, 0x4f, 0xec, 0x4d, 0x8f, 0x56, 0x92, 0x6c, 0xa9,
+    ];
+
+    const DATA: &[u8] = b"ds-rom RSA test vector";
+
+    #[test]
+    fn known_vector() {
+        let key = RsaPublicKey::new(&MODULUS, DEFAULT_PUBLIC_EXPONENT);
+        assert!(key.verify_sha1(DATA, &SIGNATURE));
+        assert!(!key.verify_sha1(b"ds-rom RSA test vectors", &SIGNATURE));
+        assert!(!key.verify_sha1(DATA, &BARE_HASH_SIGNATURE));
+        assert!(!key.verify_sha1(DATA, &SIGNATURE[1..]));
+
+        let mut signature = SIGNATURE;
+        signature[0x40] ^= 1;
+        assert!(!key.verify_sha1(DATA, &signature));
+
+        let mut verifier = RsaVerifier::new();
+        verifier.add_key("other", RsaPublicKey::new(&[0xff; 0x80], DEFAULT_PUBLIC_EXPONENT));
+        verifier.add_key("test", key);
+        assert_eq!(verifier.verify_sha1(DATA, &SIGNATURE), Some("test"));
+        assert_eq!(verifier.verify_sha1(DATA, &BARE_HASH_SIGNATURE), None);
+    }
+
+    #[test]
+    fn encode_sha1_requires_padding() {
+        let payload_size = SHA1_DIGEST_INFO.len() + 0x14;
+        assert!(RsaPublicKey::encode_sha1(payload_size + 3 + MIN_PADDING_SIZE - 1, DATA).is_none());
+
+        let block = RsaPublicKey::encode_sha1(payload_size + 3 + MIN_PADDING_SIZE, DATA).unwrap();
+        assert_eq!(&block[..2], &[0x00, 0x01]);
+        assert_eq!(&block[2..2 + MIN_PADDING_SIZE], &[0xff; MIN_PADDING_SIZE]);
+        assert_eq!(block[2 + MIN_PADDING_SIZE], 0x00);
+        assert!(block.ends_with(&Sha1::digest(DATA)));
+    }
+}
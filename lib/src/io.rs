@@ -5,7 +5,7 @@ use std::{
     path::Path,
 };
 
-use snafu::Snafu;
+use snafu::{ResultExt, Snafu};
 
 #[derive(Debug, Snafu)]
 pub enum FileError {
@@ -23,6 +23,8 @@ pub enum FileError {
     DirOutOfMemory { path: String, backtrace: Backtrace },
     #[snafu(display("the file '{path}' already exists:\n{backtrace}"))]
     AlreadyExists { path: String, backtrace: Backtrace },
+    #[snafu(display("failed to write file '{path}': {source}\n{backtrace}"))]
+    WriteFailed { path: String, source: io::Error, backtrace: Backtrace },
 }
 
 /// Wrapper for [`File::open`] with clearer errors.
@@ -51,7 +53,7 @@ pub fn create_file<P: AsRef<Path>>(path: P) -> Result<File, FileError> {
             match err.kind() {
                 io::ErrorKind::AlreadyExists => return AlreadyExistsSnafu { path }.fail(),
                 io::ErrorKind::NotFound => return FileParentNotFoundSnafu { path }.fail(),
-                _ => Err(err)?,
+                _ => return Err(err).context(WriteFailedSnafu { path }),
             }
         }
     };
@@ -75,7 +77,7 @@ pub fn read_file<P: AsRef<Path>>(path: P) -> Result<Vec<u8>, FileError> {
             match err.kind() {
                 io::ErrorKind::NotFound => return FileNotFoundSnafu { path }.fail(),
                 io::ErrorKind::OutOfMemory => return FileOutOfMemorySnafu { path }.fail(),
-                _ => todo!(),
+                _ => Err(err)?,
             }
         }
     };
@@ -92,7 +94,7 @@ pub fn write_file<P: AsRef<Path>, C: AsRef<[u8]>>(path: P, contents: C) -> Resul
             let path = path.to_string_lossy();
             match err.kind() {
                 io::ErrorKind::AlreadyExists => AlreadyExistsSnafu { path }.fail(),
-                _ => Err(err)?,
+                _ => Err(err).context(WriteFailedSnafu { path }),
             }
         }
     }
@@ -144,3 +146,16 @@ pub fn create_dir_all<P: AsRef<Path>>(path: P) -> Result<(), FileError> {
     }
     Ok(())
 }
+
+/// Wrapper for [`fs::remove_file`] with clearer errors.
+pub fn remove_file<P: AsRef<Path>>(path: P) -> Result<(), FileError> {
+    let path = path.as_ref();
+    if let Err(err) = fs::remove_file(path) {
+        let path = path.to_string_lossy();
+        match err.kind() {
+            io::ErrorKind::NotFound => return FileNotFoundSnafu { path }.fail(),
+            _ => Err(err)?,
+        }
+    }
+    Ok(())
+}
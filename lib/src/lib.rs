@@ -2,6 +2,8 @@
 
 #![warn(missing_docs)]
 
+/// NTR game card emulation.
+pub mod card;
 /// Compression algorithms.
 pub mod compress;
 /// CRC checksum algorithms.
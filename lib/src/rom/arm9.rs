@@ -11,7 +11,10 @@ use super::{
     Autoload, OverlayTable,
 };
 use crate::{
-    compress::lz77::{Lz77, Lz77DecompressError},
+    compress::{
+        cache::CompressionCache,
+        lz77::{Lz77, Lz77DecompressError, Lz77Strategy},
+    },
     crc::CRC_16_MODBUS,
     crypto::blowfish::{Blowfish, BlowfishError, BlowfishKey, BlowfishLevel},
 };
@@ -391,11 +394,34 @@ impl<'a> Arm9<'a> {
     ///
     /// See [`Self::is_compressed`], [`Lz77::compress`] and [`Self::build_info_mut`].
     pub fn compress(&mut self) -> Result<(), Arm9Error> {
+        self.compress_with_strategy(Lz77Strategy::Greedy)
+    }
+
+    /// Compresses this ARM9 program like [`Self::compress`], but chooses LZ77 tokens according to `strategy`.
+    ///
+    /// # Errors
+    ///
+    /// See [`Self::is_compressed`], [`Lz77::compress_with_strategy`] and [`Self::build_info_mut`].
+    pub fn compress_with_strategy(&mut self, strategy: Lz77Strategy) -> Result<(), Arm9Error> {
+        self.compress_with_cache(strategy, None)
+    }
+
+    /// Compresses this ARM9 program like [`Self::compress_with_strategy`], but reuses the result from `cache` if this program
+    /// has been compressed before.
+    ///
+    /// # Errors
+    ///
+    /// See [`Self::is_compressed`], [`CompressionCache::lz77`] and [`Self::build_info_mut`].
+    pub fn compress_with_cache(&mut self, strategy: Lz77Strategy, cache: Option<&CompressionCache>) -> Result<(), Arm9Error> {
         if self.is_compressed()? {
             return Ok(());
         }
 
-        let data: Cow<[u8]> = LZ77.compress(&self.data, COMPRESSION_START)?.into_vec().into();
+        let data = match cache {
+            Some(cache) => cache.lz77(&self.data, COMPRESSION_START, strategy)?,
+            None => LZ77.compress_with_strategy(&self.data, COMPRESSION_START, strategy)?,
+        };
+        let data: Cow<[u8]> = data.into_vec().into();
         let length = data.len();
         let old_data = replace(&mut self.data, data);
         let base_address = self.base_address();
@@ -45,12 +45,35 @@ pub struct RomConfig {
     pub files_dir: PathBuf,
     /// Path to path order file
     pub path_order: PathBuf,
+    /// Path to compressed files manifest, present if BIOS-compressed asset files are saved decompressed. Deserializes into
+    /// [`FileCompressionManifest`](crate::rom::FileCompressionManifest).
+    #[serde(skip_serializing_if = "Option::is_none", default)]
+    pub files_compression: Option<PathBuf>,
+    /// Path to expanded NARC files manifest, present if NARC asset files are saved as folders. Deserializes into
+    /// [`NarcManifest`](crate::rom::NarcManifest).
+    #[serde(skip_serializing_if = "Option::is_none", default)]
+    pub files_narcs: Option<PathBuf>,
+    /// Path to file ID manifest, present if asset files keep their IDs when files are added. Deserializes into
+    /// [`FileIdManifest`](crate::rom::FileIdManifest).
+    #[serde(skip_serializing_if = "Option::is_none", default)]
+    pub file_ids: Option<PathBuf>,
+    /// Asset files which share their contents with another file in the file allocation table. The built ROM shares them
+    /// too, as long as their contents are identical.
+    #[serde(skip_serializing_if = "Vec::is_empty", default)]
+    pub file_aliases: Vec<RomConfigFileAlias>,
+    /// If true, asset files with identical contents are only written once to the built ROM, to reduce its size.
+    #[serde(skip_serializing_if = "std::ops::Not::not", default)]
+    pub dedupe_files: bool,
 
     /// Path to HMAC SHA1 key file for ARM9
     pub arm9_hmac_sha1_key: Option<PathBuf>,
 
     /// Alignment of ROM sections
     pub alignment: RomConfigAlignment,
+
+    /// DSi area, only present in DSi-enhanced and DSi-exclusive ROMs
+    #[serde(skip_serializing_if = "Option::is_none", default)]
+    pub dsi: Option<RomConfigDsi>,
 }
 
 /// Path to autoload files
@@ -72,6 +95,15 @@ pub struct RomConfigUnknownAutoload {
     pub files: RomConfigAutoload,
 }
 
+/// Asset file which shares its contents with another file.
+#[derive(Serialize, Deserialize, Clone)]
+pub struct RomConfigFileAlias {
+    /// Path to the file, relative to the files directory.
+    pub path: String,
+    /// Path to the file whose contents are shared, relative to the files directory.
+    pub target: String,
+}
+
 /// Alignment of ROM sections.
 #[derive(Serialize, Deserialize, Clone)]
 pub struct RomConfigAlignment {
@@ -98,3 +130,71 @@ pub struct RomConfigAlignment {
     /// Alignment of each file.
     pub file: u32,
 }
+
+/// Paths and layout of the DSi area.
+#[derive(Serialize, Deserialize, Clone)]
+pub struct RomConfigDsi {
+    /// Byte value to append between sections in the DSi area.
+    pub padding_value: u8,
+
+    /// Path to the data between the start of the DSi area and the ARM9i program
+    pub reserved: PathBuf,
+
+    /// Path to ARM9i binary
+    pub arm9i_bin: PathBuf,
+    /// Path to ARM9i YAML, deserializes into [`DsiProgramOffsets`](crate::rom::DsiProgramOffsets).
+    pub arm9i_config: PathBuf,
+
+    /// Path to the data between the ARM9i and ARM7i programs, present if the ROM has such data
+    #[serde(skip_serializing_if = "Option::is_none", default)]
+    pub gap: Option<PathBuf>,
+
+    /// Path to ARM7i binary
+    pub arm7i_bin: PathBuf,
+    /// Path to ARM7i YAML, deserializes into [`DsiProgramOffsets`](crate::rom::DsiProgramOffsets).
+    pub arm7i_config: PathBuf,
+
+    /// Path to the data between the ARM7i program and the end of the DSi area, present if the ROM has such data
+    #[serde(skip_serializing_if = "Option::is_none", default)]
+    pub trailing: Option<PathBuf>,
+
+    /// Path to original digest sector hashtable, present if the ROM has a digest
+    #[serde(skip_serializing_if = "Option::is_none", default)]
+    pub digest_sector_hashtable: Option<PathBuf>,
+    /// Path to original digest block hashtable, present if the ROM has a digest
+    #[serde(skip_serializing_if = "Option::is_none", default)]
+    pub digest_block_hashtable: Option<PathBuf>,
+    /// Path to SHA1 of the original digest sectors, present if the ROM has a digest. Used to tell if the original hashtables
+    /// are stale.
+    #[serde(skip_serializing_if = "Option::is_none", default)]
+    pub digest_sectors_sha1: Option<PathBuf>,
+
+    /// ROM offset of the digest sector hashtable, present if the ROM has a digest. It's kept as long as the DS area still
+    /// fits before it, otherwise [`RomConfigDsiAlignment::digest_sector_hashtable`] is used.
+    #[serde(skip_serializing_if = "Option::is_none", default)]
+    pub digest_sector_hashtable_offset: Option<u32>,
+    /// ROM offset of the digest block hashtable, present if the ROM has a digest. It's kept as long as the sector hashtable
+    /// still fits before it, otherwise [`RomConfigDsiAlignment::digest_block_hashtable`] is used.
+    #[serde(skip_serializing_if = "Option::is_none", default)]
+    pub digest_block_hashtable_offset: Option<u32>,
+    /// ROM offset of the DSi area. It's kept as long as the preceding sections still fit before it, otherwise
+    /// [`RomConfigDsiAlignment::dsi_area`] is used.
+    #[serde(skip_serializing_if = "Option::is_none", default)]
+    pub dsi_area_offset: Option<u32>,
+
+    /// Alignment of DSi area sections which no longer fit at their original offsets
+    pub alignment: RomConfigDsiAlignment,
+}
+
+/// Alignment of DSi area sections.
+#[derive(Serialize, Deserialize, Clone)]
+pub struct RomConfigDsiAlignment {
+    /// Alignment of the digest sector hashtable.
+    pub digest_sector_hashtable: u32,
+    /// Alignment of the digest block hashtable.
+    pub digest_block_hashtable: u32,
+    /// Alignment of the DSi area.
+    pub dsi_area: u32,
+    /// Alignment of the ARM7i program, relative to the start of the DSi area.
+    pub arm7i: u32,
+}
@@ -0,0 +1,192 @@
+use std::{backtrace::Backtrace, borrow::Cow, ops::Range};
+
+use serde::{Deserialize, Serialize};
+use snafu::Snafu;
+
+use crate::{crypto::modcrypt::Modcrypt, rom::raw::Header};
+
+/// DSi-exclusive program, used for both the ARM9i and ARM7i programs. The limited autoload blocks of the program are kept
+/// in its data, as they are loaded and copied by the main ARM9/ARM7 program at runtime.
+#[derive(Clone)]
+pub struct DsiProgram<'a> {
+    data: Cow<'a, [u8]>,
+    offsets: DsiProgramOffsets,
+}
+
+/// Offsets in a [`DsiProgram`].
+#[derive(Serialize, Deserialize, Clone, Copy)]
+pub struct DsiProgramOffsets {
+    /// Base address.
+    pub base_address: u32,
+    /// Entrypoint function address, usually zero.
+    pub entry_function: u32,
+    /// Build info offset, relative to the start of the program. Zero if there is no build info.
+    pub build_info: u32,
+    /// Modcrypt area within the program, if any.
+    #[serde(skip_serializing_if = "Option::is_none", default)]
+    pub modcrypt_area: Option<ModcryptArea>,
+}
+
+/// Area of a [`DsiProgram`] which is encrypted with [`Modcrypt`].
+#[derive(Serialize, Deserialize, Clone, Copy)]
+pub struct ModcryptArea {
+    /// Offset relative to the start of the program.
+    pub offset: u32,
+    /// Size of the area.
+    pub size: u32,
+}
+
+impl<'a> DsiProgram<'a> {
+    /// Creates a new DSi program from raw data.
+    pub fn new<T: Into<Cow<'a, [u8]>>>(data: T, offsets: DsiProgramOffsets) -> Self {
+        Self { data: data.into(), offsets }
+    }
+
+    /// Returns a reference to the full data.
+    pub fn full_data(&self) -> &[u8] {
+        &self.data
+    }
+
+    /// Returns a mutable reference to the full data.
+    pub fn full_data_mut(&mut self) -> &mut [u8] {
+        self.data.to_mut()
+    }
+
+    /// Returns the base address of this program.
+    pub fn base_address(&self) -> u32 {
+        self.offsets.base_address
+    }
+
+    /// Returns the entrypoint function address.
+    pub fn entry_function(&self) -> u32 {
+        self.offsets.entry_function
+    }
+
+    /// Returns the build info offset.
+    pub fn build_info_offset(&self) -> u32 {
+        self.offsets.build_info
+    }
+
+    /// Returns a reference to the offsets of this program.
+    pub fn offsets(&self) -> &DsiProgramOffsets {
+        &self.offsets
+    }
+
+    /// Returns the range of the modcrypt area in this program, if it has one.
+    pub fn modcrypt_range(&self) -> Option<Range<usize>> {
+        let area = self.offsets.modcrypt_area?;
+        let start = area.offset as usize;
+        Some(start..start + area.size as usize)
+    }
+
+    /// De/encrypts the modcrypt area of this program in place, starting at the given counter. Does nothing if this program has
+    /// no modcrypt area.
+    ///
+    /// # Errors
+    ///
+    /// This function will return an error if the modcrypt area is out of bounds.
+    pub fn modcrypt(&mut self, modcrypt: &Modcrypt, counter: [u8; 16]) -> Result<(), DsiProgramError> {
+        let Some(range) = self.modcrypt_range() else {
+            return Ok(());
+        };
+        if range.end > self.data.len() {
+            return ModcryptAreaOutOfBoundsSnafu { end: range.end, size: self.data.len() }.fail();
+        }
+        modcrypt.crypt(counter, &mut self.data.to_mut()[range]);
+        Ok(())
+    }
+}
+
+/// Errors related to [`DsiProgram`].
+#[derive(Debug, Snafu)]
+pub enum DsiProgramError {
+    /// Occurs when the modcrypt area extends past the end of the program.
+    #[snafu(display("modcrypt area ends at {end:#x} but the program is only {size:#x} bytes:\n{backtrace}"))]
+    ModcryptAreaOutOfBounds {
+        /// End of the modcrypt area.
+        end: usize,
+        /// Size of the program.
+        size: usize,
+        /// Backtrace to the source of the error.
+        backtrace: Backtrace,
+    },
+}
+
+/// The DSi area, located after the DS area in DSi-enhanced and DSi-exclusive ROMs.
+#[derive(Clone)]
+pub struct DsiArea<'a> {
+    reserved: Cow<'a, [u8]>,
+    arm9i: DsiProgram<'a>,
+    gap: Cow<'a, [u8]>,
+    arm7i: DsiProgram<'a>,
+    trailing: Cow<'a, [u8]>,
+}
+
+impl<'a> DsiArea<'a> {
+    /// Creates a new [`DsiArea`]. `reserved` is the data between the start of the DSi area and the ARM9i program.
+    pub fn new<T: Into<Cow<'a, [u8]>>>(reserved: T, arm9i: DsiProgram<'a>, arm7i: DsiProgram<'a>) -> Self {
+        Self { reserved: reserved.into(), arm9i, gap: Cow::Borrowed(&[]), arm7i, trailing: Cow::Borrowed(&[]) }
+    }
+
+    /// Sets the data between the end of the ARM9i program and the start of the ARM7i program.
+    pub fn with_gap<T: Into<Cow<'a, [u8]>>>(mut self, gap: T) -> Self {
+        self.gap = gap.into();
+        self
+    }
+
+    /// Sets the data between the end of the ARM7i program and the end of the DSi area.
+    pub fn with_trailing<T: Into<Cow<'a, [u8]>>>(mut self, trailing: T) -> Self {
+        self.trailing = trailing.into();
+        self
+    }
+
+    /// Returns a reference to the data between the start of the DSi area and the ARM9i program.
+    pub fn reserved(&self) -> &[u8] {
+        &self.reserved
+    }
+
+    /// Returns a reference to the ARM9i program.
+    pub fn arm9i(&self) -> &DsiProgram<'a> {
+        &self.arm9i
+    }
+
+    /// Returns a mutable reference to the ARM9i program.
+    pub fn arm9i_mut(&mut self) -> &mut DsiProgram<'a> {
+        &mut self.arm9i
+    }
+
+    /// Returns a reference to the data between the end of the ARM9i program and the start of the ARM7i program.
+    pub fn gap(&self) -> &[u8] {
+        &self.gap
+    }
+
+    /// Returns a reference to the ARM7i program.
+    pub fn arm7i(&self) -> &DsiProgram<'a> {
+        &self.arm7i
+    }
+
+    /// Returns a mutable reference to the ARM7i program.
+    pub fn arm7i_mut(&mut self) -> &mut DsiProgram<'a> {
+        &mut self.arm7i
+    }
+
+    /// Returns a reference to the data between the end of the ARM7i program and the end of the DSi area.
+    pub fn trailing(&self) -> &[u8] {
+        &self.trailing
+    }
+
+    /// De/encrypts the modcrypt areas of the ARM9i and ARM7i programs in place, with the key and counters of `header`. Does
+    /// nothing if `header` is not modcrypted.
+    ///
+    /// # Errors
+    ///
+    /// See [`DsiProgram::modcrypt`].
+    pub fn modcrypt(&mut self, header: &Header) -> Result<(), DsiProgramError> {
+        if !header.dsi_flags.modcrypted() {
+            return Ok(());
+        }
+        let modcrypt = Modcrypt::from_header(header);
+        self.arm9i.modcrypt(&modcrypt, Modcrypt::area_1_counter(header))?;
+        self.arm7i.modcrypt(&modcrypt, Modcrypt::area_2_counter(header))
+    }
+}
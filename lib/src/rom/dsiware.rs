@@ -0,0 +1,393 @@
+use std::{
+    backtrace::Backtrace,
+    borrow::Cow,
+    path::{Path, PathBuf},
+};
+
+use serde::{Deserialize, Serialize};
+use sha1::{Digest, Sha1};
+use snafu::Snafu;
+
+use super::{
+    raw::{self, RawHeaderError},
+    Rom, RomBuildError, RomExtractError, RomLoadOptions, RomSaveError,
+};
+use crate::{
+    crypto::blowfish::BlowfishKey,
+    io::{create_file_and_dirs, open_file, read_file, write_file, FileError},
+};
+
+/// Name of the DSiWare config file, saved next to `config.yaml`.
+pub const DSIWARE_CONFIG_FILE: &str = "dsiware.yaml";
+
+/// Size of one content record in the TMD.
+const CONTENT_RECORD_SIZE: usize = 0x24;
+
+/// Title metadata (TMD) of a DSiWare title. All values are big-endian. The signature is kept as-is, so it will no longer
+/// be valid after modifying the TMD.
+pub struct Tmd<'a> {
+    data: Cow<'a, [u8]>,
+    header_offset: usize,
+}
+
+/// A content record in the [`Tmd`].
+#[derive(Serialize, Deserialize, Clone, Copy, PartialEq, Eq)]
+pub struct TmdContent {
+    /// Content ID, also the file name of the content in the title directory.
+    pub id: u32,
+    /// Content index.
+    pub index: u16,
+    /// Content type.
+    pub content_type: u16,
+    /// Content size.
+    pub size: u64,
+    /// SHA1 hash of the decrypted content.
+    pub sha1: [u8; 0x14],
+}
+
+/// Errors related to [`Tmd`].
+#[derive(Debug, Snafu)]
+pub enum TmdError {
+    /// Occurs when the TMD has an unknown signature type.
+    #[snafu(display("unknown TMD signature type {signature_type:#010x}:\n{backtrace}"))]
+    UnknownSignatureType {
+        /// Signature type.
+        signature_type: u32,
+        /// Backtrace to the source of the error.
+        backtrace: Backtrace,
+    },
+    /// Occurs when the TMD is too small to contain its header or content records.
+    #[snafu(display("TMD must be at least {expected:#x} bytes but got {actual:#x} bytes:\n{backtrace}"))]
+    TooSmall {
+        /// Expected minimum size.
+        expected: usize,
+        /// Actual size.
+        actual: usize,
+        /// Backtrace to the source of the error.
+        backtrace: Backtrace,
+    },
+    /// Occurs when the number of contents differs from the TMD.
+    #[snafu(display("expected {expected} contents but got {actual}:\n{backtrace}"))]
+    ContentCountMismatch {
+        /// Number of contents in the TMD.
+        expected: usize,
+        /// Number of contents given.
+        actual: usize,
+        /// Backtrace to the source of the error.
+        backtrace: Backtrace,
+    },
+}
+
+impl<'a> Tmd<'a> {
+    const TITLE_ID: usize = 0x4c;
+    const TITLE_VERSION: usize = 0x9c;
+    const NUM_CONTENTS: usize = 0x9e;
+    const BOOT_INDEX: usize = 0xa0;
+    const CONTENTS: usize = 0xa4;
+
+    /// Parses a TMD from raw data. Any data after the content records, such as a certificate chain, is kept as-is.
+    ///
+    /// # Errors
+    ///
+    /// This function will return an error if the signature type is unknown, or if the data is too small.
+    pub fn new<T: Into<Cow<'a, [u8]>>>(data: T) -> Result<Self, TmdError> {
+        let data = data.into();
+        if data.len() < 4 {
+            return TooSmallSnafu { expected: 4usize, actual: data.len() }.fail();
+        }
+        let signature_type = u32::from_be_bytes([data[0], data[1], data[2], data[3]]);
+        // Signature type, signature and padding to a multiple of 0x40
+        let header_offset = match signature_type {
+            0x00010000 => 4 + 0x200 + 0x3c,
+            0x00010001 => 4 + 0x100 + 0x3c,
+            0x00010002 => 4 + 0x3c + 0x40,
+            _ => return UnknownSignatureTypeSnafu { signature_type }.fail(),
+        };
+        if data.len() < header_offset + Self::CONTENTS {
+            return TooSmallSnafu { expected: header_offset + Self::CONTENTS, actual: data.len() }.fail();
+        }
+
+        let tmd = Self { data, header_offset };
+        let expected = header_offset + Self::CONTENTS + tmd.num_contents() as usize * CONTENT_RECORD_SIZE;
+        if tmd.data.len() < expected {
+            return TooSmallSnafu { expected, actual: tmd.data.len() }.fail();
+        }
+        Ok(tmd)
+    }
+
+    fn field<const N: usize>(&self, offset: usize) -> [u8; N] {
+        let start = self.header_offset + offset;
+        self.data[start..start + N].try_into().unwrap()
+    }
+
+    fn set_field(&mut self, offset: usize, value: &[u8]) {
+        let start = self.header_offset + offset;
+        self.data.to_mut()[start..start + value.len()].copy_from_slice(value);
+    }
+
+    /// Returns the title ID.
+    pub fn title_id(&self) -> u64 {
+        u64::from_be_bytes(self.field(Self::TITLE_ID))
+    }
+
+    /// Sets the title ID.
+    pub fn set_title_id(&mut self, title_id: u64) {
+        self.set_field(Self::TITLE_ID, &title_id.to_be_bytes());
+    }
+
+    /// Returns the title version.
+    pub fn title_version(&self) -> u16 {
+        u16::from_be_bytes(self.field(Self::TITLE_VERSION))
+    }
+
+    /// Sets the title version.
+    pub fn set_title_version(&mut self, title_version: u16) {
+        self.set_field(Self::TITLE_VERSION, &title_version.to_be_bytes());
+    }
+
+    /// Returns the number of content records.
+    pub fn num_contents(&self) -> u16 {
+        u16::from_be_bytes(self.field(Self::NUM_CONTENTS))
+    }
+
+    /// Returns the index of the boot content, i.e. the SRL.
+    pub fn boot_index(&self) -> u16 {
+        u16::from_be_bytes(self.field(Self::BOOT_INDEX))
+    }
+
+    /// Returns the content records.
+    pub fn contents(&self) -> Vec<TmdContent> {
+        (0..self.num_contents() as usize)
+            .map(|i| {
+                let offset = Self::CONTENTS + i * CONTENT_RECORD_SIZE;
+                TmdContent {
+                    id: u32::from_be_bytes(self.field(offset)),
+                    index: u16::from_be_bytes(self.field(offset + 0x4)),
+                    content_type: u16::from_be_bytes(self.field(offset + 0x6)),
+                    size: u64::from_be_bytes(self.field(offset + 0x8)),
+                    sha1: self.field(offset + 0x10),
+                }
+            })
+            .collect()
+    }
+
+    /// Replaces the content records.
+    ///
+    /// # Errors
+    ///
+    /// This function will return an error if the number of contents differs from [`Self::num_contents`].
+    pub fn set_contents(&mut self, contents: &[TmdContent]) -> Result<(), TmdError> {
+        if contents.len() != self.num_contents() as usize {
+            return ContentCountMismatchSnafu { expected: self.num_contents() as usize, actual: contents.len() }.fail();
+        }
+        for (i, content) in contents.iter().enumerate() {
+            let offset = Self::CONTENTS + i * CONTENT_RECORD_SIZE;
+            self.set_field(offset, &content.id.to_be_bytes());
+            self.set_field(offset + 0x4, &content.index.to_be_bytes());
+            self.set_field(offset + 0x6, &content.content_type.to_be_bytes());
+            self.set_field(offset + 0x8, &content.size.to_be_bytes());
+            self.set_field(offset + 0x10, &content.sha1);
+        }
+        Ok(())
+    }
+
+    /// Returns a reference to the full data of this [`Tmd`].
+    pub fn full_data(&self) -> &[u8] {
+        &self.data
+    }
+}
+
+/// Config file for DSiWare titles, saved as [`DSIWARE_CONFIG_FILE`] next to the ROM config.
+#[derive(Serialize, Deserialize, Clone)]
+pub struct DsiWareConfig {
+    /// Path to the original TMD, relative to the config.
+    pub tmd: PathBuf,
+    /// Title ID.
+    pub title_id: u64,
+    /// Title version.
+    pub title_version: u16,
+    /// Content records. The size and hash of the boot content are updated when building.
+    pub contents: Vec<TmdContent>,
+}
+
+/// A DSiWare title, consisting of an SRL and its TMD.
+pub struct DsiWare<'a> {
+    rom: Rom<'a>,
+    tmd: Tmd<'a>,
+    config: DsiWareConfig,
+}
+
+/// Errors related to [`DsiWare::extract`].
+#[derive(Debug, Snafu)]
+pub enum DsiWareExtractError {
+    /// See [`RomExtractError`].
+    #[snafu(transparent)]
+    RomExtract {
+        /// Source error.
+        source: RomExtractError,
+    },
+    /// See [`TmdError`].
+    #[snafu(transparent)]
+    Tmd {
+        /// Source error.
+        source: TmdError,
+    },
+}
+
+/// Errors related to [`DsiWare::save`] and [`DsiWare::load`].
+#[derive(Debug, Snafu)]
+pub enum DsiWareSaveError {
+    /// See [`RomSaveError`].
+    #[snafu(transparent)]
+    RomSave {
+        /// Source error.
+        source: RomSaveError,
+    },
+    /// See [`TmdError`].
+    #[snafu(transparent)]
+    Tmd {
+        /// Source error.
+        source: TmdError,
+    },
+    /// See [`FileError`].
+    #[snafu(transparent)]
+    File {
+        /// Source error.
+        source: FileError,
+    },
+    /// See [`serde_yml::Error`].
+    #[snafu(transparent)]
+    SerdeYml {
+        /// Source error.
+        source: serde_yml::Error,
+    },
+}
+
+/// Errors related to [`DsiWare::build`].
+#[derive(Debug, Snafu)]
+pub enum DsiWareBuildError {
+    /// See [`RomBuildError`].
+    #[snafu(transparent)]
+    RomBuild {
+        /// Source error.
+        source: RomBuildError,
+    },
+    /// See [`TmdError`].
+    #[snafu(transparent)]
+    Tmd {
+        /// Source error.
+        source: TmdError,
+    },
+    /// See [`RawHeaderError`].
+    #[snafu(transparent)]
+    RawHeader {
+        /// Source error.
+        source: RawHeaderError,
+    },
+}
+
+impl<'a> DsiWare<'a> {
+    /// Extracts a DSiWare title from its SRL and TMD.
+    ///
+    /// # Errors
+    ///
+    /// See [`Rom::extract`] and [`Tmd::new`].
+    pub fn extract(srl: &'a raw::Rom, tmd: &'a [u8]) -> Result<Self, DsiWareExtractError> {
+        let rom = Rom::extract(srl)?;
+        let tmd = Tmd::new(tmd)?;
+        let config = DsiWareConfig {
+            tmd: "tmd.bin".into(),
+            title_id: tmd.title_id(),
+            title_version: tmd.title_version(),
+            contents: tmd.contents(),
+        };
+        Ok(Self { rom, tmd, config })
+    }
+
+    /// Saves this title to a path as separate files. The SRL is saved by [`Rom::save`], and the TMD and
+    /// [`DSIWARE_CONFIG_FILE`] are saved next to `config.yaml`.
+    ///
+    /// # Errors
+    ///
+    /// See [`Rom::save`]. This function will also return an error if the TMD or config could not be saved.
+    pub fn save<P: AsRef<Path>>(&self, path: P, key: Option<&BlowfishKey>) -> Result<(), DsiWareSaveError> {
+        let path = path.as_ref();
+        self.rom.save(path, key)?;
+
+        write_file(path.join(&self.config.tmd), self.tmd.full_data())?;
+        serde_yml::to_writer(create_file_and_dirs(path.join(DSIWARE_CONFIG_FILE))?, &self.config)?;
+
+        Ok(())
+    }
+
+    /// Loads a DSiWare title from the `config.yaml` generated by [`Self::save`].
+    ///
+    /// # Errors
+    ///
+    /// See [`Rom::load`]. This function will also return an error if the TMD or config could not be loaded.
+    pub fn load<P: AsRef<Path>>(config_path: P, options: RomLoadOptions) -> Result<Self, DsiWareSaveError> {
+        let config_path = config_path.as_ref();
+        let path = config_path.parent().unwrap();
+
+        let config: DsiWareConfig = serde_yml::from_reader(open_file(path.join(DSIWARE_CONFIG_FILE))?)?;
+        let tmd = Tmd::new(read_file(path.join(&config.tmd))?)?;
+        let rom = Rom::load(config_path, options)?;
+
+        Ok(Self { rom, tmd, config })
+    }
+
+    /// Builds the SRL and TMD of this title. The title ID, title version and contents are taken from the config, except for
+    /// the size and SHA1 hash of the boot content, which are computed from the built SRL.
+    ///
+    /// Unlike cartridge ROMs, the SRL is not padded to a power of two. It is trimmed to the ROM size in its header, or to the
+    /// original content size if that is larger, so an unmodified title builds to the original SRL.
+    ///
+    /// # Errors
+    ///
+    /// See [`Rom::build`] and [`Tmd::set_contents`].
+    pub fn build(self, key: Option<&BlowfishKey>) -> Result<(raw::Rom<'a>, Tmd<'a>), DsiWareBuildError> {
+        let srl = self.rom.build(key)?;
+
+        let mut tmd = self.tmd;
+        tmd.set_title_id(self.config.title_id);
+        tmd.set_title_version(self.config.title_version);
+
+        let boot_index = tmd.boot_index();
+        let mut contents = self.config.contents;
+        let boot_content = contents.iter_mut().find(|content| content.index == boot_index);
+
+        let header = srl.header()?;
+        let rom_size = if header.rom_size_dsi != 0 { header.rom_size_dsi } else { header.rom_size_ds } as usize;
+        let original_size = boot_content.as_ref().map_or(0, |content| content.size as usize);
+        let srl_size = rom_size.max(original_size).min(srl.data().len());
+        let srl = raw::Rom::new(srl.data()[..srl_size].to_vec());
+
+        if let Some(boot_content) = boot_content {
+            boot_content.size = srl.data().len() as u64;
+            boot_content.sha1 = Sha1::digest(srl.data()).into();
+        }
+        tmd.set_contents(&contents)?;
+
+        Ok((srl, tmd))
+    }
+
+    /// Returns a reference to the SRL of this title.
+    pub fn rom(&self) -> &Rom<'a> {
+        &self.rom
+    }
+
+    /// Returns a mutable reference to the SRL of this title.
+    pub fn rom_mut(&mut self) -> &mut Rom<'a> {
+        &mut self.rom
+    }
+
+    /// Returns a reference to the TMD of this title.
+    pub fn tmd(&self) -> &Tmd<'a> {
+        &self.tmd
+    }
+
+    /// Returns a reference to the config of this title.
+    pub fn config(&self) -> &DsiWareConfig {
+        &self.config
+    }
+}
@@ -1,19 +1,24 @@
 use std::{
     borrow::Cow,
     cmp::Ordering,
-    collections::{BinaryHeap, HashSet},
+    collections::{BinaryHeap, HashMap, HashSet},
     fmt::Display,
     io::Write,
     path::{Path, PathBuf},
-    str::FromStr,
 };
 
 use encoding_rs::SHIFT_JIS;
+use serde::{Deserialize, Serialize};
+use sha1::{Digest, Sha1};
 use snafu::{Backtrace, Snafu};
 
-use super::raw::{self, FileAlloc, Fnt, FntDirectory, FntFile, FntSubtable, RawHeaderError};
+use super::{
+    narc::{Narc, NarcBuildError, NarcConfig},
+    raw::{self, FileAlloc, Fnt, FntDirectory, FntFile, FntSubtable, RawHeaderError},
+};
 use crate::{
-    io::{read_dir, read_file, FileError},
+    compress::bios::{BiosCompressError, BiosCompressionType, BiosHeader},
+    io::{create_dir_all, create_file_and_dirs, open_file, read_dir, read_file, remove_file, write_file, FileError},
     str::BlobSize,
 };
 
@@ -24,6 +29,7 @@ pub struct FileSystem<'a> {
     dirs: Vec<Dir>,
     next_file_id: u16,
     next_dir_id: u16,
+    pinned_ids: bool,
 }
 
 /// A file for the [`FileSystem`] struct.
@@ -55,6 +61,50 @@ pub enum FileParseError {
         /// Backtrace to the source of the error.
         backtrace: Backtrace,
     },
+    /// Occurs when the FNT contains a file ID which is not in the FAT.
+    #[snafu(display("the file ID {id} is out of bounds for the FAT with {num_files} files:\n{backtrace}"))]
+    FileIdOutOfBounds {
+        /// File ID.
+        id: u16,
+        /// Number of files in the FAT.
+        num_files: usize,
+        /// Backtrace to the source of the error.
+        backtrace: Backtrace,
+    },
+    /// Occurs when the FNT contains a directory ID which has no subtable.
+    #[snafu(display("the directory ID {id:#x} is out of bounds for the FNT with {num_dirs} directories:\n{backtrace}"))]
+    DirIdOutOfBounds {
+        /// Directory ID.
+        id: u16,
+        /// Number of directories in the FNT.
+        num_dirs: usize,
+        /// Backtrace to the source of the error.
+        backtrace: Backtrace,
+    },
+    /// Occurs when a directory ID appears more than once in the FNT.
+    #[snafu(display("the directory ID {id:#x} appears more than once in the FNT:\n{backtrace}"))]
+    DuplicateDirId {
+        /// Directory ID.
+        id: u16,
+        /// Backtrace to the source of the error.
+        backtrace: Backtrace,
+    },
+    /// Occurs when a file in the FAT ends before it starts, or ends outside of the file data.
+    #[snafu(display(
+        "the file ID {id} at {start:#x}..{end:#x} is out of bounds for {size:#x} bytes of file data:\n{backtrace}"
+    ))]
+    FileOutOfBounds {
+        /// File ID.
+        id: u16,
+        /// Start offset.
+        start: u32,
+        /// End offset.
+        end: u32,
+        /// Size of the file data.
+        size: usize,
+        /// Backtrace to the source of the error.
+        backtrace: Backtrace,
+    },
     /// Occurs when a directory ID is missing from the faw FNT.
     #[snafu(display("the directory ID {id} is missing from the FNT:\n{backtrace}"))]
     MissingDirId {
@@ -84,31 +134,330 @@ pub enum FileBuildError {
     },
 }
 
+/// Manifest of the files which [`FileSystem::save_decompressed`] saved decompressed, so that
+/// [`FileSystem::load_decompressed`] can compress them again.
+#[derive(Serialize, Deserialize, Default)]
+pub struct FileCompressionManifest {
+    /// Directory with the original compressed files which can't be reproduced by recompressing them, relative to the
+    /// manifest.
+    pub originals_dir: PathBuf,
+    /// Files which were saved decompressed.
+    pub files: Vec<CompressedFile>,
+}
+
+/// A file in the [`FileCompressionManifest`].
+#[derive(Serialize, Deserialize)]
+pub struct CompressedFile {
+    /// Path to the file, relative to the root directory.
+    pub path: String,
+    /// Original compression format.
+    pub format: BiosCompressionType,
+    /// SHA1 hash of the decompressed contents, used to tell whether the file has been modified.
+    pub sha1: String,
+    /// If true, recompressing the file didn't reproduce the original, so the original was saved in
+    /// [`FileCompressionManifest::originals_dir`] to be used as long as the file is unmodified.
+    #[serde(skip_serializing_if = "std::ops::Not::not", default)]
+    pub original: bool,
+    /// If true, the file is compressed with [`BiosCompressionType::compress_vram_safe`], so that it can still be decompressed
+    /// into VRAM.
+    #[serde(skip_serializing_if = "std::ops::Not::not", default)]
+    pub vram_safe: bool,
+}
+
+/// Errors related to [`FileSystem::save_decompressed`] and [`FileSystem::load_decompressed`].
+#[derive(Debug, Snafu)]
+pub enum FileCompressionError {
+    /// See [`FileError`].
+    #[snafu(transparent)]
+    File {
+        /// Source error.
+        source: FileError,
+    },
+    /// See [`std::io::Error`].
+    #[snafu(transparent)]
+    Io {
+        /// Source error.
+        source: std::io::Error,
+    },
+    /// See [`serde_yml::Error`].
+    #[snafu(transparent)]
+    SerdeYml {
+        /// Source error.
+        source: serde_yml::Error,
+    },
+    /// See [`BiosCompressError`].
+    #[snafu(transparent)]
+    BiosCompress {
+        /// Source error.
+        source: BiosCompressError,
+    },
+    /// Occurs when a file in the manifest doesn't exist in the file system.
+    #[snafu(display("compressed file {path} in the manifest was not found:\n{backtrace}"))]
+    MissingCompressedFile {
+        /// Path to the file.
+        path: String,
+        /// Backtrace to the source of the error.
+        backtrace: Backtrace,
+    },
+}
+
+/// Manifest of the NARC files which [`FileSystem::save_narcs`] expanded into folders, so that [`FileSystem::load_narcs`]
+/// can pack them again.
+#[derive(Serialize, Deserialize, Default)]
+pub struct NarcManifest {
+    /// NARC files which were expanded into folders.
+    pub narcs: Vec<ExpandedNarc>,
+}
+
+/// A NARC file in the [`NarcManifest`].
+#[derive(Serialize, Deserialize)]
+pub struct ExpandedNarc {
+    /// Path to the NARC file, relative to the root directory. The folder it was expanded into has the same path.
+    pub path: String,
+    /// Config to rebuild the NARC with.
+    #[serde(flatten)]
+    pub config: NarcConfig,
+}
+
+/// Errors related to [`FileSystem::save_narcs`] and [`FileSystem::load_narcs`].
+#[derive(Debug, Snafu)]
+pub enum FileNarcError {
+    /// See [`FileError`].
+    #[snafu(transparent)]
+    File {
+        /// Source error.
+        source: FileError,
+    },
+    /// See [`serde_yml::Error`].
+    #[snafu(transparent)]
+    SerdeYml {
+        /// Source error.
+        source: serde_yml::Error,
+    },
+    /// See [`NarcBuildError`].
+    #[snafu(transparent)]
+    NarcBuild {
+        /// Source error.
+        source: NarcBuildError,
+    },
+}
+
+/// Errors related to editing a [`FileSystem`], such as [`FileSystem::insert_file`] and [`FileSystem::move_path`].
+#[derive(Debug, Snafu)]
+pub enum FileEditError {
+    /// Occurs when a path doesn't exist in the file system.
+    #[snafu(display("the path '{path}' was not found:\n{backtrace}"))]
+    PathNotFound {
+        /// Path.
+        path: String,
+        /// Backtrace to the source of the error.
+        backtrace: Backtrace,
+    },
+    /// Occurs when creating a path which already exists.
+    #[snafu(display("the path '{path}' already exists:\n{backtrace}"))]
+    PathExists {
+        /// Path.
+        path: String,
+        /// Backtrace to the source of the error.
+        backtrace: Backtrace,
+    },
+    /// Occurs when a directory was expected but the path is a file.
+    #[snafu(display("the path '{path}' is not a directory:\n{backtrace}"))]
+    NotADir {
+        /// Path.
+        path: String,
+        /// Backtrace to the source of the error.
+        backtrace: Backtrace,
+    },
+    /// Occurs when a file was expected but the path is a directory.
+    #[snafu(display("the path '{path}' is not a file:\n{backtrace}"))]
+    NotAFile {
+        /// Path.
+        path: String,
+        /// Backtrace to the source of the error.
+        backtrace: Backtrace,
+    },
+    /// Occurs when a name is empty, contains a slash or can't be encoded to a Shift-JIS string of at most 127 bytes.
+    #[snafu(display("the name '{name}' can't be stored in the FNT:\n{backtrace}"))]
+    InvalidName {
+        /// File or directory name.
+        name: String,
+        /// Backtrace to the source of the error.
+        backtrace: Backtrace,
+    },
+    /// Occurs when adding, removing or moving files and directories after [`FileSystem::pin_file_ids`], since the IDs
+    /// would be reassigned.
+    #[snafu(display(
+        "can't add, remove or move files and directories while file IDs are pinned, edit the extracted files instead:\n{backtrace}"
+    ))]
+    PinnedFileIds {
+        /// Backtrace to the source of the error.
+        backtrace: Backtrace,
+    },
+    /// Occurs when trying to remove or move the root directory.
+    #[snafu(display("the root directory can't be removed or moved:\n{backtrace}"))]
+    RootDir {
+        /// Backtrace to the source of the error.
+        backtrace: Backtrace,
+    },
+    /// Occurs when moving a directory into itself or one of its subdirectories.
+    #[snafu(display("can't move '{from}' into itself at '{to}':\n{backtrace}"))]
+    MoveIntoItself {
+        /// Path of the directory.
+        from: String,
+        /// Destination path.
+        to: String,
+        /// Backtrace to the source of the error.
+        backtrace: Backtrace,
+    },
+    /// Occurs when inserting a file would run out of file IDs.
+    #[snafu(display("the file system can't have more than {max} files:\n{backtrace}"))]
+    TooManyFiles {
+        /// Maximum number of files.
+        max: usize,
+        /// Backtrace to the source of the error.
+        backtrace: Backtrace,
+    },
+    /// Occurs when creating a directory would run out of directory IDs.
+    #[snafu(display("the file system can't have more than {max} directories:\n{backtrace}"))]
+    TooManyDirs {
+        /// Maximum number of directories.
+        max: usize,
+        /// Backtrace to the source of the error.
+        backtrace: Backtrace,
+    },
+}
+
+/// Manifest of file IDs written by [`FileSystem::save_file_ids`], so that [`FileSystem::pin_file_ids`] can restore them after
+/// files have been added.
+#[derive(Serialize, Deserialize, Default)]
+pub struct FileIdManifest {
+    /// Files and their IDs, in ID order.
+    pub files: Vec<PinnedFileId>,
+}
+
+/// A file in the [`FileIdManifest`].
+#[derive(Serialize, Deserialize)]
+pub struct PinnedFileId {
+    /// Path to the file, relative to the root directory.
+    pub path: String,
+    /// File ID.
+    pub id: u16,
+}
+
+/// Errors related to [`FileSystem::save_file_ids`] and [`FileSystem::pin_file_ids`].
+#[derive(Debug, Snafu)]
+pub enum FileIdError {
+    /// See [`FileError`].
+    #[snafu(transparent)]
+    File {
+        /// Source error.
+        source: FileError,
+    },
+    /// See [`serde_yml::Error`].
+    #[snafu(transparent)]
+    SerdeYml {
+        /// Source error.
+        source: serde_yml::Error,
+    },
+    /// Occurs when a pinned file ID is an overlay or directory ID.
+    #[snafu(display("the file '{path}' is pinned to the invalid file ID {id}:\n{backtrace}"))]
+    InvalidFileId {
+        /// Path to the file.
+        path: String,
+        /// File ID.
+        id: u16,
+        /// Backtrace to the source of the error.
+        backtrace: Backtrace,
+    },
+    /// Occurs when two files are pinned to the same ID.
+    #[snafu(display("the file '{path}' is pinned to the file ID {id} which is already in use:\n{backtrace}"))]
+    DuplicateFileId {
+        /// Path to the file.
+        path: String,
+        /// File ID.
+        id: u16,
+        /// Backtrace to the source of the error.
+        backtrace: Backtrace,
+    },
+    /// Occurs when no file has a certain ID, for example because a pinned file was removed.
+    #[snafu(display(
+        "no file has the file ID {id}, a removed file must be replaced to keep the following IDs:\n{backtrace}"
+    ))]
+    UnusedFileId {
+        /// File ID.
+        id: u16,
+        /// Backtrace to the source of the error.
+        backtrace: Backtrace,
+    },
+    /// Occurs when the files in a directory would not have consecutive IDs, which the FNT requires.
+    #[snafu(display(
+        "the file '{path}' would get the ID {id} instead of {expected}, since the FNT requires consecutive IDs within a \
+        directory. New files must be added to new directories or to the directory with the highest file ID:\n{backtrace}"
+    ))]
+    NonConsecutiveFileIds {
+        /// Path to the file.
+        path: String,
+        /// File ID.
+        id: u16,
+        /// File ID which the FNT requires.
+        expected: u16,
+        /// Backtrace to the source of the error.
+        backtrace: Backtrace,
+    },
+}
+
 const ROOT_DIR_ID: u16 = 0xf000;
+/// Directories are indexed by the lower 12 bits of their IDs.
+const MAX_DIR_INDEX: usize = 0xfff;
+/// Default directory for [`FileCompressionManifest::originals_dir`].
+const ORIGINALS_DIR: &str = "files_original/";
 
 impl<'a> FileSystem<'a> {
     /// Creates a new [`FileSystem`]. The number of overlays are used to determine the first file ID, since overlays are also
     /// located in the FAT but not the FNT.
     pub fn new(num_overlays: usize) -> Self {
         let root = Dir { id: ROOT_DIR_ID, name: "/".to_string(), parent_id: 0, children: vec![] };
-        Self { num_overlays, files: vec![], dirs: vec![root], next_file_id: num_overlays as u16, next_dir_id: ROOT_DIR_ID + 1 }
+        Self {
+            num_overlays,
+            files: vec![],
+            dirs: vec![root],
+            next_file_id: num_overlays as u16,
+            next_dir_id: ROOT_DIR_ID + 1,
+            pinned_ids: false,
+        }
     }
 
-    fn load_in<P: AsRef<Path>>(&mut self, path: P, parent_id: u16) -> Result<(), FileError> {
+    fn load_in<P: AsRef<Path>>(
+        &mut self,
+        path: P,
+        parent_id: u16,
+        dir_path: &Path,
+        packed: &mut HashMap<String, Box<[u8]>>,
+    ) -> Result<(), FileError> {
+        // Folders of expanded NARCs are files in the FNT
+        let is_dir = |child: &Path| {
+            child.is_dir()
+                && !packed.contains_key(&Self::manifest_path(dir_path, &child.file_name().unwrap().to_string_lossy()))
+        };
+
         // Sort children by FNT order so the file/dir IDs become correct
         let mut children =
             read_dir(&path)?.collect::<Result<Vec<_>, _>>()?.into_iter().map(|entry| entry.path()).collect::<Vec<_>>();
         children.sort_unstable_by(|a, b| {
-            Self::compare_for_fnt(a.to_string_lossy().as_ref(), a.is_dir(), b.to_string_lossy().as_ref(), b.is_dir())
+            Self::compare_for_fnt(a.to_string_lossy().as_ref(), is_dir(a), b.to_string_lossy().as_ref(), is_dir(b))
         });
 
         for child in children.into_iter() {
             let name = child.file_name().unwrap().to_string_lossy().to_string();
-            if child.is_dir() {
+            if let Some(contents) = packed.remove(&Self::manifest_path(dir_path, &name)) {
+                self.make_child_file(name, parent_id, contents.into_vec());
+            } else if child.is_dir() {
                 let child_id = self.next_dir_id;
                 let child_path = path.as_ref().join(&name);
+                let child_dir_path = dir_path.join(&name);
                 self.make_child_dir(name, parent_id);
-                self.load_in(child_path, child_id)?;
+                self.load_in(child_path, child_id, &child_dir_path, packed)?;
             } else {
                 let contents = read_file(child)?;
                 self.make_child_file(name, parent_id, contents);
@@ -125,10 +474,320 @@ impl<'a> FileSystem<'a> {
     /// This function will return an error if an I/O operation fails.
     pub fn load<P: AsRef<Path>>(root: P, num_overlays: usize) -> Result<Self, FileError> {
         let mut files = Self::new(num_overlays);
-        files.load_in(root, ROOT_DIR_ID)?;
+        files.load_in(root, ROOT_DIR_ID, Path::new(""), &mut HashMap::new())?;
+        Ok(files)
+    }
+
+    /// Saves all files into the given root directory.
+    ///
+    /// # Errors
+    ///
+    /// This function will return an error if an I/O operation fails.
+    pub fn save<P: AsRef<Path>>(&self, root: P) -> Result<(), FileError> {
+        let root = root.as_ref();
+        for (file, path) in self.traverse_files(["/"]) {
+            Self::save_file(&root.join(path), file.name(), file.contents())?;
+        }
+        Ok(())
+    }
+
+    fn save_file(dir: &Path, name: &str, contents: &[u8]) -> Result<(), FileError> {
+        create_dir_all(dir)?;
+        write_file(dir.join(name), contents)
+    }
+
+    /// Saves all files into the given root directory like [`Self::save`], except that files compressed with a BIOS
+    /// compression format are saved decompressed. Their formats are recorded in a [`FileCompressionManifest`] at
+    /// `manifest_path`, which [`Self::load_decompressed`] uses to compress them again.
+    ///
+    /// # Errors
+    ///
+    /// This function will return an error if an I/O operation fails or the manifest could not be serialized.
+    pub fn save_decompressed<P: AsRef<Path>, M: AsRef<Path>>(
+        &self,
+        root: P,
+        manifest_path: M,
+    ) -> Result<(), FileCompressionError> {
+        let root = root.as_ref();
+        let manifest_path = manifest_path.as_ref();
+        let manifest_dir = manifest_path.parent().unwrap();
+        let mut manifest = FileCompressionManifest { originals_dir: ORIGINALS_DIR.into(), files: vec![] };
+
+        for (file, path) in self.traverse_files(["/"]) {
+            let dir = root.join(&path);
+            let Some((format, decompressed)) = Self::detect_compression(file.contents()) else {
+                Self::save_file(&dir, file.name(), file.contents())?;
+                continue;
+            };
+
+            let file_path = Self::manifest_path(&path, file.name());
+            // Files which are decompressed into VRAM have no LZ pairs of distance 1, so they are only reproduced in VRAM-safe
+            // mode. Files which aren't reproduced either way are also compressed in VRAM-safe mode, in case they are used so.
+            let vram_safe = format.has_vram_safe_mode() && format.compress(&decompressed)?.as_ref() != file.contents();
+            let original = if vram_safe {
+                format.compress_vram_safe(&decompressed)?.as_ref() != file.contents()
+            } else {
+                format.compress(&decompressed)?.as_ref() != file.contents()
+            };
+            if original {
+                let original_path = manifest_dir.join(&manifest.originals_dir).join(&file_path);
+                create_file_and_dirs(original_path)?.write_all(file.contents())?;
+            }
+            Self::save_file(&dir, file.name(), &decompressed)?;
+            manifest.files.push(CompressedFile {
+                path: file_path,
+                format,
+                sha1: Self::sha1_hex(&decompressed),
+                original,
+                vram_safe,
+            });
+        }
+
+        serde_yml::to_writer(create_file_and_dirs(manifest_path)?, &manifest)?;
+        Ok(())
+    }
+
+    /// Returns the BIOS compression format of `contents` and its decompressed data, or `None` if it's not compressed.
+    fn detect_compression(contents: &[u8]) -> Option<(BiosCompressionType, Box<[u8]>)> {
+        let header = BiosHeader::parse(contents)?;
+        let (decompressed, end) = header.kind.decompress_stream(contents).ok()?;
+        // Raw files can start with a valid header by chance, so the compressed stream must fill the file exactly
+        if contents.len() != end.next_multiple_of(4) {
+            return None;
+        }
+        Some((header.kind, decompressed))
+    }
+
+    pub(crate) fn manifest_path(dir: &Path, name: &str) -> String {
+        dir.components().map(|c| c.as_os_str().to_string_lossy()).chain([name.into()]).collect::<Vec<_>>().join("/")
+    }
+
+    fn sha1_hex(data: &[u8]) -> String {
+        Sha1::digest(data).iter().map(|byte| format!("{byte:02x}")).collect()
+    }
+
+    /// Loads a file system like [`Self::load`], and then compresses the files listed in the [`FileCompressionManifest`] at
+    /// `manifest_path`. Files which are unmodified since [`Self::save_decompressed`] are restored to their original
+    /// compressed contents.
+    ///
+    /// # Errors
+    ///
+    /// This function will return an error if an I/O operation fails, the manifest could not be parsed or a file in the
+    /// manifest is missing.
+    pub fn load_decompressed<P: AsRef<Path>, M: AsRef<Path>>(
+        root: P,
+        manifest_path: M,
+        num_overlays: usize,
+    ) -> Result<Self, FileCompressionError> {
+        let mut files = Self::load(root, num_overlays)?;
+        files.compress_from_manifest(manifest_path)?;
+        Ok(files)
+    }
+
+    /// Compresses the files listed in the [`FileCompressionManifest`] at `manifest_path`, see [`Self::load_decompressed`].
+    ///
+    /// # Errors
+    ///
+    /// This function will return an error if an I/O operation fails, the manifest could not be parsed or a file in the
+    /// manifest is missing.
+    pub fn compress_from_manifest<M: AsRef<Path>>(&mut self, manifest_path: M) -> Result<(), FileCompressionError> {
+        let manifest_path = manifest_path.as_ref();
+        let manifest_dir = manifest_path.parent().unwrap();
+        let manifest: FileCompressionManifest = serde_yml::from_reader(open_file(manifest_path)?)?;
+
+        for entry in manifest.files {
+            let Some(id) = self.find_path(&entry.path).filter(|&id| Self::is_file(id)) else {
+                return MissingCompressedFileSnafu { path: entry.path }.fail();
+            };
+            let file = self.file_mut(id);
+            let contents = if entry.original && Self::sha1_hex(file.contents()) == entry.sha1 {
+                read_file(manifest_dir.join(&manifest.originals_dir).join(&entry.path))?
+            } else if entry.vram_safe {
+                entry.format.compress_vram_safe(file.contents())?.into_vec()
+            } else {
+                entry.format.compress(file.contents())?.into_vec()
+            };
+            file.contents = contents.into();
+        }
+        Ok(())
+    }
+
+    /// Expands the NARC files saved by [`Self::save`] or [`Self::save_decompressed`] into folders of the same name, and
+    /// records them in a [`NarcManifest`] at `manifest_path` which [`Self::load_narcs`] uses to pack them again. Only files
+    /// with the `.narc` extension which [`Narc::build`] reproduces exactly are expanded, the rest are left as they are.
+    ///
+    /// # Errors
+    ///
+    /// This function will return an error if an I/O operation fails or the manifest could not be serialized.
+    pub fn save_narcs<P: AsRef<Path>, M: AsRef<Path>>(&self, root: P, manifest_path: M) -> Result<(), FileNarcError> {
+        let root = root.as_ref();
+        let mut manifest = NarcManifest::default();
+
+        for (file, path) in self.traverse_files(["/"]) {
+            if !Self::has_narc_extension(file.name()) {
+                continue;
+            }
+            if !Narc::is_narc(file.contents()) {
+                // Likely a compressed NARC
+                continue;
+            }
+            let file_path = Self::manifest_path(&path, file.name());
+            let narc = match Narc::parse(file.contents()) {
+                Ok(narc) => narc,
+                Err(e) => {
+                    log::warn!("Not expanding {file_path}, failed to parse NARC: {e}");
+                    continue;
+                }
+            };
+            if narc.build()?.as_ref() != file.contents() {
+                log::warn!("Not expanding {file_path}, rebuilding it would not reproduce the original");
+                continue;
+            }
+            let narc_path = root.join(path).join(file.name());
+            remove_file(&narc_path)?;
+            narc.save(narc_path)?;
+            manifest.narcs.push(ExpandedNarc { path: file_path, config: narc.config().clone() });
+        }
+
+        serde_yml::to_writer(create_file_and_dirs(manifest_path)?, &manifest)?;
+        Ok(())
+    }
+
+    fn has_narc_extension(name: &str) -> bool {
+        Path::new(name).extension().is_some_and(|ext| ext.eq_ignore_ascii_case("narc"))
+    }
+
+    /// Loads a file system like [`Self::load`], except that the folders listed in the [`NarcManifest`] at `manifest_path` are
+    /// packed into NARC files again.
+    ///
+    /// # Errors
+    ///
+    /// This function will return an error if an I/O operation fails, the manifest could not be parsed or a NARC could not be
+    /// built.
+    pub fn load_narcs<P: AsRef<Path>, M: AsRef<Path>>(
+        root: P,
+        manifest_path: M,
+        num_overlays: usize,
+    ) -> Result<Self, FileNarcError> {
+        let root = root.as_ref();
+        let manifest: NarcManifest = serde_yml::from_reader(open_file(manifest_path)?)?;
+
+        let mut packed = HashMap::new();
+        for entry in manifest.narcs {
+            let narc = Narc::load(root.join(&entry.path), entry.config)?;
+            packed.insert(entry.path, narc.build()?);
+        }
+
+        let mut files = Self::new(num_overlays);
+        files.load_in(root, ROOT_DIR_ID, Path::new(""), &mut packed)?;
         Ok(files)
     }
 
+    /// Saves the ID of every file into a [`FileIdManifest`] at `manifest_path`, which [`Self::pin_file_ids`] uses to give
+    /// the files the same IDs again.
+    ///
+    /// # Errors
+    ///
+    /// This function will return an error if an I/O operation fails or the manifest could not be serialized.
+    pub fn save_file_ids<M: AsRef<Path>>(&self, manifest_path: M) -> Result<(), FileIdError> {
+        let mut manifest = FileIdManifest::default();
+        for (file, path) in self.traverse_files(["/"]) {
+            manifest.files.push(PinnedFileId { path: Self::manifest_path(&path, file.name()), id: file.id() });
+        }
+        manifest.files.sort_unstable_by_key(|file| file.id);
+
+        serde_yml::to_writer(create_file_and_dirs(manifest_path)?, &manifest)?;
+        Ok(())
+    }
+
+    /// Reassigns file IDs according to the [`FileIdManifest`] at `manifest_path`. Files which are not in the manifest are
+    /// new, and get the IDs following the highest pinned ID in FNT order.
+    ///
+    /// Since the FNT requires the files in each directory to have consecutive IDs, new files can only be added to new
+    /// directories or to the directory with the highest pinned ID. Likewise, only the files with the highest IDs can be
+    /// removed.
+    ///
+    /// Afterwards, [`Self::insert_file`], [`Self::create_dir`], [`Self::remove`] and [`Self::move_path`] fail with
+    /// [`FileEditError::PinnedFileIds`], since they would reassign all IDs. Such edits should be made to the extracted files
+    /// before loading them.
+    ///
+    /// # Errors
+    ///
+    /// This function will return an error if an I/O operation fails, the manifest could not be parsed, or if the IDs would
+    /// be invalid.
+    pub fn pin_file_ids<M: AsRef<Path>>(&mut self, manifest_path: M) -> Result<(), FileIdError> {
+        let manifest: FileIdManifest = serde_yml::from_reader(open_file(manifest_path)?)?;
+        let pinned = manifest.files.into_iter().map(|file| (file.path, file.id)).collect::<HashMap<_, _>>();
+
+        let paths = self
+            .traverse_files(["/"])
+            .map(|(file, path)| (file.id(), Self::manifest_path(&path, file.name())))
+            .collect::<Vec<_>>();
+
+        // Map old IDs to new IDs, pinned files first
+        let mut id_map = HashMap::new();
+        let mut used_ids = HashSet::new();
+        let mut next_id = self.num_overlays as u16;
+        for (old_id, path) in &paths {
+            let Some(&id) = pinned.get(path) else { continue };
+            if Self::is_dir(id) || id < self.num_overlays as u16 {
+                return InvalidFileIdSnafu { path, id }.fail();
+            }
+            if !used_ids.insert(id) {
+                return DuplicateFileIdSnafu { path, id }.fail();
+            }
+            id_map.insert(*old_id, id);
+            next_id = next_id.max(id + 1);
+        }
+        for (old_id, _) in &paths {
+            if !id_map.contains_key(old_id) {
+                id_map.insert(*old_id, next_id);
+                used_ids.insert(next_id);
+                next_id += 1;
+            }
+        }
+
+        let num_ids = self.num_overlays + self.files.len();
+        if let Some(id) = (self.num_overlays as u16..num_ids as u16).find(|id| !used_ids.contains(id)) {
+            return UnusedFileIdSnafu { id }.fail();
+        }
+
+        for dir in &self.dirs {
+            let mut ids =
+                dir.children.iter().filter(|&&child| Self::is_file(child)).map(|child| id_map[child]).collect::<Vec<_>>();
+            ids.sort_unstable();
+            for pair in ids.windows(2) {
+                if pair[1] != pair[0] + 1 {
+                    let path = &paths.iter().find(|(old_id, _)| id_map[old_id] == pair[1]).unwrap().1;
+                    return NonConsecutiveFileIdsSnafu { path, id: pair[1], expected: pair[0] + 1 }.fail();
+                }
+            }
+        }
+
+        // Apply the new IDs, with files in ID order within each directory
+        let mut files = std::mem::take(&mut self.files);
+        for file in &mut files {
+            file.id = id_map[&file.id];
+        }
+        files.sort_unstable_by_key(|file| file.id);
+        self.files = files;
+        for dir in &mut self.dirs {
+            for child in &mut dir.children {
+                if Self::is_file(*child) {
+                    *child = id_map[child];
+                }
+            }
+            dir.children.sort_by_key(|&child| (Self::is_dir(child), if Self::is_file(child) { child } else { 0 }));
+        }
+        self.pinned_ids = true;
+        Ok(())
+    }
+
+    /// Returns the number of files in this [`FileSystem`], not counting overlays.
+    pub fn num_files(&self) -> usize {
+        self.files.len()
+    }
+
     /// Returns whether the ID is a directory ID.
     pub fn is_dir(id: u16) -> bool {
         id >= ROOT_DIR_ID
@@ -162,14 +821,18 @@ impl<'a> FileSystem<'a> {
         &self.files[id as usize - self.num_overlays]
     }
 
+    fn file_mut(&mut self, id: u16) -> &mut File<'a> {
+        &mut self.files[id as usize - self.num_overlays]
+    }
+
     fn parse_subtable(
         fnt: &Fnt,
         fat: &[FileAlloc],
-        rom: &'a raw::Rom,
+        data: &'a [u8],
         parent: &mut Dir,
         dirs: &mut Vec<Option<Dir>>,
         files: &mut Vec<Option<File<'a>>>,
-    ) -> (u16, u16) {
+    ) -> Result<(u16, u16), FileParseError> {
         let subtable_index = parent.id as usize & 0xfff;
         let subtable = &fnt.subtables[subtable_index];
 
@@ -179,9 +842,16 @@ impl<'a> FileSystem<'a> {
             let name = name.to_string();
 
             if Self::is_dir(id) {
-                max_dir_id = max_dir_id.max(id);
+                let Some(slot) = dirs.get_mut(id as usize & 0xfff) else {
+                    return DirIdOutOfBoundsSnafu { id, num_dirs: dirs.len() }.fail();
+                };
                 let mut dir = Dir { id, name, parent_id: parent.id, children: vec![] };
-                let (max_child_dir_id, max_child_file_id) = Self::parse_subtable(fnt, fat, rom, &mut dir, dirs, files);
+                // Occupy the slot before parsing the children, so that directory cycles are detected
+                if slot.replace(dir.clone()).is_some() {
+                    return DuplicateDirIdSnafu { id }.fail();
+                }
+                max_dir_id = max_dir_id.max(id);
+                let (max_child_dir_id, max_child_file_id) = Self::parse_subtable(fnt, fat, data, &mut dir, dirs, files)?;
                 max_dir_id = max_dir_id.max(max_child_dir_id);
                 max_file_id = max_file_id.max(max_child_file_id);
 
@@ -189,13 +859,20 @@ impl<'a> FileSystem<'a> {
                 parent.children.push(id);
             } else {
                 max_file_id = max_file_id.max(id);
-                let alloc = fat[id as usize];
-                let contents = &rom.data()[alloc.range()];
+                let Some(&alloc) = fat.get(id as usize) else {
+                    return FileIdOutOfBoundsSnafu { id, num_files: fat.len() }.fail();
+                };
+                let contents = Self::file_contents(id, alloc, data)?;
                 files[id as usize] = Some(File { id, name, original_offset: alloc.start, contents: Cow::Borrowed(contents) });
                 parent.children.push(id);
             }
         }
-        (max_file_id, max_dir_id)
+        Ok((max_file_id, max_dir_id))
+    }
+
+    fn file_contents(id: u16, alloc: FileAlloc, data: &'a [u8]) -> Result<&'a [u8], FileParseError> {
+        data.get(alloc.range())
+            .ok_or_else(|| FileOutOfBoundsSnafu { id, start: alloc.start, end: alloc.end, size: data.len() }.build())
     }
 
     /// Parses an FNT, FAT and ROM to create a [`FileSystem`].
@@ -206,11 +883,23 @@ impl<'a> FileSystem<'a> {
     /// a file or directory ID is missing from the FNT.
     pub fn parse(fnt: &Fnt, fat: &[FileAlloc], rom: &'a raw::Rom) -> Result<Self, FileParseError> {
         let num_overlays = rom.num_arm9_overlays()? + rom.num_arm7_overlays()?;
+        Self::parse_data(fnt, fat, rom.data(), num_overlays)
+    }
 
+    /// Parses an FNT and FAT to create a [`FileSystem`], with files taken from `data`. The first `num_overlays` entries of the
+    /// FAT are not part of the FNT, like in [`Self::parse`]. This can be used for archives which have the same FNT and FAT
+    /// structure as the ROM.
+    ///
+    /// # Errors
+    ///
+    /// This function will return an error if a file or directory ID is missing from the FNT, or if the FNT or FAT refers to
+    /// something out of bounds.
+    pub fn parse_data(fnt: &Fnt, fat: &[FileAlloc], data: &'a [u8], num_overlays: usize) -> Result<Self, FileParseError> {
         let mut root = Dir { id: ROOT_DIR_ID, name: "/".to_string(), parent_id: 0, children: vec![] };
         let mut dirs = vec![None; fnt.subtables.len()];
         let mut files = vec![None; fat.len()];
-        let (max_file_id, max_dir_id) = Self::parse_subtable(fnt, fat, rom, &mut root, &mut dirs, &mut files);
+        dirs[0] = Some(root.clone());
+        let (max_file_id, max_dir_id) = Self::parse_subtable(fnt, fat, data, &mut root, &mut dirs, &mut files)?;
         dirs[0] = Some(root);
 
         let files = files
@@ -225,19 +914,53 @@ impl<'a> FileSystem<'a> {
             .map(|(id, d)| d.ok_or(MissingDirIdSnafu { id: id as u16 + ROOT_DIR_ID }.build()))
             .collect::<Result<Vec<_>, _>>()?;
 
-        Ok(FileSystem { files, dirs, num_overlays, next_file_id: max_file_id + 1, next_dir_id: max_dir_id + 1 })
+        Ok(FileSystem {
+            files,
+            dirs,
+            num_overlays,
+            next_file_id: max_file_id + 1,
+            next_dir_id: max_dir_id + 1,
+            pinned_ids: false,
+        })
     }
 
-    fn find_first_file_id(&self, parent: &Dir) -> u16 {
-        let child = *parent.children.first().expect("No first file ID found, directory is empty");
-        if Self::is_file(child) {
-            child
-        } else {
-            self.find_first_file_id(self.dir(child))
+    /// Creates a [`FileSystem`] from a FAT alone, with files taken from `data`. This is meant for archives which store no
+    /// file names, so the files are placed in the root directory and named after their IDs.
+    ///
+    /// # Errors
+    ///
+    /// This function will return an error if the FAT refers to something out of bounds.
+    pub fn parse_unnamed(fat: &[FileAlloc], data: &'a [u8]) -> Result<Self, FileParseError> {
+        // Pad the IDs to the same width, so that sorting the names keeps the files in ID order
+        let width = fat.len().saturating_sub(1).to_string().len().max(4);
+
+        let mut files = Self::new(0);
+        for (id, &alloc) in fat.iter().enumerate() {
+            let id = id as u16;
+            let contents = Self::file_contents(id, alloc, data)?;
+            files.files.push(File {
+                id,
+                name: format!("{id:0width$}.bin"),
+                original_offset: alloc.start,
+                contents: Cow::Borrowed(contents),
+            });
+            files.dir_mut(ROOT_DIR_ID).children.push(id);
         }
+        files.next_file_id = fat.len() as u16;
+        Ok(files)
     }
 
-    fn build_subtable(&self, parent: &Dir) -> Result<FntSubtable, FileBuildError> {
+    fn find_first_file_id(&self, parent: &Dir) -> Option<u16> {
+        parent.children.iter().find_map(|&child| {
+            if Self::is_file(child) {
+                Some(child)
+            } else {
+                self.find_first_file_id(self.dir(child))
+            }
+        })
+    }
+
+    fn build_subtable(&self, parent: &Dir, first_file_id: u16) -> Result<FntSubtable, FileBuildError> {
         let mut data = vec![];
 
         for child in &parent.children {
@@ -262,21 +985,25 @@ impl<'a> FileSystem<'a> {
         }
 
         Ok(FntSubtable {
-            directory: Cow::Owned(FntDirectory {
-                subtable_offset: 0,
-                first_file_id: self.find_first_file_id(parent),
-                parent_id: parent.parent_id,
-            }),
+            directory: Cow::Owned(FntDirectory { subtable_offset: 0, first_file_id, parent_id: parent.parent_id }),
             data: Cow::Owned(data),
         })
     }
 
-    fn build_fnt_recursive(&'a self, subtables: &mut Vec<FntSubtable<'a>>, parent_id: u16) -> Result<(), FileBuildError> {
+    fn build_fnt_recursive(
+        &'a self,
+        subtables: &mut Vec<FntSubtable<'a>>,
+        parent_id: u16,
+        next_file_id: &mut u16,
+    ) -> Result<(), FileBuildError> {
         let parent = &self.dir(parent_id);
-        subtables.push(self.build_subtable(parent)?);
+        // Directories without any files get the ID which the next file would have
+        let first_file_id = self.find_first_file_id(parent).unwrap_or(*next_file_id);
+        subtables.push(self.build_subtable(parent, first_file_id)?);
+        *next_file_id = first_file_id + parent.children.iter().filter(|&&child| Self::is_file(child)).count() as u16;
         for child in &parent.children {
             if Self::is_dir(*child) {
-                self.build_fnt_recursive(subtables, *child)?;
+                self.build_fnt_recursive(subtables, *child, next_file_id)?;
             }
         }
         Ok(())
@@ -289,7 +1016,8 @@ impl<'a> FileSystem<'a> {
     /// This function will return an error if a file/directory name contains non-ASCII characters.
     pub fn build_fnt(&self) -> Result<Fnt, FileBuildError> {
         let mut subtables = vec![];
-        self.build_fnt_recursive(&mut subtables, ROOT_DIR_ID)?;
+        let mut next_file_id = self.num_overlays as u16;
+        self.build_fnt_recursive(&mut subtables, ROOT_DIR_ID, &mut next_file_id)?;
         Ok(Fnt { subtables: subtables.into_boxed_slice() })
     }
 
@@ -316,9 +1044,14 @@ impl<'a> FileSystem<'a> {
 
     fn sort_for_fnt_in(&mut self, parent_id: u16) {
         let mut parent = self.dir(parent_id).clone();
-        parent
-            .children
-            .sort_by(|a, b| Self::compare_for_fnt(self.name(*a), Self::is_dir(*a), self.name(*b), Self::is_dir(*b)));
+        parent.children.sort_by(|a, b| {
+            if Self::is_file(*a) && Self::is_file(*b) {
+                // Files must be in ID order, which only differs from name order if the IDs are pinned
+                a.cmp(b)
+            } else {
+                Self::compare_for_fnt(self.name(*a), Self::is_dir(*a), self.name(*b), Self::is_dir(*b))
+            }
+        });
 
         for child in &mut parent.children {
             if Self::is_dir(*child) {
@@ -372,8 +1105,251 @@ impl<'a> FileSystem<'a> {
         }
     }
 
-    fn find_path(&self, path: &str) -> Option<u16> {
-        self.find_path_in(path, ROOT_DIR_ID)
+    /// Returns the ID of the file or directory at `path`, relative to the root directory. An empty path or `/` is the root
+    /// directory.
+    pub fn find_path(&self, path: &str) -> Option<u16> {
+        let path = path.trim_matches('/');
+        if path.is_empty() {
+            Some(ROOT_DIR_ID)
+        } else {
+            self.find_path_in(path, ROOT_DIR_ID)
+        }
+    }
+
+    fn find_edit_path(&self, path: &str) -> Result<u16, FileEditError> {
+        self.find_path(path).ok_or_else(|| PathNotFoundSnafu { path }.build())
+    }
+
+    fn find_edit_dir(&self, path: &str) -> Result<u16, FileEditError> {
+        let id = self.find_edit_path(path)?;
+        if Self::is_file(id) {
+            return NotADirSnafu { path }.fail();
+        }
+        Ok(id)
+    }
+
+    /// Splits a path into its parent directory path and name, and checks that the name can be stored in the FNT.
+    fn split_edit_path(path: &str) -> Result<(&str, &str), FileEditError> {
+        let path = path.trim_matches('/');
+        let (parent, name) = path.rsplit_once('/').unwrap_or(("", path));
+        let (sjis_name, _, had_errors) = SHIFT_JIS.encode(name);
+        if name.is_empty() || had_errors || sjis_name.len() > 0x7f {
+            return InvalidNameSnafu { name }.fail();
+        }
+        Ok((parent, name))
+    }
+
+    /// Checks that `path` is free to be created, and returns its parent ID and name.
+    fn prepare_new_path<'p>(&self, path: &'p str) -> Result<(u16, &'p str), FileEditError> {
+        let (parent, name) = Self::split_edit_path(path)?;
+        let parent_id = self.find_edit_dir(parent)?;
+        if self.dir(parent_id).children.iter().any(|&child| self.name(child) == name) {
+            return PathExistsSnafu { path }.fail();
+        }
+        Ok((parent_id, name))
+    }
+
+    /// Checks that IDs may be reassigned, see [`Self::pin_file_ids`].
+    fn check_unpinned(&self) -> Result<(), FileEditError> {
+        if self.pinned_ids {
+            return PinnedFileIdsSnafu {}.fail();
+        }
+        Ok(())
+    }
+
+    /// Adds a child to a directory, in FNT order among the other children.
+    fn insert_child(&mut self, parent_id: u16, child_id: u16) {
+        let name = self.name(child_id);
+        let is_dir = Self::is_dir(child_id);
+        let parent = self.dir(parent_id);
+        let index = parent
+            .children
+            .iter()
+            .position(|&c| Self::compare_for_fnt(self.name(c), Self::is_dir(c), name, is_dir).is_gt())
+            .unwrap_or(parent.children.len());
+        self.dir_mut(parent_id).children.insert(index, child_id);
+    }
+
+    /// Inserts a new file at `path`, relative to the root directory. The parent directory must already exist.
+    ///
+    /// File IDs are reassigned in FNT order, so the IDs of other files may change. Returns the ID of the new file.
+    ///
+    /// # Errors
+    ///
+    /// This function will return an error if the parent directory doesn't exist, `path` already exists, the name can't be
+    /// stored in the FNT, there are too many files, or the file IDs are pinned.
+    pub fn insert_file<C: Into<Cow<'a, [u8]>>>(&mut self, path: &str, contents: C) -> Result<u16, FileEditError> {
+        self.check_unpinned()?;
+        let (parent_id, name) = self.prepare_new_path(path)?;
+        let id = (self.num_overlays + self.files.len()) as u16;
+        if id >= ROOT_DIR_ID {
+            return TooManyFilesSnafu { max: ROOT_DIR_ID as usize - self.num_overlays }.fail();
+        }
+        self.files.push(File { id, name: name.to_string(), original_offset: 0, contents: contents.into() });
+        self.insert_child(parent_id, id);
+        self.renumber();
+        Ok(self.find_path(path).unwrap())
+    }
+
+    /// Creates a new empty directory at `path`, relative to the root directory. The parent directory must already exist.
+    ///
+    /// Directory IDs are reassigned in FNT order, so the IDs of other directories may change. Returns the ID of the new
+    /// directory.
+    ///
+    /// # Errors
+    ///
+    /// This function will return an error if the parent directory doesn't exist, `path` already exists, the name can't be
+    /// stored in the FNT, there are too many directories, or the file IDs are pinned.
+    pub fn create_dir(&mut self, path: &str) -> Result<u16, FileEditError> {
+        self.check_unpinned()?;
+        let (parent_id, name) = self.prepare_new_path(path)?;
+        if self.dirs.len() > MAX_DIR_INDEX {
+            return TooManyDirsSnafu { max: MAX_DIR_INDEX + 1 }.fail();
+        }
+        let id = ROOT_DIR_ID + self.dirs.len() as u16;
+        self.dirs.push(Dir { id, name: name.to_string(), parent_id, children: vec![] });
+        self.insert_child(parent_id, id);
+        self.renumber();
+        Ok(self.find_path(path).unwrap())
+    }
+
+    /// Removes the file or directory at `path`, relative to the root directory. Directories are removed along with their
+    /// contents.
+    ///
+    /// File and directory IDs are reassigned in FNT order, so the IDs of other files and directories may change.
+    ///
+    /// # Errors
+    ///
+    /// This function will return an error if `path` doesn't exist, is the root directory, or the file IDs are pinned.
+    pub fn remove(&mut self, path: &str) -> Result<(), FileEditError> {
+        self.check_unpinned()?;
+        let id = self.find_edit_path(path)?;
+        let parent_id = self.parent_id(id).ok_or_else(|| RootDirSnafu {}.build())?;
+        self.dir_mut(parent_id).children.retain(|&child| child != id);
+        self.renumber();
+        Ok(())
+    }
+
+    /// Moves the file or directory at `from` to `to`, both relative to the root directory. The parent directory of `to` must
+    /// already exist. This can also be used to rename without moving, see [`Self::rename`].
+    ///
+    /// File and directory IDs are reassigned in FNT order, so the IDs of other files and directories may change. Returns the
+    /// new ID of the moved file or directory.
+    ///
+    /// # Errors
+    ///
+    /// This function will return an error if `from` doesn't exist or is the root directory, the parent of `to` doesn't
+    /// exist, `to` already exists, the name can't be stored in the FNT, a directory would be moved into itself, or the file
+    /// IDs are pinned.
+    pub fn move_path(&mut self, from: &str, to: &str) -> Result<u16, FileEditError> {
+        self.check_unpinned()?;
+        let id = self.find_edit_path(from)?;
+        let old_parent_id = self.parent_id(id).ok_or_else(|| RootDirSnafu {}.build())?;
+        let (new_parent_id, name) = self.prepare_new_path(to)?;
+
+        let mut ancestor = Some(new_parent_id);
+        while let Some(ancestor_id) = ancestor {
+            if ancestor_id == id {
+                return MoveIntoItselfSnafu { from, to }.fail();
+            }
+            ancestor = self.parent_id(ancestor_id);
+        }
+
+        self.dir_mut(old_parent_id).children.retain(|&child| child != id);
+        if Self::is_dir(id) {
+            let dir = self.dir_mut(id);
+            dir.name = name.to_string();
+            dir.parent_id = new_parent_id;
+        } else {
+            self.file_mut(id).name = name.to_string();
+        }
+        self.insert_child(new_parent_id, id);
+        self.renumber();
+        Ok(self.find_path(to).unwrap())
+    }
+
+    /// Renames the file or directory at `path`, relative to the root directory, without moving it to another directory.
+    /// See [`Self::move_path`].
+    ///
+    /// # Errors
+    ///
+    /// See [`Self::move_path`].
+    pub fn rename(&mut self, path: &str, name: &str) -> Result<u16, FileEditError> {
+        let path = path.trim_matches('/');
+        let parent = path.rsplit_once('/').map_or("", |(parent, _)| parent);
+        if name.contains('/') {
+            return InvalidNameSnafu { name }.fail();
+        }
+        self.move_path(path, &format!("{parent}/{name}"))
+    }
+
+    /// Replaces the contents of the file at `path`, relative to the root directory. IDs are not changed.
+    ///
+    /// # Errors
+    ///
+    /// This function will return an error if `path` doesn't exist or is a directory.
+    pub fn replace_file<C: Into<Cow<'a, [u8]>>>(&mut self, path: &str, contents: C) -> Result<(), FileEditError> {
+        let id = self.find_edit_path(path)?;
+        if Self::is_dir(id) {
+            return NotAFileSnafu { path }.fail();
+        }
+        self.file_mut(id).contents = contents.into();
+        Ok(())
+    }
+
+    /// Returns the parent directory ID of a file or directory, or `None` for the root directory.
+    fn parent_id(&self, id: u16) -> Option<u16> {
+        if id == ROOT_DIR_ID {
+            None
+        } else if Self::is_dir(id) {
+            Some(self.dir(id).parent_id)
+        } else {
+            self.dirs.iter().find(|dir| dir.children.contains(&id)).map(|dir| dir.id)
+        }
+    }
+
+    /// Reassigns all file and directory IDs in the order that [`Self::build_fnt`] expects them, and drops the files and
+    /// directories which are no longer reachable from the root directory.
+    fn renumber(&mut self) {
+        let mut old_files = std::mem::take(&mut self.files).into_iter().map(Some).collect::<Vec<_>>();
+        let mut old_dirs = std::mem::take(&mut self.dirs).into_iter().map(Some).collect::<Vec<_>>();
+        self.renumber_in(ROOT_DIR_ID, 0, &mut old_files, &mut old_dirs);
+        self.next_file_id = (self.num_overlays + self.files.len()) as u16;
+        self.next_dir_id = ROOT_DIR_ID + self.dirs.len() as u16;
+    }
+
+    fn renumber_in(
+        &mut self,
+        old_id: u16,
+        parent_id: u16,
+        old_files: &mut [Option<File<'a>>],
+        old_dirs: &mut [Option<Dir>],
+    ) -> u16 {
+        let mut dir = old_dirs[old_id as usize & 0xfff].take().unwrap();
+        let id = ROOT_DIR_ID + self.dirs.len() as u16;
+        let old_children = std::mem::take(&mut dir.children);
+        dir.id = id;
+        dir.parent_id = parent_id;
+        self.dirs.push(dir);
+
+        // Files in the same directory must have consecutive IDs, so they are numbered before any subdirectory
+        let mut children = old_children.clone();
+        for (child, &old_child) in children.iter_mut().zip(&old_children) {
+            if Self::is_file(old_child) {
+                let mut file = old_files[old_child as usize - self.num_overlays].take().unwrap();
+                file.id = (self.num_overlays + self.files.len()) as u16;
+                *child = file.id;
+                self.files.push(file);
+            }
+        }
+        for (child, &old_child) in children.iter_mut().zip(&old_children) {
+            if Self::is_dir(old_child) {
+                *child = self.renumber_in(old_child, id, old_files, old_dirs);
+            }
+        }
+
+        self.dir_mut(id).children = children;
+        id
     }
 
     fn make_child_dir(&mut self, name: String, parent_id: u16) -> &Dir {
@@ -394,63 +1370,14 @@ impl<'a> FileSystem<'a> {
         self.files.last().unwrap()
     }
 
-    fn traverse_nonvisited_files<Cb>(&self, visited: &mut HashSet<u16>, callback: &mut Cb, subdir: &Dir, path: &Path)
-    where
-        Cb: FnMut(&File, &Path),
-    {
-        if visited.contains(&subdir.id) {
-            return;
-        }
-        for child in &subdir.children {
-            if visited.contains(child) {
-                continue;
-            }
-
-            if Self::is_dir(*child) {
-                let path = path.join(self.name(*child));
-                self.traverse_nonvisited_files(visited, callback, self.dir(*child), &path);
-            } else {
-                callback(self.file(*child), path);
-                let first_time_visiting_file = visited.insert(*child);
-                assert!(first_time_visiting_file);
-            }
-        }
-        let first_time_visiting_file = visited.insert(subdir.id);
-        assert!(first_time_visiting_file);
-    }
-
-    /// Traverses the [`FileSystem`] and calls `callback` for each file found. The directories will be prioritized according to
-    /// the `path_order`.
-    pub fn traverse_files<I, Cb>(&self, path_order: I, mut callback: Cb)
+    /// Returns an iterator over the files of this [`FileSystem`] and the paths of their directories. The directories will
+    /// be prioritized according to the `path_order`, and files which are not in any of its paths are traversed last.
+    pub fn traverse_files<I, S>(&self, path_order: I) -> TraverseFiles<'_, I::IntoIter>
     where
-        I: IntoIterator<Item = &'a str>,
-        Cb: FnMut(&File, &Path),
+        I: IntoIterator<Item = S>,
+        S: AsRef<str>,
     {
-        let mut visited = HashSet::<u16>::new();
-
-        for path in path_order {
-            let path = path.strip_prefix("/").unwrap_or(path);
-            let path_buf = &PathBuf::from_str(path).unwrap();
-            let subdir = if path.trim() == "" {
-                self.dir(ROOT_DIR_ID)
-            } else {
-                let Some(child) = self.find_path(path) else { continue };
-                if visited.contains(&child) {
-                    continue;
-                }
-
-                if Self::is_dir(child) {
-                    self.dir(child)
-                } else {
-                    let file = self.file(child);
-                    callback(file, path_buf);
-                    let first_time_visiting_file = visited.insert(file.id);
-                    assert!(first_time_visiting_file);
-                    continue;
-                }
-            };
-            self.traverse_nonvisited_files(&mut visited, &mut callback, subdir, path_buf);
-        }
+        TraverseFiles { files: self, path_order: path_order.into_iter(), visited: HashSet::new(), stack: vec![], done: false }
     }
 
     fn max_file_id_in(&self, parent_id: u16) -> u16 {
@@ -595,6 +1522,88 @@ impl Ord for PathOrder {
     }
 }
 
+/// Iterator over the files of a [`FileSystem`] in path order, created by [`FileSystem::traverse_files`].
+pub struct TraverseFiles<'a, I> {
+    files: &'a FileSystem<'a>,
+    path_order: I,
+    visited: HashSet<u16>,
+    /// Directories being traversed, with the index of the next child and the path of the directory.
+    stack: Vec<(&'a Dir, usize, PathBuf)>,
+    /// Whether the path order is exhausted and the remaining files are being traversed.
+    done: bool,
+}
+
+impl<I, S> TraverseFiles<'_, I>
+where
+    I: Iterator<Item = S>,
+    S: AsRef<str>,
+{
+    fn push_dir(&mut self, id: u16, path: PathBuf) {
+        if !self.visited.contains(&id) {
+            self.stack.push((self.files.dir(id), 0, path));
+        }
+    }
+}
+
+impl<'a, I, S> Iterator for TraverseFiles<'a, I>
+where
+    I: Iterator<Item = S>,
+    S: AsRef<str>,
+{
+    type Item = (&'a File<'a>, PathBuf);
+
+    fn next(&mut self) -> Option<Self::Item> {
+        loop {
+            if let Some((dir, index, path)) = self.stack.last_mut() {
+                let dir_id = dir.id;
+                let Some(&child) = dir.children.get(*index) else {
+                    self.stack.pop();
+                    let first_time_visiting_dir = self.visited.insert(dir_id);
+                    assert!(first_time_visiting_dir);
+                    continue;
+                };
+                *index += 1;
+                if self.visited.contains(&child) {
+                    continue;
+                }
+
+                if FileSystem::is_file(child) {
+                    self.visited.insert(child);
+                    return Some((self.files.file(child), path.clone()));
+                }
+                let path = path.join(self.files.name(child));
+                self.push_dir(child, path);
+                continue;
+            }
+
+            if self.done {
+                return None;
+            }
+            let Some(path) = self.path_order.next() else {
+                // Files which are not covered by the path order, such as newly inserted ones, come last
+                self.done = true;
+                self.push_dir(ROOT_DIR_ID, PathBuf::new());
+                continue;
+            };
+            let path = path.as_ref();
+            let path = path.strip_prefix("/").unwrap_or(path);
+            let Some(child) = self.files.find_path(path) else { continue };
+            if self.visited.contains(&child) {
+                continue;
+            }
+
+            let path_buf = PathBuf::from(path.trim_matches('/'));
+            if FileSystem::is_dir(child) {
+                self.push_dir(child, path_buf);
+            } else {
+                self.visited.insert(child);
+                let dir_path = path_buf.parent().map(Path::to_path_buf).unwrap_or_default();
+                return Some((self.files.file(child), dir_path));
+            }
+        }
+    }
+}
+
 /// Can be used to display the file hierarchy of a [`FileSystem`].
 pub struct DisplayFileSystem<'a> {
     files: &'a FileSystem<'a>,
@@ -622,3 +1631,213 @@ impl Display for DisplayFileSystem<'_> {
         Ok(())
     }
 }
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::compress::lz10::Lz10;
+
+    const NUM_OVERLAYS: usize = 2;
+
+    /// Returns a file system with two files in the root directory and one file in each of the directories `x` and `y`. The
+    /// contents of each file is its path.
+    fn build_files(extra_files: &[&str]) -> FileSystem<'static> {
+        let mut files = FileSystem::new(NUM_OVERLAYS);
+        files.create_dir("x").unwrap();
+        files.create_dir("y").unwrap();
+        for path in ["a.bin", "b.bin", "x/c.bin", "y/d.bin"].iter().chain(extra_files) {
+            files.insert_file(path, path.as_bytes().to_vec()).unwrap();
+        }
+        files
+    }
+
+    /// Returns a FAT and file image with the files of `files` in ID order, after empty overlays.
+    fn build_fat(files: &FileSystem) -> (Vec<FileAlloc>, Vec<u8>) {
+        let mut fat = vec![FileAlloc { start: 0, end: 0 }; NUM_OVERLAYS + files.num_files()];
+        let mut data = vec![];
+        for file in &files.files {
+            let start = data.len() as u32;
+            data.extend(file.contents());
+            fat[file.id() as usize] = FileAlloc { start, end: data.len() as u32 };
+        }
+        (fat, data)
+    }
+
+    /// Returns the ID of the file at `path`, and checks that it has the contents given by [`build_files`].
+    fn file_id(files: &FileSystem, path: &str) -> u16 {
+        let id = files.find_path(path).unwrap();
+        assert_eq!(files.file(id).contents(), path.as_bytes());
+        id
+    }
+
+    /// Saves the file IDs of `files` and returns the path to the manifest.
+    fn save_file_ids(files: &FileSystem, name: &str) -> PathBuf {
+        let manifest_path = std::env::temp_dir().join(format!("ds-rom-{}-{name}.yaml", std::process::id()));
+        files.save_file_ids(&manifest_path).unwrap();
+        manifest_path
+    }
+
+    #[test]
+    fn pin_file_ids_append_to_highest_dir() {
+        let manifest_path = save_file_ids(&build_files(&[]), "append");
+        let mut files = build_files(&["y/e.bin"]);
+        files.create_dir("z").unwrap();
+        files.insert_file("z/f.bin", b"z/f.bin".to_vec()).unwrap();
+        files.pin_file_ids(&manifest_path).unwrap();
+        std::fs::remove_file(&manifest_path).unwrap();
+
+        let expected = [("a.bin", 2), ("b.bin", 3), ("x/c.bin", 4), ("y/d.bin", 5), ("y/e.bin", 6), ("z/f.bin", 7)];
+        for (path, id) in expected {
+            assert_eq!(file_id(&files, path), id, "{path}");
+        }
+
+        // The pinned IDs survive the FNT and FAT
+        let (fat, data) = build_fat(&files);
+        let parsed = FileSystem::parse_data(&files.build_fnt().unwrap(), &fat, &data, NUM_OVERLAYS).unwrap();
+        for (path, id) in expected {
+            assert_eq!(file_id(&parsed, path), id, "{path}");
+        }
+
+        assert!(matches!(files.insert_file("z/g.bin", vec![]), Err(FileEditError::PinnedFileIds { .. })));
+        assert!(matches!(files.remove("a.bin"), Err(FileEditError::PinnedFileIds { .. })));
+        assert!(matches!(files.rename("a.bin", "g.bin"), Err(FileEditError::PinnedFileIds { .. })));
+    }
+
+    #[test]
+    fn pin_file_ids_non_consecutive() {
+        let manifest_path = save_file_ids(&build_files(&[]), "non-consecutive");
+        let mut files = build_files(&["x/e.bin"]);
+        let result = files.pin_file_ids(&manifest_path);
+        std::fs::remove_file(&manifest_path).unwrap();
+
+        let Err(FileIdError::NonConsecutiveFileIds { path, id, expected, .. }) = result else {
+            panic!("expected NonConsecutiveFileIds");
+        };
+        assert_eq!(path, "x/e.bin");
+        assert_eq!(id, 6);
+        assert_eq!(expected, 5);
+    }
+
+    /// Checks that the file IDs are in use exactly once, and consecutive within each directory in FNT order.
+    fn check_file_ids(files: &FileSystem) {
+        let mut ids = files.files.iter().map(|file| file.id()).collect::<Vec<_>>();
+        ids.sort_unstable();
+        assert_eq!(ids, (NUM_OVERLAYS as u16..(NUM_OVERLAYS + files.num_files()) as u16).collect::<Vec<_>>());
+
+        for dir in &files.dirs {
+            let ids = dir.children.iter().copied().filter(|&child| FileSystem::is_file(child)).collect::<Vec<_>>();
+            for pair in ids.windows(2) {
+                assert_eq!(pair[1], pair[0] + 1, "IDs in '{}' are not consecutive", dir.name);
+            }
+        }
+    }
+
+    #[test]
+    fn edit_and_rebuild() {
+        let mut files = build_files(&["x/e.bin"]);
+        files.create_dir("y/sub").unwrap();
+        files.insert_file("y/sub/g.bin", b"y/sub/g.bin".to_vec()).unwrap();
+        files.remove("b.bin").unwrap();
+        files.move_path("x/c.bin", "y/c.bin").unwrap();
+        files.rename("y", "w").unwrap();
+        files.move_path("/x/", "w/x2").unwrap();
+        check_file_ids(&files);
+
+        let expected = [
+            ("a.bin", "a.bin"),
+            ("w/c.bin", "x/c.bin"),
+            ("w/d.bin", "y/d.bin"),
+            ("w/sub/g.bin", "y/sub/g.bin"),
+            ("w/x2/e.bin", "x/e.bin"),
+        ];
+        assert_eq!(files.num_files(), expected.len());
+        for (path, contents) in expected {
+            let id = files.find_path(path).unwrap();
+            assert_eq!(files.file(id).contents(), contents.as_bytes(), "{path}");
+        }
+        assert_eq!(files.find_path("b.bin"), None);
+        assert_eq!(files.find_path("x"), None);
+        assert_eq!(files.find_path("y"), None);
+
+        let (fat, data) = build_fat(&files);
+        let parsed = FileSystem::parse_data(&files.build_fnt().unwrap(), &fat, &data, NUM_OVERLAYS).unwrap();
+        check_file_ids(&parsed);
+        assert_eq!(parsed.num_files(), expected.len());
+        for (path, contents) in expected {
+            let id = parsed.find_path(path).unwrap();
+            assert_eq!(id, files.find_path(path).unwrap(), "{path}");
+            assert_eq!(parsed.file(id).contents(), contents.as_bytes(), "{path}");
+        }
+
+        files.remove("w/x2").unwrap();
+        check_file_ids(&files);
+        assert_eq!(files.num_files(), expected.len() - 1);
+        assert_eq!(files.find_path("w/x2/e.bin"), None);
+    }
+
+    #[test]
+    fn edit_errors() {
+        let mut files = build_files(&[]);
+        assert!(matches!(files.insert_file("a.bin", vec![]), Err(FileEditError::PathExists { .. })));
+        assert!(matches!(files.insert_file("z/a.bin", vec![]), Err(FileEditError::PathNotFound { .. })));
+        assert!(matches!(files.insert_file("a.bin/b.bin", vec![]), Err(FileEditError::NotADir { .. })));
+        assert!(matches!(files.remove("/"), Err(FileEditError::RootDir { .. })));
+        assert!(matches!(files.move_path("x", "x/z"), Err(FileEditError::MoveIntoItself { .. })));
+        assert!(matches!(files.rename("a.bin", "x/b.bin"), Err(FileEditError::InvalidName { .. })));
+        check_file_ids(&files);
+    }
+
+    #[test]
+    fn save_decompressed_vram_safe() {
+        let bytes = [b'A'; 0x20];
+        let mut files = FileSystem::new(NUM_OVERLAYS);
+        files.insert_file("plain.bin", Lz10 {}.compress(&bytes).unwrap().into_vec()).unwrap();
+        files.insert_file("vram.bin", Lz10 {}.compress_vram_safe(&bytes).unwrap().into_vec()).unwrap();
+
+        let root = std::env::temp_dir().join(format!("ds-rom-{}-vram-safe", std::process::id()));
+        let manifest_path = root.join("files_compression.yaml");
+        files.save_decompressed(root.join("files"), &manifest_path).unwrap();
+        let manifest: FileCompressionManifest = serde_yml::from_reader(open_file(&manifest_path).unwrap()).unwrap();
+        let vram_safe =
+            manifest.files.iter().map(|file| (file.path.as_str(), file.vram_safe, file.original)).collect::<Vec<_>>();
+        assert_eq!(vram_safe, [("plain.bin", false, false), ("vram.bin", true, false)]);
+
+        // Modified files are compressed in the same mode
+        write_file(root.join("files/vram.bin"), [b'A'; 0x21]).unwrap();
+        let loaded = FileSystem::load_decompressed(root.join("files"), &manifest_path, NUM_OVERLAYS).unwrap();
+        let vram = loaded.file(loaded.find_path("vram.bin").unwrap()).contents();
+        assert_eq!(vram, &*Lz10 {}.compress_vram_safe(&[b'A'; 0x21]).unwrap());
+        let plain = loaded.file(loaded.find_path("plain.bin").unwrap()).contents();
+        assert_eq!(plain, files.file(files.find_path("plain.bin").unwrap()).contents());
+        std::fs::remove_dir_all(root).unwrap();
+    }
+
+    #[test]
+    fn detect_compression_requires_exact_size() {
+        // A valid LZ10 stream with four literals
+        let contents = [0x10, 0x04, 0x00, 0x00, 0x00, 1, 2, 3, 4, 0, 0, 0];
+        let (format, decompressed) = FileSystem::detect_compression(&contents).unwrap();
+        assert_eq!(format, BiosCompressionType::Lz10);
+        assert_eq!(&*decompressed, &[1, 2, 3, 4]);
+
+        // Raw data which happens to start with an LZ10 header and decompress, but doesn't end with the stream
+        let raw = [&[0x10, 0x08, 0x00, 0x00, 0x00][..], b"raw data, not compressed"].concat();
+        assert!(BiosCompressionType::Lz10.decompress(&raw).is_ok());
+        assert!(FileSystem::detect_compression(&raw).is_none());
+    }
+
+    #[test]
+    fn traverse_files_path_order() {
+        let files = build_files(&["x/e.bin"]);
+        let path_order = ["/y/d.bin", "/x", "/missing", "/x/c.bin"];
+        let traversed = files
+            .traverse_files(path_order)
+            .map(|(file, path)| path.join(file.name()).to_string_lossy().replace('\\', "/"))
+            .collect::<Vec<_>>();
+        assert_eq!(traversed, ["y/d.bin", "x/c.bin", "x/e.bin", "a.bin", "b.bin"]);
+
+        let traversed = files.traverse_files::<_, &str>([]).map(|(file, _)| file.id()).collect::<Vec<_>>();
+        assert_eq!(traversed.len(), files.num_files());
+        assert_eq!(traversed.iter().collect::<HashSet<_>>().len(), files.num_files());
+    }
+}
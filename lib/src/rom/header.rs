@@ -4,14 +4,14 @@ use std::{
 };
 
 use serde::{Deserialize, Serialize};
-use snafu::Snafu;
+use snafu::{Backtrace, Snafu};
 
 use super::{
     raw::{
         self, AccessControl, Capacity, Delay, DsFlags, DsiFlags, DsiFlags2, HeaderVersion, ProgramOffset, RegionFlags,
         TableOffset,
     },
-    BuildContext, Rom,
+    BuildContext, DsiProgram, Rom,
 };
 use crate::{
     crc::CRC_16_MODBUS,
@@ -26,6 +26,9 @@ pub struct Header {
     /// Values for DS games after DSi release, [`HeaderVersion::DsPostDsi`].
     #[serde(skip_serializing_if = "Option::is_none")]
     pub ds_post_dsi: Option<HeaderDsPostDsi>,
+    /// Values in the DSi extended header, used by DSi titles and some DS games after DSi release.
+    #[serde(skip_serializing_if = "Option::is_none")]
+    pub dsi: Option<HeaderDsi>,
 }
 
 /// Values for the original header version, [`HeaderVersion::Original`].
@@ -53,6 +56,9 @@ pub struct HeaderOriginal {
     pub key1_cmd_setting: u32,
     /// Delay to wait for secure area.
     pub secure_area_delay: Delay,
+    /// Disables the secure area with an encrypted "NmMdOnly" marker. If true, the secure area will not be encrypted.
+    #[serde(default)]
+    pub secure_area_disable: bool,
     /// NAND end of ROM area in multiples of 0x20000 (0x80000 on DSi).
     pub rom_nand_end: u16,
     /// NAND end of RW area in multiples of 0x20000 (0x80000 on DSi).
@@ -76,6 +82,72 @@ pub struct HeaderDsPostDsi {
     pub rsa_sha1: Box<[u8]>,
 }
 
+/// Values in the DSi extended header. Offsets and sizes of the DSi area are not included, as they are computed when
+/// building the ROM.
+#[derive(Serialize, Deserialize)]
+pub struct HeaderDsi {
+    /// DSi-specific flags.
+    pub dsi_flags: DsiFlags,
+    /// MBK1 to MBK5
+    pub memory_banks_wram: [u32; 5],
+    /// MBK6 to MBK8
+    pub memory_banks_arm9: [u32; 3],
+    /// MBK6 to MBK8
+    pub memory_banks_arm7: [u32; 3],
+    /// MBK9
+    pub memory_bank_9: u32,
+    /// Region flags.
+    pub region_flags: RegionFlags,
+    /// Access control.
+    pub access_control: AccessControl,
+    /// ARM7 SCFG_EXT7 setting.
+    pub arm7_scfg_ext7_setting: u32,
+    /// Digest sector size.
+    pub digest_sector_size: u32,
+    /// Number of digest sectors per block.
+    pub digest_sector_count: u32,
+    /// Banner size.
+    pub banner_size: u32,
+    /// SD/MMC size of shared2/0000 file
+    pub sd_shared2_0000_size: u8,
+    /// SD/MMC size of shared2/0001 file
+    pub sd_shared2_0001_size: u8,
+    /// EULA version.
+    pub eula_version: u8,
+    /// Use age ratings.
+    pub use_ratings: bool,
+    /// SD/MMC size of shared/0002 file
+    pub sd_shared2_0002_size: u8,
+    /// SD/MMC size of shared/0003 file
+    pub sd_shared2_0003_size: u8,
+    /// SD/MMC size of shared/0004 file
+    pub sd_shared2_0004_size: u8,
+    /// SD/MMC size of shared/0005 file
+    pub sd_shared2_0005_size: u8,
+    /// Same as [`HeaderOriginal::gamecode`] but byte-reversed.
+    pub gamecode_rev: AsciiArray<4>,
+    /// File type.
+    pub file_type: u32,
+    /// SD/MMC size of public.sav file.
+    pub sd_public_sav_size: u32,
+    /// SD/MMC size of private.sav file.
+    pub sd_private_sav_size: u32,
+    /// Age ratings.
+    pub age_ratings: [u8; 0x10],
+    /// SHA1-HMAC of ARM9 program including secure area.
+    pub sha1_hmac_arm9_with_secure_area: [u8; 0x14],
+    /// SHA1-HMAC of ARM7 program.
+    pub sha1_hmac_arm7: [u8; 0x14],
+    /// SHA1-HMAC of digest block hashtable.
+    pub sha1_hmac_digest: [u8; 0x14],
+    /// SHA1-HMAC of ARM9i program.
+    pub sha1_hmac_arm9i: [u8; 0x14],
+    /// SHA1-HMAC of ARM7i program.
+    pub sha1_hmac_arm7i: [u8; 0x14],
+    /// SHA1-HMAC of ARM9 program excluding secure area.
+    pub sha1_hmac_arm9: [u8; 0x14],
+}
+
 /// Errors related to [`Header::build`].
 #[derive(Snafu, Debug)]
 pub enum HeaderBuildError {
@@ -85,6 +157,12 @@ pub enum HeaderBuildError {
         /// Source error.
         source: AsciiArrayError,
     },
+    /// Occurs when the secure area is disabled but no Blowfish key was provided to encrypt the "NmMdOnly" marker.
+    #[snafu(display("blowfish key is required to disable the secure area:\n{backtrace}"))]
+    SecureAreaDisableKeyNeeded {
+        /// Backtrace to the source of the error.
+        backtrace: Backtrace,
+    },
 }
 
 impl Header {
@@ -104,6 +182,7 @@ impl Header {
                 normal_cmd_setting: header.normal_cmd_setting,
                 key1_cmd_setting: header.key1_cmd_setting,
                 secure_area_delay: header.secure_area_delay,
+                secure_area_disable: header.secure_area_disable != 0,
                 rom_nand_end: header.rom_nand_end,
                 rw_nand_end: header.rw_nand_end,
                 has_arm9_build_info_offset: header.arm9_build_info_offset != 0,
@@ -115,6 +194,38 @@ impl Header {
                 sha1_hmac_unk2: header.sha1_hmac_unk2,
                 rsa_sha1: Box::new(header.rsa_sha1),
             }),
+            dsi: (version >= HeaderVersion::DsPostDsi || header.arm9i.offset != 0).then_some(HeaderDsi {
+                dsi_flags: header.dsi_flags,
+                memory_banks_wram: header.memory_banks_wram,
+                memory_banks_arm9: header.memory_banks_arm9,
+                memory_banks_arm7: header.memory_banks_arm7,
+                memory_bank_9: header.memory_bank_9,
+                region_flags: header.region_flags,
+                access_control: header.access_control,
+                arm7_scfg_ext7_setting: header.arm7_scfg_ext7_setting,
+                digest_sector_size: header.digest_sector_size,
+                digest_sector_count: header.digest_sector_count,
+                banner_size: header.banner_size,
+                sd_shared2_0000_size: header.sd_shared2_0000_size,
+                sd_shared2_0001_size: header.sd_shared2_0001_size,
+                eula_version: header.eula_version,
+                use_ratings: header.use_ratings,
+                sd_shared2_0002_size: header.sd_shared2_0002_size,
+                sd_shared2_0003_size: header.sd_shared2_0003_size,
+                sd_shared2_0004_size: header.sd_shared2_0004_size,
+                sd_shared2_0005_size: header.sd_shared2_0005_size,
+                gamecode_rev: header.gamecode_rev,
+                file_type: header.file_type,
+                sd_public_sav_size: header.sd_public_sav_size,
+                sd_private_sav_size: header.sd_private_sav_size,
+                age_ratings: header.age_ratings,
+                sha1_hmac_arm9_with_secure_area: header.sha1_hmac_arm9_with_secure_area,
+                sha1_hmac_arm7: header.sha1_hmac_arm7,
+                sha1_hmac_digest: header.sha1_hmac_digest,
+                sha1_hmac_arm9i: header.sha1_hmac_arm9i,
+                sha1_hmac_arm7i: header.sha1_hmac_arm7i,
+                sha1_hmac_arm9: header.sha1_hmac_arm9,
+            }),
         }
     }
 
@@ -133,13 +244,44 @@ impl Header {
         let arm7 = rom.arm7();
         let arm9_offset = context.arm9_offset.expect("ARM9 offset must be known");
         let arm7_offset = context.arm7_offset.expect("ARM7 offset must be known");
+        let rom_size = context.rom_size.expect("ROM size must be known");
+        let (arm9i, arm7i) = if let Some(dsi_area) = rom.dsi_area() {
+            let arm9i = dsi_area.arm9i();
+            let arm7i = dsi_area.arm7i();
+            (
+                ProgramOffset {
+                    offset: context.arm9i_offset.expect("ARM9i offset must be known"),
+                    entry: arm9i.entry_function(),
+                    base_addr: arm9i.base_address(),
+                    size: arm9i.full_data().len() as u32,
+                },
+                ProgramOffset {
+                    offset: context.arm7i_offset.expect("ARM7i offset must be known"),
+                    entry: arm7i.entry_function(),
+                    base_addr: arm7i.base_address(),
+                    size: arm7i.full_data().len() as u32,
+                },
+            )
+        } else {
+            Default::default()
+        };
+        let modcrypt_area = |program: &ProgramOffset, dsi_program: Option<&DsiProgram>| {
+            dsi_program
+                .and_then(|dsi_program| dsi_program.offsets().modcrypt_area)
+                .map(|area| TableOffset { offset: program.offset + area.offset, size: area.size })
+                .unwrap_or_default()
+        };
+        let modcrypt_area_1 = modcrypt_area(&arm9i, rom.dsi_area().map(|dsi_area| dsi_area.arm9i()));
+        let modcrypt_area_2 = modcrypt_area(&arm7i, rom.dsi_area().map(|dsi_area| dsi_area.arm7i()));
+        let arm9i_build_info_offset = rom.dsi_area().map(|dsi_area| dsi_area.arm9i().build_info_offset()).unwrap_or(0);
+        let arm7i_build_info_offset = rom.dsi_area().map(|dsi_area| dsi_area.arm7i().build_info_offset()).unwrap_or(0);
         let mut header = raw::Header {
             title: AsciiArray::from_str(&self.original.title)?,
             gamecode: self.original.gamecode,
             makercode: self.original.makercode,
             unitcode: self.original.unitcode,
             seed_select: self.original.seed_select,
-            capacity: Capacity::from_size(context.rom_size.expect("ROM size must be known")),
+            capacity: Capacity::from_size(context.rom_size_dsi.unwrap_or(rom_size)),
             reserved0: [0; 7],
             dsi_flags: DsiFlags::new(),
             ds_flags: self.original.ds_flags,
@@ -172,8 +314,15 @@ impl Header {
             secure_area_delay: self.original.secure_area_delay,
             arm9_autoload_callback: context.arm9_autoload_callback.expect("ARM9 autoload callback must be known"),
             arm7_autoload_callback: context.arm7_autoload_callback.expect("ARM7 autoload callback must be known"),
-            secure_area_disable: 0,
-            rom_size_ds: context.rom_size.expect("ROM size must be known"),
+            secure_area_disable: if self.original.secure_area_disable {
+                let Some(key) = context.blowfish_key else {
+                    return SecureAreaDisableKeyNeededSnafu {}.fail();
+                };
+                raw::Header::encrypted_secure_area_disable(key, self.original.gamecode.to_le_u32())
+            } else {
+                0
+            },
+            rom_size_ds: rom_size,
             header_size: size_of::<raw::Header>() as u32,
             arm9_build_info_offset: if self.original.has_arm9_build_info_offset {
                 context.arm9_build_info_offset.map(|offset| offset + arm9_offset).unwrap_or(0)
@@ -181,8 +330,8 @@ impl Header {
                 0
             },
             arm7_build_info_offset: context.arm7_build_info_offset.map(|offset| offset + arm7_offset).unwrap_or(0),
-            ds_rom_region_end: 0,
-            dsi_rom_region_end: 0,
+            ds_rom_region_end: context.dsi_area_offset.map(|offset| (offset / 0x80000) as u16).unwrap_or(0),
+            dsi_rom_region_end: context.rom_size_dsi.map(|size| size.div_ceil(0x80000) as u16).unwrap_or(0),
             rom_nand_end: self.original.rom_nand_end,
             rw_nand_end: self.original.rw_nand_end,
             reserved1: [0; 0x18],
@@ -195,7 +344,7 @@ impl Header {
             debug_ram_addr: 0,
             reserved3: [0; 0x4],
             reserved4: [0; 0x10],
-            // The below fields are for DSi only and are not supported yet
+            // The below fields are for DSi only and get updated below
             memory_banks_wram: [0; 5],
             memory_banks_arm9: [0; 3],
             memory_banks_arm7: [0; 3],
@@ -204,12 +353,12 @@ impl Header {
             access_control: AccessControl::new(),
             arm7_scfg_ext7_setting: 0,
             dsi_flags_2: DsiFlags2::new(),
-            arm9i: ProgramOffset::default(),
-            arm7i: ProgramOffset::default(),
-            digest_ds_area: TableOffset::default(),
-            digest_dsi_area: TableOffset::default(),
-            digest_sector_hashtable: TableOffset::default(),
-            digest_block_hashtable: TableOffset::default(),
+            arm9i,
+            arm7i,
+            digest_ds_area: context.digest_ds_area.unwrap_or_default(),
+            digest_dsi_area: context.digest_dsi_area.unwrap_or_default(),
+            digest_sector_hashtable: context.digest_sector_hashtable.unwrap_or_default(),
+            digest_block_hashtable: context.digest_block_hashtable.unwrap_or_default(),
             digest_sector_size: 0,
             digest_sector_count: 0,
             banner_size: 0,
@@ -217,15 +366,23 @@ impl Header {
             sd_shared2_0001_size: 0,
             eula_version: 0,
             use_ratings: false,
-            rom_size_dsi: 0,
+            rom_size_dsi: context.rom_size_dsi.unwrap_or(0),
             sd_shared2_0002_size: 0,
             sd_shared2_0003_size: 0,
             sd_shared2_0004_size: 0,
             sd_shared2_0005_size: 0,
-            arm9i_build_info_offset: 0,
-            arm7i_build_info_offset: 0,
-            modcrypt_area_1: TableOffset::default(),
-            modcrypt_area_2: TableOffset::default(),
+            arm9i_build_info_offset: if arm9i_build_info_offset == 0 {
+                0
+            } else {
+                arm9i_build_info_offset.wrapping_add(arm9i.offset)
+            },
+            arm7i_build_info_offset: if arm7i_build_info_offset == 0 {
+                0
+            } else {
+                arm7i_build_info_offset.wrapping_add(arm7i.offset)
+            },
+            modcrypt_area_1,
+            modcrypt_area_2,
             gamecode_rev: AsciiArray([0; 4]),
             file_type: 0,
             sd_public_sav_size: 0,
@@ -255,6 +412,39 @@ impl Header {
             header.rsa_sha1.copy_from_slice(&ds_post_dsi.rsa_sha1);
         }
 
+        if let Some(dsi) = &self.dsi {
+            header.dsi_flags = dsi.dsi_flags;
+            header.memory_banks_wram = dsi.memory_banks_wram;
+            header.memory_banks_arm9 = dsi.memory_banks_arm9;
+            header.memory_banks_arm7 = dsi.memory_banks_arm7;
+            header.memory_bank_9 = dsi.memory_bank_9;
+            header.region_flags = dsi.region_flags;
+            header.access_control = dsi.access_control;
+            header.arm7_scfg_ext7_setting = dsi.arm7_scfg_ext7_setting;
+            header.digest_sector_size = dsi.digest_sector_size;
+            header.digest_sector_count = dsi.digest_sector_count;
+            header.banner_size = dsi.banner_size;
+            header.sd_shared2_0000_size = dsi.sd_shared2_0000_size;
+            header.sd_shared2_0001_size = dsi.sd_shared2_0001_size;
+            header.eula_version = dsi.eula_version;
+            header.use_ratings = dsi.use_ratings;
+            header.sd_shared2_0002_size = dsi.sd_shared2_0002_size;
+            header.sd_shared2_0003_size = dsi.sd_shared2_0003_size;
+            header.sd_shared2_0004_size = dsi.sd_shared2_0004_size;
+            header.sd_shared2_0005_size = dsi.sd_shared2_0005_size;
+            header.gamecode_rev = dsi.gamecode_rev;
+            header.file_type = dsi.file_type;
+            header.sd_public_sav_size = dsi.sd_public_sav_size;
+            header.sd_private_sav_size = dsi.sd_private_sav_size;
+            header.age_ratings = dsi.age_ratings;
+            header.sha1_hmac_arm9_with_secure_area = dsi.sha1_hmac_arm9_with_secure_area;
+            header.sha1_hmac_arm7 = dsi.sha1_hmac_arm7;
+            header.sha1_hmac_digest = dsi.sha1_hmac_digest;
+            header.sha1_hmac_arm9i = dsi.sha1_hmac_arm9i;
+            header.sha1_hmac_arm7i = dsi.sha1_hmac_arm7i;
+            header.sha1_hmac_arm9 = dsi.sha1_hmac_arm9;
+        }
+
         header.header_crc = CRC_16_MODBUS.checksum(&bytemuck::bytes_of(&header)[0..offset_of!(raw::Header, header_crc)]);
         Ok(header)
     }
@@ -268,3 +458,101 @@ impl Header {
         }
     }
 }
+
+#[cfg(test)]
+mod tests {
+    use bytemuck::Zeroable;
+
+    use super::*;
+    use crate::rom::{raw::NITROCODE, Arm7, Arm7Offsets, Arm9, Arm9Offsets, DsiArea, DsiProgramOffsets};
+
+    /// Returns a raw header with nonzero values in every field of [`HeaderDsPostDsi`] and [`HeaderDsi`].
+    fn raw_header() -> raw::Header {
+        let mut header = raw::Header::zeroed();
+        header.dsi_flags_2 = DsiFlags2::from_bits(0x0000_0011);
+        header.sha1_hmac_banner = [0x01; 0x14];
+        header.sha1_hmac_unk1 = [0x02; 0x14];
+        header.sha1_hmac_unk2 = [0x03; 0x14];
+        header.rsa_sha1 = [0x04; 0x80];
+
+        header.dsi_flags = DsiFlags::from_bits(0x03);
+        header.memory_banks_wram = [0x8d898581, 0x8c888480, 0x9c989490, 0x8d898581, 0x9d999591];
+        header.memory_banks_arm9 = [0x080037c0, 0x080037c1, 0x080037c2];
+        header.memory_banks_arm7 = [0x080037c3, 0x080037c4, 0x080037c5];
+        header.memory_bank_9 = 0x0300000f;
+        header.region_flags = RegionFlags::from_bits(0x0000_0005);
+        header.access_control = AccessControl::from_bits(0x8000_0038);
+        header.arm7_scfg_ext7_setting = 0x80040000;
+        header.digest_sector_size = 0x400;
+        header.digest_sector_count = 0x20;
+        header.banner_size = 0x23c0;
+        header.sd_shared2_0000_size = 0x05;
+        header.sd_shared2_0001_size = 0x06;
+        header.eula_version = 0x07;
+        header.use_ratings = true;
+        header.sd_shared2_0002_size = 0x08;
+        header.sd_shared2_0003_size = 0x09;
+        header.sd_shared2_0004_size = 0x0a;
+        header.sd_shared2_0005_size = 0x0b;
+        header.gamecode_rev = AsciiArray(*b"ECBA");
+        header.file_type = 0x00030004;
+        header.sd_public_sav_size = 0x4000;
+        header.sd_private_sav_size = 0x8000;
+        header.age_ratings = [0x0c; 0x10];
+        header.sha1_hmac_arm9_with_secure_area = [0x0d; 0x14];
+        header.sha1_hmac_arm7 = [0x0e; 0x14];
+        header.sha1_hmac_digest = [0x0f; 0x14];
+        header.sha1_hmac_arm9i = [0x10; 0x14];
+        header.sha1_hmac_arm7i = [0x11; 0x14];
+        header.sha1_hmac_arm9 = [0x12; 0x14];
+        header
+    }
+
+    #[test]
+    fn dsi_fields_roundtrip() {
+        let raw_header = raw_header();
+        let yaml = serde_yml::to_string(&Header::load_raw(&raw_header)).unwrap();
+        let header: Header = serde_yml::from_str(&yaml).unwrap();
+        assert!(header.dsi.is_some());
+
+        // Build info with the nitrocode, which the ARM9 program must have
+        let mut arm9 = vec![0u32; 0x100];
+        arm9[7] = NITROCODE;
+        arm9[8] = NITROCODE.swap_bytes();
+        let arm9_offsets = Arm9Offsets {
+            base_address: 0x2000000,
+            entry_function: 0x2000800,
+            build_info: 0,
+            autoload_callback: 0x2000900,
+            overlay_signatures: 0,
+        };
+        let arm9 = Arm9::new(bytemuck::cast_slice::<u32, u8>(&arm9), arm9_offsets).unwrap();
+        let arm7_offsets =
+            Arm7Offsets { base_address: 0x2380000, entry_function: 0x2380000, build_info: 0, autoload_callback: 0 };
+        let arm7 = Arm7::new(vec![0; 0x400], arm7_offsets);
+        let dsi_program = |size| {
+            let offsets = DsiProgramOffsets { base_address: 0x2400000, entry_function: 0, build_info: 0, modcrypt_area: None };
+            DsiProgram::new(vec![0; size], offsets)
+        };
+        let dsi_area = DsiArea::new(vec![0; 0x100], dsi_program(0x400), dsi_program(0x400));
+        let rom = Rom::with_programs(header, arm9, arm7, Some(dsi_area));
+
+        let context = BuildContext {
+            arm9_offset: Some(0x4000),
+            arm7_offset: Some(0x5000),
+            fnt_offset: Some(TableOffset { offset: 0x6000, size: 9 }),
+            fat_offset: Some(TableOffset { offset: 0x6200, size: 0 }),
+            banner_offset: Some(TableOffset { offset: 0x6200, size: 0x23c0 }),
+            arm9_autoload_callback: Some(0),
+            arm7_autoload_callback: Some(0),
+            rom_size: Some(0x8600),
+            dsi_area_offset: Some(0x80000),
+            arm9i_offset: Some(0x80100),
+            arm7i_offset: Some(0x80500),
+            rom_size_dsi: Some(0x80900),
+            ..Default::default()
+        };
+        let built = rom.header().build(&context, &rom).unwrap();
+        assert_eq!(serde_yml::to_string(&Header::load_raw(&built)).unwrap(), yaml);
+    }
+}
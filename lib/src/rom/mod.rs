@@ -4,9 +4,14 @@ mod autoload;
 mod banner;
 mod build_info;
 mod config;
+mod dsi;
+/// DSiWare titles.
+pub mod dsiware;
 mod file;
 mod header;
 mod logo;
+/// NARC archives.
+pub mod narc;
 mod overlay;
 mod overlay_table;
 /// Raw ROM access.
@@ -19,6 +24,7 @@ pub use autoload::*;
 pub use banner::*;
 pub use build_info::*;
 pub use config::*;
+pub use dsi::*;
 pub use file::*;
 pub use header::*;
 pub use logo::*;
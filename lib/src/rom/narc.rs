@@ -0,0 +1,400 @@
+use std::{backtrace::Backtrace, io, mem::size_of, path::Path};
+
+use serde::{Deserialize, Serialize};
+use snafu::Snafu;
+
+use super::{
+    raw::{FileAlloc, Fnt, RawFntError},
+    FileBuildError, FileParseError, FileSystem,
+};
+use crate::io::{create_dir_all, FileError};
+
+/// Magic number at the start of a NARC.
+pub const NARC_MAGIC: [u8; 4] = *b"NARC";
+
+/// Magic number of the file allocation chunk.
+const BTAF_MAGIC: [u8; 4] = *b"BTAF";
+/// Magic number of the file name chunk.
+const BTNF_MAGIC: [u8; 4] = *b"BTNF";
+/// Magic number of the file image chunk.
+const GMIF_MAGIC: [u8; 4] = *b"GMIF";
+/// Byte order mark of a little-endian NARC.
+const BYTE_ORDER_MARK: u16 = 0xfffe;
+/// Format version.
+const VERSION: u16 = 0x0100;
+/// Size of the NARC header.
+const HEADER_SIZE: usize = 0x10;
+/// Size of a chunk header, which consists of the magic number and chunk size.
+const CHUNK_HEADER_SIZE: usize = 8;
+/// Number of chunks in a NARC.
+const NUM_CHUNKS: u16 = 3;
+/// Size of the file count and reserved field at the start of the file allocation chunk.
+const BTAF_HEADER_SIZE: usize = 4;
+/// Alignment of chunks and files.
+const ALIGNMENT: usize = 4;
+/// FNT of a NARC without file names. It has only the root directory, whose subtable offset points to its own zero-valued
+/// first file ID, so the subtable is empty.
+const UNNAMED_FNT: [u8; 8] = [0x04, 0x00, 0x00, 0x00, 0x00, 0x00, 0x01, 0x00];
+
+/// Nitro archive (NARC), a container of files which uses the same FNT and FAT structures as the ROM. It consists of a
+/// header followed by three chunks: the FAT (BTAF), the FNT (BTNF) and the file data (GMIF).
+pub struct Narc<'a> {
+    files: FileSystem<'a>,
+    config: NarcConfig,
+}
+
+/// Layout details of a [`Narc`] which are not part of its files, needed to rebuild it exactly.
+#[derive(Serialize, Deserialize, Clone, PartialEq, Eq, Debug)]
+pub struct NarcConfig {
+    /// Whether the file names are stored in the FNT. If false, the files are named after their IDs.
+    pub named: bool,
+    /// Byte value to append after each file in the file image chunk.
+    pub file_padding_value: u8,
+    /// Byte value to append after the FNT.
+    pub fnt_padding_value: u8,
+}
+
+/// Errors related to [`Narc::parse`].
+#[derive(Debug, Snafu)]
+pub enum NarcParseError {
+    /// See [`RawFntError`].
+    #[snafu(transparent)]
+    RawFnt {
+        /// Source error.
+        source: RawFntError,
+    },
+    /// See [`FileParseError`].
+    #[snafu(transparent)]
+    FileParse {
+        /// Source error.
+        source: FileParseError,
+    },
+    /// Occurs when the input is too small to contain a header or chunk.
+    #[snafu(display("NARC must be at least {expected:#x} bytes but got {actual:#x} bytes:\n{backtrace}"))]
+    TooSmall {
+        /// Expected minimum size.
+        expected: usize,
+        /// Actual size.
+        actual: usize,
+        /// Backtrace to the source of the error.
+        backtrace: Backtrace,
+    },
+    /// Occurs when the header or a chunk has the wrong magic number.
+    #[snafu(display("expected {} at offset {offset:#x}:\n{backtrace}", String::from_utf8_lossy(expected)))]
+    InvalidMagic {
+        /// Expected magic number.
+        expected: [u8; 4],
+        /// Offset of the header or chunk.
+        offset: usize,
+        /// Backtrace to the source of the error.
+        backtrace: Backtrace,
+    },
+    /// Occurs when the NARC is not little-endian.
+    #[snafu(display("unsupported byte order mark {byte_order_mark:#06x}:\n{backtrace}"))]
+    InvalidByteOrder {
+        /// Byte order mark.
+        byte_order_mark: u16,
+        /// Backtrace to the source of the error.
+        backtrace: Backtrace,
+    },
+    /// Occurs when a chunk is smaller than its header or extends past the end of the input.
+    #[snafu(display("chunk at offset {offset:#x} has invalid size {size:#x}:\n{backtrace}"))]
+    InvalidChunkSize {
+        /// Offset of the chunk.
+        offset: usize,
+        /// Chunk size.
+        size: usize,
+        /// Backtrace to the source of the error.
+        backtrace: Backtrace,
+    },
+}
+
+/// Errors related to [`Narc::build`].
+#[derive(Debug, Snafu)]
+pub enum NarcBuildError {
+    /// See [`FileBuildError`].
+    #[snafu(transparent)]
+    FileBuild {
+        /// Source error.
+        source: FileBuildError,
+    },
+    /// See [`io::Error`].
+    #[snafu(transparent)]
+    Io {
+        /// Source error.
+        source: io::Error,
+    },
+}
+
+impl<'a> Narc<'a> {
+    /// Creates a new [`Narc`] from a file system. The files are stored in file ID order.
+    pub fn new(files: FileSystem<'a>, config: NarcConfig) -> Self {
+        Self { files, config }
+    }
+
+    /// Returns whether `data` starts with the NARC magic number.
+    pub fn is_narc(data: &[u8]) -> bool {
+        data.starts_with(&NARC_MAGIC)
+    }
+
+    /// Parses a NARC. The files borrow from `data`.
+    ///
+    /// # Errors
+    ///
+    /// This function will return an error if the header or a chunk is invalid, or if the FNT or FAT is malformed.
+    pub fn parse(data: &'a [u8]) -> Result<Self, NarcParseError> {
+        if data.len() < HEADER_SIZE {
+            return TooSmallSnafu { expected: HEADER_SIZE, actual: data.len() }.fail();
+        }
+        if !Self::is_narc(data) {
+            return InvalidMagicSnafu { expected: NARC_MAGIC, offset: 0usize }.fail();
+        }
+        let byte_order_mark = u16::from_le_bytes([data[4], data[5]]);
+        if byte_order_mark != BYTE_ORDER_MARK {
+            return InvalidByteOrderSnafu { byte_order_mark }.fail();
+        }
+        let header_size = u16::from_le_bytes([data[0xc], data[0xd]]) as usize;
+
+        let (btaf, btnf_offset) = Self::parse_chunk(data, header_size, BTAF_MAGIC)?;
+        let (btnf, gmif_offset) = Self::parse_chunk(data, btnf_offset, BTNF_MAGIC)?;
+        let (gmif, _) = Self::parse_chunk(data, gmif_offset, GMIF_MAGIC)?;
+
+        // --------------------- FAT ---------------------
+        if btaf.len() < BTAF_HEADER_SIZE {
+            return InvalidChunkSizeSnafu { offset: header_size, size: btaf.len() + CHUNK_HEADER_SIZE }.fail();
+        }
+        let num_files = u16::from_le_bytes([btaf[0], btaf[1]]) as usize;
+        let fat_size = num_files * size_of::<FileAlloc>();
+        let Some(fat) = btaf.get(BTAF_HEADER_SIZE..BTAF_HEADER_SIZE + fat_size) else {
+            return InvalidChunkSizeSnafu { offset: header_size, size: btaf.len() + CHUNK_HEADER_SIZE }.fail();
+        };
+        // The FAT is not necessarily aligned within the NARC, so it's read by value
+        let fat = fat
+            .chunks_exact(size_of::<FileAlloc>())
+            .map(|entry| FileAlloc {
+                start: u32::from_le_bytes(entry[0..4].try_into().unwrap()),
+                end: u32::from_le_bytes(entry[4..8].try_into().unwrap()),
+            })
+            .collect::<Vec<_>>();
+
+        // --------------------- FNT ---------------------
+        // Copy the FNT into an aligned buffer, since Fnt::borrow_from_slice requires alignment
+        let mut fnt_buffer = vec![0u32; btnf.len().div_ceil(4)];
+        bytemuck::cast_slice_mut::<u32, u8>(&mut fnt_buffer)[..btnf.len()].copy_from_slice(btnf);
+        let fnt = Fnt::borrow_from_slice(&bytemuck::cast_slice(&fnt_buffer)[..btnf.len()])?;
+
+        let named = fnt.subtables.len() > 1 || fnt.subtables[0].iter().next().is_some();
+        let files = if named { FileSystem::parse_data(&fnt, &fat, gmif, 0)? } else { FileSystem::parse_unnamed(&fat, gmif)? };
+
+        // The FNT always ends with a zero byte, so any other byte after it is padding
+        let fnt_padding_value = btnf.last().copied().unwrap_or(0);
+        let file_padding_value = fat
+            .iter()
+            .map(|alloc| alloc.end as usize)
+            .find(|&end| end % ALIGNMENT != 0 && end < gmif.len())
+            .map(|end| gmif[end])
+            .unwrap_or(0xff);
+
+        Ok(Self { files, config: NarcConfig { named, file_padding_value, fnt_padding_value } })
+    }
+
+    /// Returns the data of the chunk at `offset` and the offset of the next chunk.
+    fn parse_chunk(data: &[u8], offset: usize, magic: [u8; 4]) -> Result<(&[u8], usize), NarcParseError> {
+        let Some(header) = data.get(offset..offset + CHUNK_HEADER_SIZE) else {
+            return TooSmallSnafu { expected: offset + CHUNK_HEADER_SIZE, actual: data.len() }.fail();
+        };
+        if header[0..4] != magic {
+            return InvalidMagicSnafu { expected: magic, offset }.fail();
+        }
+        let size = u32::from_le_bytes(header[4..8].try_into().unwrap()) as usize;
+        if size < CHUNK_HEADER_SIZE || offset + size > data.len() {
+            return InvalidChunkSizeSnafu { offset, size }.fail();
+        }
+        Ok((&data[offset + CHUNK_HEADER_SIZE..offset + size], offset + size))
+    }
+
+    /// Loads the files of a NARC from the given directory, see [`FileSystem::load`].
+    ///
+    /// # Errors
+    ///
+    /// This function will return an error if an I/O operation fails.
+    pub fn load<P: AsRef<Path>>(path: P, config: NarcConfig) -> Result<Self, FileError> {
+        let files = FileSystem::load(path, 0)?;
+        Ok(Self { files, config })
+    }
+
+    /// Saves the files of this NARC into the given directory. Unnamed files are saved with the names given by
+    /// [`FileSystem::parse_unnamed`].
+    ///
+    /// # Errors
+    ///
+    /// This function will return an error if an I/O operation fails.
+    pub fn save<P: AsRef<Path>>(&self, path: P) -> Result<(), FileError> {
+        // Create the directory even if there are no files to save, so that it can be loaded again
+        create_dir_all(&path)?;
+        self.files.save(path)
+    }
+
+    /// Builds this NARC. A NARC which was parsed and left unmodified is rebuilt byte-for-byte, as long as its files were
+    /// laid out in file ID order like the official tools do.
+    ///
+    /// # Errors
+    ///
+    /// This function will return an error if a file name could not be encoded.
+    pub fn build(&self) -> Result<Box<[u8]>, NarcBuildError> {
+        let mut fnt = if self.config.named { self.files.build_fnt()?.build()?.into_vec() } else { UNNAMED_FNT.to_vec() };
+        fnt.resize(fnt.len().next_multiple_of(ALIGNMENT), self.config.fnt_padding_value);
+
+        let num_files = self.files.num_files();
+        let mut fat = Vec::with_capacity(num_files);
+        let mut file_image = vec![];
+        for id in 0..num_files {
+            let contents = self.files.file(id as u16).contents();
+            let start = file_image.len() as u32;
+            file_image.extend_from_slice(contents);
+            fat.push(FileAlloc { start, end: file_image.len() as u32 });
+            file_image.resize(file_image.len().next_multiple_of(ALIGNMENT), self.config.file_padding_value);
+        }
+
+        let btaf_size = CHUNK_HEADER_SIZE + BTAF_HEADER_SIZE + num_files * size_of::<FileAlloc>();
+        let btnf_size = CHUNK_HEADER_SIZE + fnt.len();
+        let gmif_size = CHUNK_HEADER_SIZE + file_image.len();
+        let size = HEADER_SIZE + btaf_size + btnf_size + gmif_size;
+
+        let mut bytes = Vec::with_capacity(size);
+        bytes.extend_from_slice(&NARC_MAGIC);
+        bytes.extend_from_slice(&BYTE_ORDER_MARK.to_le_bytes());
+        bytes.extend_from_slice(&VERSION.to_le_bytes());
+        bytes.extend_from_slice(&(size as u32).to_le_bytes());
+        bytes.extend_from_slice(&(HEADER_SIZE as u16).to_le_bytes());
+        bytes.extend_from_slice(&NUM_CHUNKS.to_le_bytes());
+
+        bytes.extend_from_slice(&BTAF_MAGIC);
+        bytes.extend_from_slice(&(btaf_size as u32).to_le_bytes());
+        bytes.extend_from_slice(&(num_files as u16).to_le_bytes());
+        bytes.extend_from_slice(&[0; 2]);
+        bytes.extend_from_slice(bytemuck::cast_slice(&fat));
+
+        bytes.extend_from_slice(&BTNF_MAGIC);
+        bytes.extend_from_slice(&(btnf_size as u32).to_le_bytes());
+        bytes.extend_from_slice(&fnt);
+
+        bytes.extend_from_slice(&GMIF_MAGIC);
+        bytes.extend_from_slice(&(gmif_size as u32).to_le_bytes());
+        bytes.extend_from_slice(&file_image);
+
+        Ok(bytes.into_boxed_slice())
+    }
+
+    /// Returns a reference to the files of this [`Narc`].
+    pub fn files(&self) -> &FileSystem<'a> {
+        &self.files
+    }
+
+    /// Returns a reference to the config of this [`Narc`].
+    pub fn config(&self) -> &NarcConfig {
+        &self.config
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn chunk(magic: [u8; 4], payload: &[u8]) -> Vec<u8> {
+        let size = (CHUNK_HEADER_SIZE + payload.len()) as u32;
+        [&magic[..], &size.to_le_bytes(), payload].concat()
+    }
+
+    /// Returns a NARC with the given chunks after the header.
+    fn narc(chunks: &[Vec<u8>]) -> Vec<u8> {
+        let size = (HEADER_SIZE + chunks.iter().map(Vec::len).sum::<usize>()) as u32;
+        let header = [&b"NARC\xfe\xff\x00\x01"[..], &size.to_le_bytes(), &[0x10, 0x00, 0x03, 0x00]].concat();
+        [header, chunks.concat()].concat()
+    }
+
+    /// Returns a NARC without file names, containing the files `abc` and `defgh`.
+    fn unnamed_narc() -> Vec<u8> {
+        #[rustfmt::skip]
+        let fat = [
+            0x02, 0x00, 0x00, 0x00,
+            0x00, 0x00, 0x00, 0x00, 0x03, 0x00, 0x00, 0x00,
+            0x04, 0x00, 0x00, 0x00, 0x09, 0x00, 0x00, 0x00,
+        ];
+        narc(&[chunk(BTAF_MAGIC, &fat), chunk(BTNF_MAGIC, &UNNAMED_FNT), chunk(GMIF_MAGIC, b"abc\xffdefgh\xff\xff\xff")])
+    }
+
+    /// Returns a NARC with the files `a.bin` and `d/b.bin`.
+    fn named_narc() -> Vec<u8> {
+        #[rustfmt::skip]
+        let fat = [
+            0x02, 0x00, 0x00, 0x00,
+            0x00, 0x00, 0x00, 0x00, 0x02, 0x00, 0x00, 0x00,
+            0x04, 0x00, 0x00, 0x00, 0x08, 0x00, 0x00, 0x00,
+        ];
+        #[rustfmt::skip]
+        let fnt = [
+            // Directory table: subtable offset, first file ID, directory count or parent ID
+            0x10, 0x00, 0x00, 0x00, 0x00, 0x00, 0x02, 0x00,
+            0x1b, 0x00, 0x00, 0x00, 0x01, 0x00, 0x00, 0xf0,
+            // Root subtable
+            0x05, b'a', b'.', b'b', b'i', b'n',
+            0x81, b'd', 0x01, 0xf0,
+            0x00,
+            // Subtable of d
+            0x05, b'b', b'.', b'b', b'i', b'n',
+            0x00,
+            // Padding
+            0xff, 0xff,
+        ];
+        narc(&[chunk(BTAF_MAGIC, &fat), chunk(BTNF_MAGIC, &fnt), chunk(GMIF_MAGIC, b"aa\x00\x00bbbb")])
+    }
+
+    #[test]
+    fn rebuild_unnamed() {
+        let data = unnamed_narc();
+        let narc = Narc::parse(&data).unwrap();
+        assert_eq!(narc.config(), &NarcConfig { named: false, file_padding_value: 0xff, fnt_padding_value: 0x00 });
+        assert_eq!(narc.files().num_files(), 2);
+        assert_eq!(narc.files().file(1).contents(), b"defgh");
+        assert_eq!(&*narc.build().unwrap(), data.as_slice());
+    }
+
+    #[test]
+    fn rebuild_named() {
+        let data = named_narc();
+        let narc = Narc::parse(&data).unwrap();
+        assert_eq!(narc.config(), &NarcConfig { named: true, file_padding_value: 0x00, fnt_padding_value: 0xff });
+        assert_eq!(narc.files().file(narc.files().find_path("a.bin").unwrap()).contents(), b"aa");
+        assert_eq!(narc.files().file(narc.files().find_path("d/b.bin").unwrap()).contents(), b"bbbb");
+        assert_eq!(&*narc.build().unwrap(), data.as_slice());
+    }
+
+    #[test]
+    fn parse_errors() {
+        let data = named_narc();
+        assert!(matches!(Narc::parse(&data[..8]), Err(NarcParseError::TooSmall { expected: 0x10, actual: 8, .. })));
+
+        // Truncated in the middle of a chunk, or of a chunk header
+        assert!(matches!(Narc::parse(&data[..data.len() - 1]), Err(NarcParseError::InvalidChunkSize { offset: 0x58, .. })));
+        assert!(matches!(Narc::parse(&data[..0x5c]), Err(NarcParseError::TooSmall { expected: 0x60, .. })));
+
+        let mut invalid = data.clone();
+        invalid[4] = 0xff;
+        assert!(matches!(Narc::parse(&invalid), Err(NarcParseError::InvalidByteOrder { byte_order_mark: 0xffff, .. })));
+
+        let mut invalid = data.clone();
+        invalid[0x2c] = b'X';
+        assert!(matches!(Narc::parse(&invalid), Err(NarcParseError::InvalidMagic { offset: 0x2c, .. })));
+
+        // The FAT claims more files than fit in its chunk
+        let mut invalid = data.clone();
+        invalid[0x18] = 3;
+        assert!(matches!(Narc::parse(&invalid), Err(NarcParseError::InvalidChunkSize { offset: 0x10, .. })));
+
+        // A chunk size smaller than its own header
+        let mut invalid = data;
+        invalid[0x14] = 4;
+        assert!(matches!(Narc::parse(&invalid), Err(NarcParseError::InvalidChunkSize { offset: 0x10, size: 4, .. })));
+    }
+}
@@ -8,7 +8,10 @@ use super::{
     Arm9, Arm9OverlaySignaturesError,
 };
 use crate::{
-    compress::lz77::{Lz77, Lz77DecompressError},
+    compress::{
+        cache::CompressionCache,
+        lz77::{Lz77, Lz77DecompressError, Lz77Strategy},
+    },
     crypto::hmac_sha1::HmacSha1,
 };
 
@@ -222,10 +225,33 @@ impl<'a> Overlay<'a> {
     ///
     /// This function will return an error if an I/O operation fails.
     pub fn compress(&mut self) -> Result<(), io::Error> {
+        self.compress_with_strategy(Lz77Strategy::Greedy)
+    }
+
+    /// Compresses this [`Overlay`] like [`Self::compress`], but chooses LZ77 tokens according to `strategy`.
+    ///
+    /// # Errors
+    ///
+    /// This function will return an error if an I/O operation fails.
+    pub fn compress_with_strategy(&mut self, strategy: Lz77Strategy) -> Result<(), io::Error> {
+        self.compress_with_cache(strategy, None)
+    }
+
+    /// Compresses this [`Overlay`] like [`Self::compress_with_strategy`], but reuses the result from `cache` if this overlay
+    /// has been compressed before.
+    ///
+    /// # Errors
+    ///
+    /// This function will return an error if an I/O operation fails.
+    pub fn compress_with_cache(&mut self, strategy: Lz77Strategy, cache: Option<&CompressionCache>) -> Result<(), io::Error> {
         if self.is_compressed() {
             return Ok(());
         }
-        self.data = LZ77.compress(&self.data, 0)?.into_vec().into();
+        let data = match cache {
+            Some(cache) => cache.lz77(&self.data, 0, strategy)?,
+            None => LZ77.compress_with_strategy(&self.data, 0, strategy)?,
+        };
+        self.data = data.into_vec().into();
         self.info.compressed = true;
         Ok(())
     }
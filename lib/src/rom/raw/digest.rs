@@ -0,0 +1,318 @@
+use std::ops::Range;
+
+use sha1::{Digest as _, Sha1};
+use snafu::{Backtrace, Snafu};
+
+use super::{Header, RawHeaderError, TableOffset};
+use crate::crypto::hmac_sha1::HmacSha1;
+
+/// Size of one SHA1-HMAC in the digest hashtables.
+const HASH_SIZE: usize = 0x14;
+
+/// Layout of the digest areas and hashtables in DSi-enhanced and DSi-exclusive ROMs. Every sector in the DS and DSi areas
+/// is hashed into the sector hashtable, and every block of sector hashes is hashed into the block hashtable.
+#[derive(Clone, Copy)]
+pub struct Digest {
+    /// DS area digest range.
+    pub ds_area: TableOffset,
+    /// DSi area digest range.
+    pub dsi_area: TableOffset,
+    /// Digest sector size.
+    pub sector_size: u32,
+    /// Number of sectors per block.
+    pub sectors_per_block: u32,
+}
+
+/// Errors related to [`Digest`].
+#[derive(Debug, Snafu)]
+pub enum RawDigestError {
+    /// See [`RawHeaderError`].
+    #[snafu(transparent)]
+    RawHeader {
+        /// Source error.
+        source: RawHeaderError,
+    },
+    /// Occurs when the ROM has no digest, i.e. the sector size or sector count is zero.
+    #[snafu(display("the ROM has no digest hashtables:\n{backtrace}"))]
+    NoDigest {
+        /// Backtrace to the source of the error.
+        backtrace: Backtrace,
+    },
+    /// Occurs when a digest area or hashtable goes past the end of the ROM.
+    #[snafu(display("digest range {start:#x}..{end:#x} is out of bounds for ROM of size {size:#x}:\n{backtrace}"))]
+    OutOfBounds {
+        /// Start of the range.
+        start: usize,
+        /// End of the range.
+        end: usize,
+        /// ROM size.
+        size: usize,
+        /// Backtrace to the source of the error.
+        backtrace: Backtrace,
+    },
+}
+
+/// Sector and block hashtables of a [`Digest`].
+#[derive(Clone)]
+pub struct DigestHashtables {
+    /// Sector hashtable, with one SHA1-HMAC per sector.
+    pub sector: Box<[u8]>,
+    /// Block hashtable, with one SHA1-HMAC per block of sector hashes.
+    pub block: Box<[u8]>,
+    /// Plain SHA1 of the sectors which the hashtables were computed from, see [`Digest::sectors_sha1`]. It tells whether the
+    /// hashtables are still valid without knowing the HMAC-SHA1 key.
+    pub sectors_sha1: [u8; 0x14],
+}
+
+/// Result of [`Rom::verify_digest`](super::Rom::verify_digest).
+pub struct DigestVerification {
+    /// ROM offsets of sectors which don't match their hash in the sector hashtable.
+    pub mismatching_sectors: Vec<u32>,
+    /// Indices of blocks which don't match their hash in the block hashtable.
+    pub mismatching_blocks: Vec<usize>,
+    /// Whether [`Header::sha1_hmac_digest`] matches the block hashtable.
+    pub digest_matches: bool,
+}
+
+impl DigestVerification {
+    /// Returns `true` if all hashes matched.
+    pub fn is_valid(&self) -> bool {
+        self.mismatching_sectors.is_empty() && self.mismatching_blocks.is_empty() && self.digest_matches
+    }
+}
+
+impl Digest {
+    /// Loads the digest layout from a header. Returns `None` if the header has no digest.
+    pub fn from_header(header: &Header) -> Option<Self> {
+        if header.digest_sector_size == 0 || header.digest_sector_count == 0 {
+            return None;
+        }
+        Some(Self {
+            ds_area: header.digest_ds_area,
+            dsi_area: header.digest_dsi_area,
+            sector_size: header.digest_sector_size,
+            sectors_per_block: header.digest_sector_count,
+        })
+    }
+
+    /// Returns the ROM ranges of every sector in the DS and DSi areas, in hashtable order.
+    pub fn sectors(&self) -> impl Iterator<Item = Range<usize>> {
+        let sector_size = self.sector_size as usize;
+        [self.ds_area, self.dsi_area].into_iter().flat_map(move |area| {
+            let start = area.offset as usize;
+            let end = start + area.size as usize;
+            (start..end).step_by(sector_size).map(move |sector| sector..(sector + sector_size).min(end))
+        })
+    }
+
+    /// Returns the number of sectors in the DS and DSi areas.
+    pub fn num_sectors(&self) -> usize {
+        let sector_size = self.sector_size as usize;
+        (self.ds_area.size as usize).div_ceil(sector_size) + (self.dsi_area.size as usize).div_ceil(sector_size)
+    }
+
+    /// Returns the size of the sector hashtable.
+    pub fn sector_hashtable_size(&self) -> usize {
+        self.num_sectors() * HASH_SIZE
+    }
+
+    /// Returns the size of the block hashtable.
+    pub fn block_hashtable_size(&self) -> usize {
+        self.num_sectors().div_ceil(self.sectors_per_block as usize) * HASH_SIZE
+    }
+
+    /// Computes the sector hashtable of the given ROM data.
+    ///
+    /// # Errors
+    ///
+    /// This function will return an error if a sector is out of bounds.
+    pub fn compute_sector_hashtable(&self, rom: &[u8], hmac_sha1: &HmacSha1) -> Result<Vec<u8>, RawDigestError> {
+        let mut hashtable = Vec::with_capacity(self.sector_hashtable_size());
+        for sector in self.sectors() {
+            if sector.end > rom.len() {
+                return OutOfBoundsSnafu { start: sector.start, end: sector.end, size: rom.len() }.fail();
+            }
+            hashtable.extend(hmac_sha1.compute(&rom[sector]));
+        }
+        Ok(hashtable)
+    }
+
+    /// Computes a plain SHA1 over every sector in the DS and DSi areas, in hashtable order.
+    ///
+    /// # Errors
+    ///
+    /// This function will return an error if a sector is out of bounds.
+    pub fn sectors_sha1(&self, rom: &[u8]) -> Result<[u8; 0x14], RawDigestError> {
+        let mut sha1 = Sha1::new();
+        for sector in self.sectors() {
+            if sector.end > rom.len() {
+                return OutOfBoundsSnafu { start: sector.start, end: sector.end, size: rom.len() }.fail();
+            }
+            sha1.update(&rom[sector]);
+        }
+        Ok(sha1.finalize().into())
+    }
+
+    /// Verifies the sector hashtable, block hashtable and [`Header::sha1_hmac_digest`] of the given ROM data.
+    ///
+    /// # Errors
+    ///
+    /// This function will return an error if the header has no digest, or if a sector or hashtable is out of bounds.
+    pub fn verify(rom: &[u8], header: &Header, hmac_sha1: &HmacSha1) -> Result<DigestVerification, RawDigestError> {
+        let Some(digest) = Self::from_header(header) else {
+            return NoDigestSnafu {}.fail();
+        };
+        let sector_hashtable = Self::table(rom, header.digest_sector_hashtable)?;
+        let block_hashtable = Self::table(rom, header.digest_block_hashtable)?;
+
+        let mut mismatching_sectors = vec![];
+        for (index, sector) in digest.sectors().enumerate() {
+            if sector.end > rom.len() {
+                return OutOfBoundsSnafu { start: sector.start, end: sector.end, size: rom.len() }.fail();
+            }
+            let expected = sector_hashtable.get(index * HASH_SIZE..(index + 1) * HASH_SIZE);
+            if expected != Some(hmac_sha1.compute(&rom[sector.clone()]).as_slice()) {
+                mismatching_sectors.push(sector.start as u32);
+            }
+        }
+
+        let computed_blocks = digest.compute_block_hashtable(sector_hashtable, hmac_sha1);
+        let mismatching_blocks = computed_blocks
+            .chunks(HASH_SIZE)
+            .enumerate()
+            .filter(|(index, hash)| block_hashtable.get(index * HASH_SIZE..(index + 1) * HASH_SIZE) != Some(*hash))
+            .map(|(index, _)| index)
+            .collect();
+
+        let digest_matches = hmac_sha1.compute(block_hashtable) == header.sha1_hmac_digest;
+
+        Ok(DigestVerification { mismatching_sectors, mismatching_blocks, digest_matches })
+    }
+
+    /// Returns the sector and block hashtables of the given ROM data.
+    ///
+    /// # Errors
+    ///
+    /// This function will return an error if the header has no digest, or if a sector or hashtable is out of bounds.
+    pub fn hashtables(rom: &[u8], header: &Header) -> Result<DigestHashtables, RawDigestError> {
+        let Some(digest) = Self::from_header(header) else {
+            return NoDigestSnafu {}.fail();
+        };
+        Ok(DigestHashtables {
+            sector: Self::table(rom, header.digest_sector_hashtable)?.into(),
+            block: Self::table(rom, header.digest_block_hashtable)?.into(),
+            sectors_sha1: digest.sectors_sha1(rom)?,
+        })
+    }
+
+    fn table(rom: &[u8], table: TableOffset) -> Result<&[u8], RawDigestError> {
+        let start = table.offset as usize;
+        let end = start + table.size as usize;
+        if end > rom.len() {
+            return OutOfBoundsSnafu { start, end, size: rom.len() }.fail();
+        }
+        Ok(&rom[start..end])
+    }
+
+    /// Computes the block hashtable from a sector hashtable.
+    pub fn compute_block_hashtable(&self, sector_hashtable: &[u8], hmac_sha1: &HmacSha1) -> Vec<u8> {
+        let block_size = self.sectors_per_block as usize * HASH_SIZE;
+        sector_hashtable.chunks(block_size).flat_map(|block| hmac_sha1.compute(block)).collect()
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use bytemuck::Zeroable;
+
+    use super::*;
+    use crate::rom::raw::test_fixtures::{build_rom, write_header};
+
+    const SECTOR_HASHTABLE: usize = 0x7000;
+    const BLOCK_HASHTABLE: usize = 0x7100;
+
+    /// Returns ROM data with a DS area, a DSi area and their hashtables, along with its header.
+    fn build_digest_rom(hmac_sha1: &HmacSha1) -> (Vec<u8>, Header) {
+        let mut words = build_rom(0x8000, &Header::zeroed());
+        let rom: &mut [u8] = bytemuck::cast_slice_mut(&mut words);
+        let digest = Digest {
+            ds_area: TableOffset { offset: 0x4000, size: 0x2000 },
+            dsi_area: TableOffset { offset: 0x6000, size: 0xc00 },
+            sector_size: 0x400,
+            sectors_per_block: 4,
+        };
+
+        let sector_hashtable = digest.compute_sector_hashtable(rom, hmac_sha1).unwrap();
+        assert_eq!(sector_hashtable.len(), digest.sector_hashtable_size());
+        rom[SECTOR_HASHTABLE..SECTOR_HASHTABLE + sector_hashtable.len()].copy_from_slice(&sector_hashtable);
+        let block_hashtable = digest.compute_block_hashtable(&sector_hashtable, hmac_sha1);
+        assert_eq!(block_hashtable.len(), digest.block_hashtable_size());
+        rom[BLOCK_HASHTABLE..BLOCK_HASHTABLE + block_hashtable.len()].copy_from_slice(&block_hashtable);
+
+        let mut header = Header::zeroed();
+        header.digest_ds_area = digest.ds_area;
+        header.digest_dsi_area = digest.dsi_area;
+        header.digest_sector_size = digest.sector_size;
+        header.digest_sector_count = digest.sectors_per_block;
+        header.digest_sector_hashtable =
+            TableOffset { offset: SECTOR_HASHTABLE as u32, size: digest.sector_hashtable_size() as u32 };
+        header.digest_block_hashtable =
+            TableOffset { offset: BLOCK_HASHTABLE as u32, size: digest.block_hashtable_size() as u32 };
+        header.sha1_hmac_digest = hmac_sha1.compute(&block_hashtable);
+        write_header(&mut words, &header);
+        (bytemuck::cast_slice(&words).to_vec(), header)
+    }
+
+    #[test]
+    fn verify_built_hashtables() {
+        let hmac_sha1 = HmacSha1::new([0x5a; 64]);
+        let (rom, header) = build_digest_rom(&hmac_sha1);
+
+        let verification = Digest::verify(&rom, &header, &hmac_sha1).unwrap();
+        assert!(verification.is_valid());
+
+        let hashtables = Digest::hashtables(&rom, &header).unwrap();
+        assert_eq!(hashtables.sector.len(), 11 * HASH_SIZE);
+        assert_eq!(hashtables.block.len(), 3 * HASH_SIZE);
+    }
+
+    #[test]
+    fn sectors_sha1_tracks_sectors_only() {
+        let hmac_sha1 = HmacSha1::new([0x5a; 64]);
+        let (mut rom, header) = build_digest_rom(&hmac_sha1);
+        let sectors_sha1 = Digest::hashtables(&rom, &header).unwrap().sectors_sha1;
+
+        // Bytes outside of the digest areas, such as the hashtables themselves, are not covered
+        rom[SECTOR_HASHTABLE] ^= 1;
+        assert_eq!(Digest::hashtables(&rom, &header).unwrap().sectors_sha1, sectors_sha1);
+
+        rom[0x6345] ^= 1;
+        assert_ne!(Digest::hashtables(&rom, &header).unwrap().sectors_sha1, sectors_sha1);
+    }
+
+    #[test]
+    fn verify_reports_stale_sector() {
+        let hmac_sha1 = HmacSha1::new([0x5a; 64]);
+        let (mut rom, header) = build_digest_rom(&hmac_sha1);
+        rom[0x6345] ^= 1;
+
+        let verification = Digest::verify(&rom, &header, &hmac_sha1).unwrap();
+        assert!(!verification.is_valid());
+        assert_eq!(verification.mismatching_sectors, vec![0x6000]);
+        assert!(verification.mismatching_blocks.is_empty());
+        assert!(verification.digest_matches);
+    }
+
+    #[test]
+    fn verify_reports_stale_block() {
+        let hmac_sha1 = HmacSha1::new([0x5a; 64]);
+        let (mut rom, header) = build_digest_rom(&hmac_sha1);
+        // Corrupt the hash of the fifth sector, which is in the second block
+        rom[SECTOR_HASHTABLE + 4 * HASH_SIZE] ^= 1;
+
+        let verification = Digest::verify(&rom, &header, &hmac_sha1).unwrap();
+        assert_eq!(verification.mismatching_sectors, vec![0x5000]);
+        assert_eq!(verification.mismatching_blocks, vec![1]);
+        assert!(verification.digest_matches);
+    }
+}
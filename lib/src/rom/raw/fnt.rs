@@ -53,6 +53,26 @@ pub enum RawFntError {
         /// Backtrace to the source of the error.
         backtrace: Backtrace,
     },
+    /// Occurs when the directory count doesn't fit in the input.
+    #[snafu(display("file name table of {size:#x} bytes can't contain {num_dirs} directories:\n{backtrace}"))]
+    InvalidDirectoryCount {
+        /// Number of directories.
+        num_dirs: usize,
+        /// Input size.
+        size: usize,
+        /// Backtrace to the source of the error.
+        backtrace: Backtrace,
+    },
+    /// Occurs when a subtable starts outside of the input.
+    #[snafu(display("subtable offset {offset:#x} is out of bounds for file name table of {size:#x} bytes:\n{backtrace}"))]
+    InvalidSubtableOffset {
+        /// Subtable offset.
+        offset: u32,
+        /// Input size.
+        size: usize,
+        /// Backtrace to the source of the error.
+        backtrace: Backtrace,
+    },
     /// Occurs when the input is not aligned enough.
     #[snafu(display("expected {expected}-alignment but got {actual}-alignment:\n{backtrace}"))]
     Misaligned {
@@ -89,7 +109,8 @@ impl<'a> Fnt<'a> {
     ///
     /// # Errors
     ///
-    /// This function will return an error if the input is too small or not aligned enough.
+    /// This function will return an error if the input is too small, not aligned enough, or if a directory is out of
+    /// bounds.
     pub fn borrow_from_slice(data: &'a [u8]) -> Result<Self, RawFntError> {
         Self::check_size(data)?;
         let addr = data as *const [u8] as *const () as usize;
@@ -102,11 +123,17 @@ impl<'a> Fnt<'a> {
 
         // the root entry has no parent, so `parent_id` is instead the number of directories
         let num_dirs = root_dir.parent_id as usize;
+        if num_dirs == 0 || size * num_dirs > data.len() {
+            return InvalidDirectoryCountSnafu { num_dirs, size: data.len() }.fail();
+        }
         let directories: &[FntDirectory] = Self::handle_pod_cast(bytemuck::try_cast_slice(&data[..size * num_dirs]));
 
         let mut subtables = Vec::with_capacity(directories.len());
         for directory in directories {
             let start = directory.subtable_offset as usize;
+            if start >= data.len() {
+                return InvalidSubtableOffsetSnafu { offset: directory.subtable_offset, size: data.len() }.fail();
+            }
             subtables.push(FntSubtable { directory: Cow::Borrowed(directory), data: Cow::Borrowed(&data[start..]) });
         }
 
@@ -169,7 +196,8 @@ impl<'a> Iterator for IterFntSubtable<'a> {
         let subdir = self.data[0] & 0x80 != 0;
         self.data = &self.data[1..];
 
-        let (name, _, had_errors) = SHIFT_JIS.decode(&self.data[..length]);
+        // Stop at truncated entries instead of reading past the subtable
+        let (name, _, had_errors) = SHIFT_JIS.decode(self.data.get(..length)?);
         if had_errors {
             log::warn!("The file name '{name}' contains a malformed byte sequence");
         }
@@ -177,12 +205,13 @@ impl<'a> Iterator for IterFntSubtable<'a> {
         self.data = &self.data[length..];
 
         let id = if subdir {
-            let id = u16::from_le_bytes([self.data[0], self.data[1]]);
+            let &[low, high, ..] = self.data else { return None };
+            let id = u16::from_le_bytes([low, high]);
             self.data = &self.data[2..];
             id
         } else {
             let id = self.id;
-            self.id += 1;
+            self.id = self.id.wrapping_add(1);
             id
         };
 
@@ -1,6 +1,6 @@
 use std::{
     fmt::Display,
-    mem::{align_of, size_of},
+    mem::{align_of, offset_of, size_of},
 };
 
 use bitfield_struct::bitfield;
@@ -9,10 +9,17 @@ use serde::{Deserialize, Serialize};
 use snafu::{Backtrace, Snafu};
 
 use crate::{
+    crypto::{
+        blowfish::{Blowfish, BlowfishKey, BlowfishLevel},
+        rsa::RsaVerifier,
+    },
     rom::Logo,
     str::{AsciiArray, BlobSize},
 };
 
+/// Plaintext of the marker in [`Header::secure_area_disable`].
+pub const SECURE_AREA_DISABLE_MARKER: &[u8; 8] = b"NmMdOnly";
+
 /// ROM header.
 #[repr(C)]
 #[derive(Clone, Copy)]
@@ -209,6 +216,7 @@ unsafe impl Pod for Header {}
 
 /// Errors related to [`Header`].
 #[derive(Debug, Snafu)]
+#[snafu(visibility(pub(crate)))]
 pub enum RawHeaderError {
     /// Occurs when the input is too small to contain a header.
     #[snafu(display("expected {expected:#x} bytes for header but had only {actual:#x}:\n{backtrace}"))]
@@ -230,6 +238,20 @@ pub enum RawHeaderError {
         /// Backtrace to the source of the error.
         backtrace: Backtrace,
     },
+    /// Occurs when a section of the DSi area is out of bounds of the ROM.
+    #[snafu(display("{section} spans {start:#x}..{end:#x} but the ROM is only {size:#x} bytes:\n{backtrace}"))]
+    DsiAreaOutOfBounds {
+        /// Name of the section.
+        section: &'static str,
+        /// Start offset of the section.
+        start: usize,
+        /// End offset of the section.
+        end: usize,
+        /// Size of the ROM.
+        size: usize,
+        /// Backtrace to the source of the error.
+        backtrace: Backtrace,
+    },
 }
 
 impl Header {
@@ -287,12 +309,67 @@ impl Header {
         Self::handle_pod_cast(bytemuck::try_from_bytes_mut(&mut data[..size]), addr)
     }
 
+    /// Returns the "NmMdOnly" marker encrypted with the level 3 KEY1 key, to be stored in [`Self::secure_area_disable`]. This
+    /// is the same key level that encrypts the secure area after its first block, see [`crate::rom::Arm9`].
+    pub fn encrypted_secure_area_disable(key: &BlowfishKey, gamecode: u32) -> u64 {
+        let mut marker = *SECURE_AREA_DISABLE_MARKER;
+        let blowfish = Blowfish::new(key, gamecode, BlowfishLevel::Level3);
+        blowfish.encrypt(&mut marker).unwrap();
+        u64::from_le_bytes(marker)
+    }
+
+    /// Returns whether [`Self::secure_area_disable`] contains the encrypted "NmMdOnly" marker.
+    pub fn is_secure_area_disabled(&self, key: &BlowfishKey) -> bool {
+        let secure_area_disable = self.secure_area_disable;
+        secure_area_disable != 0 && secure_area_disable == Self::encrypted_secure_area_disable(key, self.gamecode.to_le_u32())
+    }
+
+    /// Returns the header data which is signed by [`Self::rsa_sha1`], i.e. everything up to [`Self::debug_args`].
+    pub fn rsa_signed_data(&self) -> &[u8] {
+        &bytemuck::bytes_of(self)[..offset_of!(Self, debug_args)]
+    }
+
+    /// Verifies [`Self::rsa_sha1`] against the keys in `verifier`.
+    pub fn verify_rsa_signature(&self, verifier: &RsaVerifier) -> HeaderSignature {
+        if self.rsa_sha1.iter().all(|&b| b == 0) {
+            return HeaderSignature::Missing;
+        }
+        match verifier.verify_sha1(self.rsa_signed_data(), &self.rsa_sha1) {
+            Some(key_name) => HeaderSignature::Valid { key_name: key_name.to_string() },
+            None => HeaderSignature::Invalid,
+        }
+    }
+
     /// Creates a [`DisplayHeader`] which implements [`Display`].
     pub fn display(&self, indent: usize) -> DisplayHeader {
         DisplayHeader { header: self, indent }
     }
 }
 
+/// Result of [`Header::verify_rsa_signature`].
+#[derive(Clone, PartialEq, Eq, Debug)]
+pub enum HeaderSignature {
+    /// The header has no RSA signature.
+    Missing,
+    /// The signature matches one of the keys.
+    Valid {
+        /// Name of the matching key.
+        key_name: String,
+    },
+    /// The signature doesn't match any of the keys, so the header has been modified or the key is unknown.
+    Invalid,
+}
+
+impl Display for HeaderSignature {
+    fn fmt(&self, f: &mut std::fmt::Formatter<'_>) -> std::fmt::Result {
+        match self {
+            HeaderSignature::Missing => write!(f, "Missing"),
+            HeaderSignature::Valid { key_name } => write!(f, "Valid ({key_name})"),
+            HeaderSignature::Invalid => write!(f, "Invalid"),
+        }
+    }
+}
+
 /// Can be used to display values inside [`Header`].
 pub struct DisplayHeader<'a> {
     header: &'a Header,
@@ -344,6 +421,18 @@ impl Display for DisplayHeader<'_> {
         writeln!(f, "{i}Debug size .............. : {:#x}", header.debug_size)?;
         writeln!(f, "{i}Debug RAM address ....... : {:#x}", header.debug_ram_addr)?;
         writeln!(f, "{i}Header size ............. : {:#x}", header.header_size)?;
+        if header.arm9i.offset != 0 {
+            writeln!(
+                f,
+                "{i}ROM size (DSi) .......... : {} ({:#x})",
+                BlobSize(header.rom_size_dsi as usize),
+                header.rom_size_dsi
+            )?;
+            write!(f, "{i}ARM9i program\n{}", header.arm9i.display(self.indent + 2))?;
+            writeln!(f, "{i}ARM9i build info offset . : {:#x}", header.arm9i_build_info_offset)?;
+            write!(f, "{i}ARM7i program\n{}", header.arm7i.display(self.indent + 2))?;
+            writeln!(f, "{i}ARM7i build info offset . : {:#x}", header.arm7i_build_info_offset)?;
+        }
         Ok(())
     }
 }
@@ -389,13 +478,14 @@ impl Display for Capacity {
 
 /// DSi-specific flags.
 #[bitfield(u8)]
+#[derive(Serialize, Deserialize)]
 pub struct DsiFlags {
     /// If `true`, the ROM has a DSi area.
-    dsi_title: bool,
+    pub dsi_title: bool,
     /// If `true`, the ROM is modcrypted.
-    modcrypted: bool,
+    pub modcrypted: bool,
     /// If `true`, use debug key, otherwise retail key.
-    modcrypt_debug_key: bool,
+    pub modcrypt_debug_key: bool,
     /// Disable debug?
     disable_debug: bool,
     /// Reserved, zero.
@@ -547,6 +637,7 @@ impl Display for Delay {
 
 /// Region flags, only used in DSi titles.
 #[bitfield(u32)]
+#[derive(Serialize, Deserialize)]
 pub struct RegionFlags {
     japan: bool,
     usa: bool,
@@ -579,6 +670,7 @@ impl Display for RegionFlags {
 
 /// Access control flags.
 #[bitfield(u32)]
+#[derive(Serialize, Deserialize)]
 pub struct AccessControl {
     common_client_key: bool,
     aes_slot_b: bool,
@@ -621,3 +713,29 @@ pub struct DsiFlags2 {
     #[bits(24)]
     reserved: u32,
 }
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn secure_area_disable_marker() {
+        let key = BlowfishKey::try_from([0x5a; size_of::<BlowfishKey>()].as_slice()).unwrap();
+        let mut header = Header::zeroed();
+        header.gamecode = AsciiArray(*b"ABCE");
+        let gamecode = header.gamecode.to_le_u32();
+        assert!(!header.is_secure_area_disabled(&key));
+
+        header.secure_area_disable = Header::encrypted_secure_area_disable(&key, gamecode);
+        assert!(header.is_secure_area_disabled(&key));
+
+        let mut marker = header.secure_area_disable.to_le_bytes();
+        Blowfish::new(&key, gamecode, BlowfishLevel::Level3).decrypt(&mut marker).unwrap();
+        assert_eq!(&marker, SECURE_AREA_DISABLE_MARKER);
+
+        let mut marker = *SECURE_AREA_DISABLE_MARKER;
+        Blowfish::new(&key, gamecode, BlowfishLevel::Level2).encrypt(&mut marker).unwrap();
+        header.secure_area_disable = u64::from_le_bytes(marker);
+        assert!(!header.is_secure_area_disabled(&key));
+    }
+}
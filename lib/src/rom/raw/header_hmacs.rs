@@ -0,0 +1,217 @@
+use std::{fmt::Display, ops::Range};
+
+use snafu::{Backtrace, Snafu};
+
+use super::{Banner, Header, HeaderVersion, RawBannerError, RawHeaderError};
+use crate::crypto::hmac_sha1::HmacSha1;
+
+/// Size of the secure area at the start of the ARM9 program.
+const SECURE_AREA_SIZE: usize = 0x4000;
+
+/// SHA1-HMACs in the header which are computed from other parts of the ROM.
+#[derive(Clone, Copy)]
+pub struct HeaderHmacs {
+    /// SHA1-HMAC of the ARM9 program, including the encrypted secure area.
+    pub arm9_with_secure_area: [u8; 0x14],
+    /// SHA1-HMAC of the ARM7 program.
+    pub arm7: [u8; 0x14],
+    /// SHA1-HMAC of the banner.
+    pub banner: [u8; 0x14],
+    /// SHA1-HMAC of the ARM9 program, excluding the secure area.
+    pub arm9: [u8; 0x14],
+}
+
+/// Kinds of [`HeaderHmacs`].
+#[derive(Clone, Copy, PartialEq, Eq, Debug)]
+pub enum HeaderHmacKind {
+    /// [`Header::sha1_hmac_arm9_with_secure_area`]
+    Arm9WithSecureArea,
+    /// [`Header::sha1_hmac_arm7`]
+    Arm7,
+    /// [`Header::sha1_hmac_banner`]
+    Banner,
+    /// [`Header::sha1_hmac_arm9`]
+    Arm9,
+}
+
+/// Errors related to [`HeaderHmacs`].
+#[derive(Debug, Snafu)]
+pub enum RawHeaderHmacsError {
+    /// See [`RawHeaderError`].
+    #[snafu(transparent)]
+    RawHeader {
+        /// Source error.
+        source: RawHeaderError,
+    },
+    /// See [`RawBannerError`].
+    #[snafu(transparent)]
+    RawBanner {
+        /// Source error.
+        source: RawBannerError,
+    },
+    /// Occurs when a program goes past the end of the ROM.
+    #[snafu(display("{kind} range {start:#x}..{end:#x} is out of bounds for ROM of size {size:#x}:\n{backtrace}"))]
+    OutOfBounds {
+        /// Which HMAC was being computed.
+        kind: HeaderHmacKind,
+        /// Start of the range.
+        start: usize,
+        /// End of the range.
+        end: usize,
+        /// ROM size.
+        size: usize,
+        /// Backtrace to the source of the error.
+        backtrace: Backtrace,
+    },
+}
+
+impl HeaderHmacs {
+    /// Computes the header HMACs from the given ROM data, using the offsets and sizes in `header`.
+    ///
+    /// # Errors
+    ///
+    /// This function will return an error if the ARM9 or ARM7 program is out of bounds, or if the banner fails to parse.
+    pub fn compute(rom: &[u8], header: &Header, hmac_sha1: &HmacSha1) -> Result<Self, RawHeaderHmacsError> {
+        let arm9_start = header.arm9.offset as usize;
+        let arm9_end = arm9_start + header.arm9.size as usize;
+        let arm9 = Self::range(rom, arm9_start..arm9_end, HeaderHmacKind::Arm9WithSecureArea)?;
+        let arm9_no_secure_area = &arm9[SECURE_AREA_SIZE.min(arm9.len())..];
+
+        let arm7_start = header.arm7.offset as usize;
+        let arm7_end = arm7_start + header.arm7.size as usize;
+        let arm7 = Self::range(rom, arm7_start..arm7_end, HeaderHmacKind::Arm7)?;
+
+        let banner_start = header.banner_offset as usize;
+        let banner = Self::range(rom, banner_start..rom.len(), HeaderHmacKind::Banner)?;
+        let banner = Banner::borrow_from_slice(banner)?;
+
+        Ok(Self {
+            arm9_with_secure_area: hmac_sha1.compute(arm9),
+            arm7: hmac_sha1.compute(arm7),
+            banner: hmac_sha1.compute(banner.full_data()),
+            arm9: hmac_sha1.compute(arm9_no_secure_area),
+        })
+    }
+
+    fn range(rom: &[u8], range: Range<usize>, kind: HeaderHmacKind) -> Result<&[u8], RawHeaderHmacsError> {
+        if range.start > range.end || range.end > rom.len() {
+            return OutOfBoundsSnafu { kind, start: range.start, end: range.end, size: rom.len() }.fail();
+        }
+        Ok(&rom[range])
+    }
+
+    /// Returns the HMACs which are used by the given header. All of them are used since [`HeaderVersion::DsPostDsi`], even
+    /// by DS-only titles, since the DSi checks them against its whitelist.
+    pub fn used_by(header: &Header) -> Vec<HeaderHmacKind> {
+        if header.version() < HeaderVersion::DsPostDsi {
+            return vec![];
+        }
+        vec![HeaderHmacKind::Arm9WithSecureArea, HeaderHmacKind::Arm7, HeaderHmacKind::Banner, HeaderHmacKind::Arm9]
+    }
+
+    /// Returns the HMAC of the given kind.
+    pub fn get(&self, kind: HeaderHmacKind) -> [u8; 0x14] {
+        match kind {
+            HeaderHmacKind::Arm9WithSecureArea => self.arm9_with_secure_area,
+            HeaderHmacKind::Arm7 => self.arm7,
+            HeaderHmacKind::Banner => self.banner,
+            HeaderHmacKind::Arm9 => self.arm9,
+        }
+    }
+
+    /// Writes the HMACs used by the header into it, see [`Self::used_by`].
+    pub fn apply(&self, header: &mut Header) {
+        for kind in Self::used_by(header) {
+            *kind.field_mut(header) = self.get(kind);
+        }
+    }
+
+    /// Returns the HMACs used by the header which don't match these HMACs.
+    pub fn stale(&self, header: &Header) -> Vec<HeaderHmacKind> {
+        Self::used_by(header).into_iter().filter(|&kind| *kind.field(header) != self.get(kind)).collect()
+    }
+}
+
+impl HeaderHmacKind {
+    /// Returns a reference to the header field of this HMAC.
+    pub fn field(self, header: &Header) -> &[u8; 0x14] {
+        match self {
+            HeaderHmacKind::Arm9WithSecureArea => &header.sha1_hmac_arm9_with_secure_area,
+            HeaderHmacKind::Arm7 => &header.sha1_hmac_arm7,
+            HeaderHmacKind::Banner => &header.sha1_hmac_banner,
+            HeaderHmacKind::Arm9 => &header.sha1_hmac_arm9,
+        }
+    }
+
+    /// Returns a mutable reference to the header field of this HMAC.
+    pub fn field_mut(self, header: &mut Header) -> &mut [u8; 0x14] {
+        match self {
+            HeaderHmacKind::Arm9WithSecureArea => &mut header.sha1_hmac_arm9_with_secure_area,
+            HeaderHmacKind::Arm7 => &mut header.sha1_hmac_arm7,
+            HeaderHmacKind::Banner => &mut header.sha1_hmac_banner,
+            HeaderHmacKind::Arm9 => &mut header.sha1_hmac_arm9,
+        }
+    }
+}
+
+impl Display for HeaderHmacKind {
+    fn fmt(&self, f: &mut std::fmt::Formatter<'_>) -> std::fmt::Result {
+        match self {
+            HeaderHmacKind::Arm9WithSecureArea => write!(f, "ARM9 with secure area"),
+            HeaderHmacKind::Arm7 => write!(f, "ARM7"),
+            HeaderHmacKind::Banner => write!(f, "banner"),
+            HeaderHmacKind::Arm9 => write!(f, "ARM9"),
+        }
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use bytemuck::Zeroable;
+
+    use super::*;
+    use crate::rom::raw::{test_fixtures::build_rom, DsiFlags2};
+
+    const ARM7_OFFSET: usize = 0x8800;
+    const BANNER_OFFSET: usize = 0x9000;
+
+    /// Returns ROM data for a post-DSi DS-only title, along with its header.
+    fn build_ds_rom() -> (Vec<u32>, Header) {
+        let mut header = Header::zeroed();
+        header.unitcode = 0;
+        header.dsi_flags_2 = DsiFlags2::from_bits(1);
+        header.arm9.offset = 0x4000;
+        header.arm9.size = 0x4800;
+        header.arm7.offset = ARM7_OFFSET as u32;
+        header.arm7.size = 0x800;
+        header.banner_offset = BANNER_OFFSET as u32;
+
+        let mut rom = build_rom(0xa000, &header);
+        // Banner version 1
+        rom[BANNER_OFFSET / 4] = 1;
+        (rom, header)
+    }
+
+    #[test]
+    fn used_by_post_dsi_ds_only() {
+        let (_, mut header) = build_ds_rom();
+        assert_eq!(HeaderHmacs::used_by(&header).len(), 4);
+
+        header.dsi_flags_2 = DsiFlags2::from_bits(0);
+        assert!(HeaderHmacs::used_by(&header).is_empty());
+    }
+
+    #[test]
+    fn verify_reports_stale_arm7() {
+        let hmac_sha1 = HmacSha1::new([0x5a; 64]);
+        let (mut rom, mut header) = build_ds_rom();
+
+        let hmacs = HeaderHmacs::compute(bytemuck::cast_slice(&rom), &header, &hmac_sha1).unwrap();
+        hmacs.apply(&mut header);
+        assert!(hmacs.stale(&header).is_empty());
+
+        rom[ARM7_OFFSET / 4 + 0x10] ^= 1;
+        let hmacs = HeaderHmacs::compute(bytemuck::cast_slice(&rom), &header, &hmac_sha1).unwrap();
+        assert_eq!(hmacs.stale(&header), vec![HeaderHmacKind::Arm7]);
+    }
+}
@@ -2,21 +2,27 @@ mod arm9_footer;
 mod autoload_info;
 mod banner;
 mod build_info;
+mod digest;
 mod fat;
 mod fnt;
 mod header;
+mod header_hmacs;
 mod hmac_sha1_signature;
 mod overlay;
 mod overlay_table;
 mod rom;
+#[cfg(test)]
+pub(crate) mod test_fixtures;
 
 pub use arm9_footer::*;
 pub use autoload_info::*;
 pub use banner::*;
 pub use build_info::*;
+pub use digest::*;
 pub use fat::*;
 pub use fnt::*;
 pub use header::*;
+pub use header_hmacs::*;
 pub use hmac_sha1_signature::*;
 pub use overlay::*;
 pub use overlay_table::*;
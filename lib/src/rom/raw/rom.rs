@@ -3,12 +3,18 @@ use std::{borrow::Cow, collections::BTreeSet, io::Read, mem::size_of, path::Path
 use snafu::Snafu;
 
 use super::{
-    Arm9Footer, Arm9FooterError, Banner, FileAlloc, Fnt, Header, Overlay, OverlayTable, RawBannerError, RawBuildInfoError,
-    RawFatError, RawFntError, RawHeaderError, RawOverlayError,
+    Arm9Footer, Arm9FooterError, Banner, Digest, DigestHashtables, DigestVerification, DsiAreaOutOfBoundsSnafu, FileAlloc,
+    Fnt, Header, HeaderHmacKind, HeaderHmacs, HeaderSignature, Overlay, OverlayTable, ProgramOffset, RawBannerError,
+    RawBuildInfoError, RawDigestError, RawFatError, RawFntError, RawHeaderError, RawHeaderHmacsError, RawOverlayError,
+    TableOffset,
 };
 use crate::{
+    crypto::{hmac_sha1::HmacSha1, rsa::RsaVerifier},
     io::{open_file, write_file, FileError},
-    rom::{Arm7, Arm7Offsets, Arm9, Arm9Offsets, RomConfigAlignment},
+    rom::{
+        Arm7, Arm7Offsets, Arm9, Arm9Offsets, DsiArea, DsiProgram, DsiProgramOffsets, ModcryptArea, RomConfigAlignment,
+        RomConfigDsiAlignment,
+    },
 };
 
 /// A raw DS ROM, see the plain struct [here](super::super::Rom).
@@ -378,6 +384,201 @@ impl<'a> Rom<'a> {
         Ok(self.data[gap[0].end as usize])
     }
 
+    /// Returns the offset to the start of the DSi area, or `None` if this [`Rom`] has no DSi area.
+    ///
+    /// # Errors
+    ///
+    /// See [`Self::header`].
+    pub fn dsi_area_offset(&self) -> Result<Option<u32>, RawHeaderError> {
+        let header = self.header()?;
+        if header.arm9i.offset == 0 {
+            return Ok(None);
+        }
+        let region_start = header.ds_rom_region_end as u32 * 0x80000;
+        if region_start != 0 && region_start <= header.arm9i.offset {
+            Ok(Some(region_start))
+        } else {
+            Ok(Some(header.arm9i.offset))
+        }
+    }
+
+    /// Returns the DSi area of this [`Rom`], or `None` if it has no DSi area.
+    ///
+    /// # Errors
+    ///
+    /// See [`Self::header`]. This function will also return an error if a section of the DSi area is out of bounds.
+    pub fn dsi_area(&self) -> Result<Option<DsiArea>, RawHeaderError> {
+        let Some(start) = self.dsi_area_offset()? else {
+            return Ok(None);
+        };
+        let header = self.header()?;
+        let reserved = self.dsi_section("reserved DSi area", start as usize, header.arm9i.offset as usize)?;
+        let arm9i = self.dsi_program("ARM9i", &header.arm9i, header.arm9i_build_info_offset, &header.modcrypt_area_1)?;
+        let arm7i = self.dsi_program("ARM7i", &header.arm7i, header.arm7i_build_info_offset, &header.modcrypt_area_2)?;
+
+        // Keep any data between the ARM9i and ARM7i programs, unless they're out of order
+        let arm9i_end = header.arm9i.offset as usize + header.arm9i.size as usize;
+        let arm7i_start = header.arm7i.offset as usize;
+        let gap = &self.data[arm9i_end.min(arm7i_start)..arm7i_start];
+
+        // Keep any data between the ARM7i program and the end of the DSi area
+        let arm7i_end = header.arm7i.offset as usize + header.arm7i.size as usize;
+        let trailing_end = (header.rom_size_dsi as usize).min(self.data.len()).max(arm7i_end);
+        let trailing = &self.data[arm7i_end..trailing_end];
+
+        Ok(Some(
+            DsiArea::new(Cow::Borrowed(reserved), arm9i, arm7i)
+                .with_gap(Cow::Borrowed(gap))
+                .with_trailing(Cow::Borrowed(trailing)),
+        ))
+    }
+
+    fn dsi_section(&self, section: &'static str, start: usize, end: usize) -> Result<&[u8], RawHeaderError> {
+        if start > end || end > self.data.len() {
+            return DsiAreaOutOfBoundsSnafu { section, start, end, size: self.data.len() }.fail();
+        }
+        Ok(&self.data[start..end])
+    }
+
+    fn dsi_program(
+        &self,
+        section: &'static str,
+        program: &ProgramOffset,
+        build_info_offset: u32,
+        modcrypt_area: &TableOffset,
+    ) -> Result<DsiProgram, RawHeaderError> {
+        let start = program.offset as usize;
+        let end = start + program.size as usize;
+        let data = self.dsi_section(section, start, end)?;
+
+        // Wrapping keeps the header value intact even if it's not an absolute ROM offset
+        let build_info_offset = if build_info_offset == 0 { 0 } else { build_info_offset.wrapping_sub(program.offset) };
+        let modcrypt_area = (modcrypt_area.size != 0)
+            .then(|| ModcryptArea { offset: modcrypt_area.offset.wrapping_sub(program.offset), size: modcrypt_area.size });
+
+        Ok(DsiProgram::new(
+            Cow::Borrowed(data),
+            DsiProgramOffsets {
+                base_address: program.base_addr,
+                entry_function: program.entry,
+                build_info: build_info_offset,
+                modcrypt_area,
+            },
+        ))
+    }
+
+    /// Returns the padding value in the DSi area of this [`Rom`].
+    ///
+    /// # Errors
+    ///
+    /// See [`Self::header`].
+    pub fn dsi_padding_value(&self) -> Result<u8, RawHeaderError> {
+        let header = self.header()?;
+        let Some(start) = self.dsi_area_offset()? else {
+            return Ok(0xff);
+        };
+
+        // Get sorted list of adjacent sections between the DS area and the DSi area
+        let mut sections = vec![
+            0..header.rom_size_ds,
+            header.digest_sector_hashtable.offset..header.digest_sector_hashtable.offset + header.digest_sector_hashtable.size,
+            header.digest_block_hashtable.offset..header.digest_block_hashtable.offset + header.digest_block_hashtable.size,
+            start..start + 1,
+        ];
+        sections.retain(|section| section.start != section.end);
+        sections.sort_by_key(|section| section.start);
+
+        // Find a gap between two adjacent sections, and return the padding byte between them
+        let Some(gap) = sections.windows(2).find(|pair| pair[0].end < pair[1].start) else {
+            return Ok(0xff);
+        };
+        Ok(self.data[gap[0].end as usize])
+    }
+
+    /// Returns the alignment of DSi area sections, or `None` if this [`Rom`] has no DSi area. These are only used to place
+    /// sections which no longer fit at their original offsets, see [`RomConfigDsi`](crate::rom::RomConfigDsi). The ARM7i
+    /// alignment is relative to the start of the DSi area.
+    ///
+    /// # Errors
+    ///
+    /// See [`Self::header`].
+    pub fn dsi_alignments(&self) -> Result<Option<RomConfigDsiAlignment>, RawHeaderError> {
+        const DEFAULT_ALIGNMENT: u32 = 0x4;
+
+        // Get the alignment of a section, capped at the size of one megabyte.
+        fn get_alignment(section: u32) -> u32 {
+            if section == 0 {
+                DEFAULT_ALIGNMENT
+            } else {
+                1 << section.trailing_zeros().min(20)
+            }
+        }
+
+        let Some(start) = self.dsi_area_offset()? else {
+            return Ok(None);
+        };
+        let header = self.header()?;
+
+        Ok(Some(RomConfigDsiAlignment {
+            digest_sector_hashtable: get_alignment(header.digest_sector_hashtable.offset),
+            digest_block_hashtable: get_alignment(header.digest_block_hashtable.offset),
+            dsi_area: get_alignment(start),
+            arm7i: get_alignment(header.arm7i.offset.wrapping_sub(start)),
+        }))
+    }
+
+    /// Returns the digest layout of this [`Rom`], or `None` if it has no digest.
+    ///
+    /// # Errors
+    ///
+    /// See [`Self::header`].
+    pub fn digest(&self) -> Result<Option<Digest>, RawHeaderError> {
+        Ok(Digest::from_header(self.header()?))
+    }
+
+    /// Returns the digest sector and block hashtables of this [`Rom`], or `None` if it has no digest.
+    ///
+    /// # Errors
+    ///
+    /// See [`Self::header`] and [`Digest::hashtables`].
+    pub fn digest_hashtables(&self) -> Result<Option<DigestHashtables>, RawDigestError> {
+        let header = self.header()?;
+        if Digest::from_header(header).is_none() {
+            return Ok(None);
+        }
+        Ok(Some(Digest::hashtables(&self.data, header)?))
+    }
+
+    /// Verifies the digest sector and block hashtables of this [`Rom`], and reports which sectors and blocks no longer match.
+    ///
+    /// # Errors
+    ///
+    /// See [`Self::header`] and [`Digest::verify`].
+    pub fn verify_digest(&self, hmac_sha1: &HmacSha1) -> Result<DigestVerification, RawDigestError> {
+        Digest::verify(&self.data, self.header()?, hmac_sha1)
+    }
+
+    /// Verifies the ARM9, ARM7 and banner SHA1-HMACs in the header of this [`Rom`], and returns the ones which are stale.
+    /// Only the HMACs used by the header are checked, see [`HeaderHmacs::used_by`].
+    ///
+    /// # Errors
+    ///
+    /// See [`Self::header`] and [`HeaderHmacs::compute`].
+    pub fn verify_header_hmacs(&self, hmac_sha1: &HmacSha1) -> Result<Vec<HeaderHmacKind>, RawHeaderHmacsError> {
+        let header = self.header()?;
+        let hmacs = HeaderHmacs::compute(&self.data, header, hmac_sha1)?;
+        Ok(hmacs.stale(header))
+    }
+
+    /// Verifies the RSA signature of the header of this [`Rom`] against the keys in `verifier`.
+    ///
+    /// # Errors
+    ///
+    /// See [`Self::header`].
+    pub fn verify_rsa_signature(&self, verifier: &RsaVerifier) -> Result<HeaderSignature, RawHeaderError> {
+        Ok(self.header()?.verify_rsa_signature(verifier))
+    }
+
     /// Returns a reference to the data of this [`Rom`].
     pub fn data(&self) -> &[u8] {
         &self.data
@@ -463,3 +664,88 @@ impl<'a> Rom<'a> {
         })
     }
 }
+
+#[cfg(test)]
+mod tests {
+    use bytemuck::Zeroable;
+
+    use super::*;
+    use crate::rom::raw::test_fixtures::build_rom;
+
+    const DSI_AREA_START: usize = 0x80000;
+    const ARM9I_OFFSET: usize = DSI_AREA_START + 0x100;
+    const ARM7I_OFFSET: usize = ARM9I_OFFSET + 0x500;
+    const ROM_SIZE_DSI: usize = ARM7I_OFFSET + 0x400;
+
+    /// Returns ROM words with a DSi area containing reserved data, the ARM9i and ARM7i programs and trailing data.
+    fn build_dsi_rom(arm7i_size: u32) -> Vec<u32> {
+        let mut header = Header::zeroed();
+        header.ds_rom_region_end = (DSI_AREA_START / 0x80000) as u16;
+        header.arm9i = ProgramOffset { offset: ARM9I_OFFSET as u32, entry: 0, base_addr: 0, size: 0x480 };
+        header.arm7i = ProgramOffset { offset: ARM7I_OFFSET as u32, entry: 0, base_addr: 0, size: arm7i_size };
+        header.rom_size_dsi = ROM_SIZE_DSI as u32;
+        build_rom(0x81000, &header)
+    }
+
+    #[test]
+    fn dsi_area_keeps_all_bytes() {
+        let words = build_dsi_rom(0x300);
+        let data: &[u8] = bytemuck::cast_slice(&words);
+        let rom = Rom::new(data);
+        let dsi_area = rom.dsi_area().unwrap().unwrap();
+
+        assert_eq!(dsi_area.reserved(), &data[DSI_AREA_START..ARM9I_OFFSET]);
+        assert_eq!(dsi_area.arm9i().full_data(), &data[ARM9I_OFFSET..ARM9I_OFFSET + 0x480]);
+        assert_eq!(dsi_area.gap(), &data[ARM9I_OFFSET + 0x480..ARM7I_OFFSET]);
+        assert_eq!(dsi_area.arm7i().full_data(), &data[ARM7I_OFFSET..ARM7I_OFFSET + 0x300]);
+        assert_eq!(dsi_area.trailing(), &data[ARM7I_OFFSET + 0x300..ROM_SIZE_DSI]);
+
+        let mut rebuilt = dsi_area.reserved().to_vec();
+        rebuilt.extend(dsi_area.arm9i().full_data());
+        rebuilt.extend(dsi_area.gap());
+        rebuilt.extend(dsi_area.arm7i().full_data());
+        rebuilt.extend(dsi_area.trailing());
+        assert_eq!(rebuilt, &data[DSI_AREA_START..ROM_SIZE_DSI]);
+    }
+
+    #[test]
+    fn modcrypt_roundtrip() {
+        let mut words = build_dsi_rom(0x300);
+        let data: &mut [u8] = bytemuck::cast_slice_mut(&mut words);
+        let header = Header::borrow_from_slice_mut(data).unwrap();
+        header.dsi_flags.set_modcrypted(true);
+        header.modcrypt_area_1 = TableOffset { offset: ARM9I_OFFSET as u32 + 0x40, size: 0x100 };
+        header.modcrypt_area_2 = TableOffset { offset: ARM7I_OFFSET as u32, size: 0x88 };
+        let header = *header;
+        let data: &[u8] = bytemuck::cast_slice(&words);
+
+        // Extracting decrypts only the modcrypt areas
+        let rom = Rom::new(data);
+        let encrypted = rom.dsi_area().unwrap().unwrap();
+        let mut decrypted = encrypted.clone();
+        decrypted.modcrypt(&header).unwrap();
+        let arm9i_diff = (0..0x480).filter(|&i| decrypted.arm9i().full_data()[i] != encrypted.arm9i().full_data()[i]);
+        assert!(arm9i_diff.clone().all(|i| (0x40..0x140).contains(&i)));
+        assert!(arm9i_diff.count() > 0xf0);
+        assert_eq!(decrypted.arm7i().full_data()[0x88..], encrypted.arm7i().full_data()[0x88..]);
+        assert_ne!(decrypted.arm7i().full_data()[..0x88], encrypted.arm7i().full_data()[..0x88]);
+
+        // Building encrypts them again
+        let mut rebuilt = decrypted.clone();
+        rebuilt.modcrypt(&header).unwrap();
+        assert_eq!(rebuilt.arm9i().full_data(), encrypted.arm9i().full_data());
+        assert_eq!(rebuilt.arm7i().full_data(), encrypted.arm7i().full_data());
+    }
+
+    #[test]
+    fn dsi_area_out_of_bounds() {
+        let words = build_dsi_rom(0x100000);
+        let rom = Rom::new(bytemuck::cast_slice::<u32, u8>(&words));
+        assert!(matches!(rom.dsi_area(), Err(RawHeaderError::DsiAreaOutOfBounds { section: "ARM7i", .. })));
+
+        let words = build_dsi_rom(0x300);
+        let data: &[u8] = bytemuck::cast_slice(&words);
+        let rom = Rom::new(&data[..ARM9I_OFFSET + 0x100]);
+        assert!(matches!(rom.dsi_area(), Err(RawHeaderError::DsiAreaOutOfBounds { section: "ARM9i", .. })));
+    }
+}
@@ -0,0 +1,16 @@
+use std::mem::size_of;
+
+use super::Header;
+
+/// Returns the words of a ROM of `size` bytes which starts with `header`, and is otherwise filled with a pseudo-random
+/// pattern.
+pub(crate) fn build_rom(size: usize, header: &Header) -> Vec<u32> {
+    let mut words = (0..size as u32 / 4).map(|i| i.wrapping_mul(0x9e3779b9)).collect::<Vec<_>>();
+    write_header(&mut words, header);
+    words
+}
+
+/// Overwrites the header at the start of the ROM `words`.
+pub(crate) fn write_header(words: &mut [u32], header: &Header) {
+    bytemuck::cast_slice_mut(words)[..size_of::<Header>()].copy_from_slice(bytemuck::bytes_of(header));
+}
@@ -1,25 +1,35 @@
 use std::{
     backtrace::Backtrace,
+    collections::{hash_map::Entry, HashMap},
     io::{self, Cursor, Write},
     mem::size_of,
+    num::NonZeroUsize,
+    panic,
     path::Path,
+    sync::Mutex,
+    thread,
 };
 
 use serde::{Deserialize, Serialize};
-use snafu::Snafu;
+use snafu::{ResultExt, Snafu};
 
 use super::{
     raw::{
-        self, Arm9Footer, HmacSha1Signature, RawArm9Error, RawBannerError, RawBuildInfoError, RawFatError, RawFntError,
-        RawHeaderError, RawOverlayError, RomAlignmentsError, TableOffset,
+        self, Arm9Footer, Digest, DigestHashtables, HeaderHmacs, HmacSha1Signature, RawArm9Error, RawBannerError,
+        RawBuildInfoError, RawDigestError, RawFatError, RawFntError, RawHeaderError, RawHeaderHmacsError, RawOverlayError,
+        RomAlignmentsError, TableOffset,
     },
     Arm7, Arm9, Arm9AutoloadError, Arm9Error, Arm9HmacSha1KeyError, Arm9Offsets, Arm9OverlaySignaturesError, Autoload, Banner,
-    BannerError, BannerImageError, BuildInfo, FileBuildError, FileParseError, FileSystem, Header, HeaderBuildError, Logo,
-    LogoError, LogoLoadError, LogoSaveError, Overlay, OverlayError, OverlayInfo, OverlayOptions, OverlayTable,
-    RomConfigAutoload, RomConfigUnknownAutoload,
+    BannerError, BannerImageError, BuildInfo, DsiArea, DsiProgram, DsiProgramError, FileBuildError, FileCompressionError,
+    FileEditError, FileIdError, FileNarcError, FileParseError, FileSystem, Header, HeaderBuildError, Logo, LogoError,
+    LogoLoadError, LogoSaveError, Overlay, OverlayError, OverlayInfo, OverlayOptions, OverlayTable, RomConfigAutoload,
+    RomConfigDsi, RomConfigFileAlias, RomConfigUnknownAutoload,
 };
 use crate::{
-    compress::lz77::Lz77DecompressError,
+    compress::{
+        cache::CompressionCache,
+        lz77::{Lz77DecompressError, Lz77Strategy},
+    },
     crypto::{
         blowfish::BlowfishKey,
         hmac_sha1::{HmacSha1, HmacSha1FromBytesError},
@@ -28,6 +38,15 @@ use crate::{
     rom::{raw::FileAlloc, Arm9WithTcmsOptions, RomConfig},
 };
 
+/// Default path of the [`FileCompressionManifest`](super::FileCompressionManifest), relative to the config file.
+const FILE_COMPRESSION_MANIFEST: &str = "files_compression.yaml";
+/// Default path of the [`NarcManifest`](super::NarcManifest), relative to the config file.
+const FILE_NARC_MANIFEST: &str = "files_narcs.yaml";
+/// Default path of the [`FileIdManifest`](super::FileIdManifest), relative to the config file.
+const FILE_ID_MANIFEST: &str = "file_ids.yaml";
+/// Path of the [`CompressionCache`] used by [`Rom::load`], relative to the config file.
+const COMPRESSION_CACHE_DIR: &str = ".dsrom-cache";
+
 /// A plain ROM.
 pub struct Rom<'a> {
     header: Header,
@@ -39,6 +58,10 @@ pub struct Rom<'a> {
     banner: Banner,
     files: FileSystem<'a>,
     path_order: Vec<String>,
+    dsi_area: Option<DsiArea<'a>>,
+    header_hmac_sha1: Option<HmacSha1>,
+    digest_hashtables: Option<DigestHashtables>,
+    digest_hmac_sha1: Option<HmacSha1>,
     config: RomConfig,
 }
 
@@ -129,6 +152,18 @@ pub enum RomExtractError {
         /// Source error.
         source: Arm9HmacSha1KeyError,
     },
+    /// See [`DsiProgramError`].
+    #[snafu(transparent)]
+    DsiProgram {
+        /// Source error.
+        source: DsiProgramError,
+    },
+    /// See [`RawDigestError`].
+    #[snafu(transparent)]
+    RawDigest {
+        /// Source error.
+        source: RawDigestError,
+    },
 }
 
 /// Errors related to [`Rom::build`].
@@ -158,6 +193,45 @@ pub enum RomBuildError {
         /// Source error.
         source: HeaderBuildError,
     },
+    /// See [`DsiProgramError`].
+    #[snafu(transparent)]
+    DsiProgram {
+        /// Source error.
+        source: DsiProgramError,
+    },
+    /// See [`RawDigestError`].
+    #[snafu(transparent)]
+    RawDigest {
+        /// Source error.
+        source: RawDigestError,
+    },
+    /// See [`RawHeaderHmacsError`].
+    #[snafu(transparent)]
+    RawHeaderHmacs {
+        /// Source error.
+        source: RawHeaderHmacsError,
+    },
+    /// Occurs when the ROM has DSi digest hashtables which don't match the built ROM, and no digest HMAC-SHA1 key was
+    /// provided to recompute them.
+    #[snafu(display(
+        "DSi digest hashtables are out of date, a digest HMAC-SHA1 key is required to recompute them:\n{backtrace}"
+    ))]
+    DigestHmacSha1KeyNeeded {
+        /// Backtrace to the source of the error.
+        backtrace: Backtrace,
+    },
+    /// Occurs when an asset file could not be written to the ROM.
+    #[snafu(display("failed to write file '{path}' (ID {id}) to the ROM: {source}:\n{backtrace}"))]
+    WriteFile {
+        /// Path to the file.
+        path: String,
+        /// File ID.
+        id: u16,
+        /// Source error.
+        source: io::Error,
+        /// Backtrace to the source of the error.
+        backtrace: Backtrace,
+    },
 }
 
 /// Errors related to [`Rom::save`] and [`Rom::load`].
@@ -232,6 +306,24 @@ pub enum RomSaveError {
         /// Source error.
         source: OverlayError,
     },
+    /// See [`FileCompressionError`].
+    #[snafu(transparent)]
+    FileCompression {
+        /// Source error.
+        source: FileCompressionError,
+    },
+    /// See [`FileNarcError`].
+    #[snafu(transparent)]
+    FileNarc {
+        /// Source error.
+        source: FileNarcError,
+    },
+    /// See [`FileIdError`].
+    #[snafu(transparent)]
+    FileId {
+        /// Source error.
+        source: FileIdError,
+    },
     /// See [`Arm9OverlaySignaturesError`].
     #[snafu(transparent)]
     HmacSha1FromBytes {
@@ -250,6 +342,16 @@ pub enum RomSaveError {
         /// Source error.
         source: Arm9OverlaySignaturesError,
     },
+    /// Occurs when an overlay could not be compressed.
+    #[snafu(display("failed to compress {processor} overlay {id}: {source}"))]
+    OverlayCompress {
+        /// Processor of the overlay, `arm9` or `arm7`.
+        processor: &'static str,
+        /// Overlay ID.
+        id: u16,
+        /// Source error.
+        source: io::Error,
+    },
     /// Occurs when the HMAC-SHA1 key was not provided for a signed overlay.
     #[snafu(display("HMAC-SHA1 key was not provided for a signed overlay:\n{backtrace}"))]
     NoHmacSha1Key {
@@ -362,11 +464,16 @@ impl<'a> Rom<'a> {
             None
         };
 
-        // --------------------- Load ARM9 overlays ---------------------
-        let arm9_overlays = if let Some(arm9_overlays_config) = &config.arm9_overlays {
-            Self::load_overlays(&path.join(arm9_overlays_config), "arm9", arm9_hmac_sha1, &options)?
+        // --------------------- Load overlays ---------------------
+        let mut arm9_overlays = if let Some(arm9_overlays_config) = &config.arm9_overlays {
+            Self::load_overlays(&path.join(arm9_overlays_config), "arm9")?
         } else {
-            Default::default()
+            LoadedOverlays::empty("arm9")
+        };
+        let mut arm7_overlays = if let Some(arm7_overlays_config) = &config.arm7_overlays {
+            Self::load_overlays(&path.join(arm7_overlays_config), "arm7")?
+        } else {
+            LoadedOverlays::empty("arm7")
         };
 
         // --------------------- Build ARM9 program ---------------------
@@ -380,12 +487,32 @@ impl<'a> Rom<'a> {
             },
         )?;
         arm9_build_config.build_info.assign_to_raw(arm9.build_info_mut()?);
+
+        // --------------------- Compress ARM9 program and overlays ---------------------
+        let compress_arm9 = arm9_build_config.compressed && options.compress;
+        // The ARM9 program can only be compressed alongside the overlays if it doesn't contain their signatures
+        let compress_arm9_concurrently = compress_arm9 && arm9.overlay_signatures_offset() == 0;
+        let cache = options.compression_cache.then(|| CompressionCache::new(path.join(COMPRESSION_CACHE_DIR)));
+        if options.compress {
+            let mut jobs = vec![];
+            if compress_arm9_concurrently {
+                jobs.push(CompressJob::Arm9(&mut arm9));
+            }
+            jobs.extend(arm9_overlays.compress_jobs());
+            jobs.extend(arm7_overlays.compress_jobs());
+            CompressJob::run_all(jobs, options.compression_workers, options.lz77_strategy, cache.as_ref())?;
+        }
+
+        let arm9_overlays = arm9_overlays.into_table(arm9_hmac_sha1.as_ref(), &options)?;
+        let arm7_overlays = arm7_overlays.into_table(None, &options)?;
+
         arm9.update_overlay_signatures(&arm9_overlays)?;
-        if arm9_build_config.compressed && options.compress {
-            log::info!("Compressing ARM9 program");
-            arm9.compress()?;
+        if compress_arm9 && !compress_arm9_concurrently {
+            CompressJob::Arm9(&mut arm9).run(options.lz77_strategy, cache.as_ref())?;
         }
-        if arm9_build_config.encrypted && options.encrypt {
+        if header.original.secure_area_disable {
+            log::info!("Secure area is disabled, skipping ARM9 encryption");
+        } else if arm9_build_config.encrypted && options.encrypt {
             let Some(key) = options.key else {
                 return BlowfishKeyNeededSnafu {}.fail();
             };
@@ -393,13 +520,6 @@ impl<'a> Rom<'a> {
             arm9.encrypt(key, header.original.gamecode.to_le_u32())?;
         }
 
-        // --------------------- Load ARM7 overlays ---------------------
-        let arm7_overlays = if let Some(arm7_overlays_config) = &config.arm7_overlays {
-            Self::load_overlays(&path.join(arm7_overlays_config), "arm7", None, &options)?
-        } else {
-            Default::default()
-        };
-
         // --------------------- Load ARM7 program ---------------------
         let arm7 = read_file(path.join(&config.arm7_bin))?;
         let arm7_config = serde_yml::from_reader(open_file(path.join(&config.arm7_config))?)?;
@@ -420,7 +540,18 @@ impl<'a> Rom<'a> {
         let num_overlays = arm9_overlays.overlays().len() + arm7_overlays.overlays().len();
         let (files, path_order) = if options.load_files {
             log::info!("Loading ROM assets");
-            let files = FileSystem::load(path.join(&config.files_dir), num_overlays)?;
+            let files_path = path.join(&config.files_dir);
+            let mut files = if let Some(files_narcs) = &config.files_narcs {
+                FileSystem::load_narcs(files_path, path.join(files_narcs), num_overlays)?
+            } else {
+                FileSystem::load(files_path, num_overlays)?
+            };
+            if let Some(files_compression) = &config.files_compression {
+                files.compress_from_manifest(path.join(files_compression))?;
+            }
+            if let Some(file_ids) = &config.file_ids {
+                files.pin_file_ids(path.join(file_ids))?;
+            }
             let path_order =
                 read_to_string(path.join(&config.path_order))?.trim().lines().map(|l| l.to_string()).collect::<Vec<_>>();
             (files, path_order)
@@ -428,6 +559,41 @@ impl<'a> Rom<'a> {
             (FileSystem::new(num_overlays), vec![])
         };
 
+        // --------------------- Load DSi area ---------------------
+        let dsi_area = if let Some(dsi_config) = &config.dsi {
+            let reserved = read_file(path.join(&dsi_config.reserved))?;
+            let arm9i = read_file(path.join(&dsi_config.arm9i_bin))?;
+            let arm9i_config = serde_yml::from_reader(open_file(path.join(&dsi_config.arm9i_config))?)?;
+            let arm7i = read_file(path.join(&dsi_config.arm7i_bin))?;
+            let arm7i_config = serde_yml::from_reader(open_file(path.join(&dsi_config.arm7i_config))?)?;
+            let gap = if let Some(gap) = &dsi_config.gap { read_file(path.join(gap))? } else { vec![] };
+            let trailing = if let Some(trailing) = &dsi_config.trailing { read_file(path.join(trailing))? } else { vec![] };
+            Some(
+                DsiArea::new(reserved, DsiProgram::new(arm9i, arm9i_config), DsiProgram::new(arm7i, arm7i_config))
+                    .with_gap(gap)
+                    .with_trailing(trailing),
+            )
+        } else {
+            None
+        };
+        let digest_hashtables = match config
+            .dsi
+            .as_ref()
+            .map(|dsi| (&dsi.digest_sector_hashtable, &dsi.digest_block_hashtable, &dsi.digest_sectors_sha1))
+        {
+            Some((Some(sector), Some(block), Some(sectors_sha1))) => {
+                let sectors_sha1 = read_file(path.join(sectors_sha1))?;
+                // A malformed checksum is treated as stale, so that the hashtables are recomputed or rejected on build
+                let sectors_sha1 = sectors_sha1.as_slice().try_into().unwrap_or_default();
+                Some(DigestHashtables {
+                    sector: read_file(path.join(sector))?.into(),
+                    block: read_file(path.join(block))?.into(),
+                    sectors_sha1,
+                })
+            }
+            _ => None,
+        };
+
         Ok(Self {
             header,
             header_logo,
@@ -438,56 +604,34 @@ impl<'a> Rom<'a> {
             banner,
             files,
             path_order,
+            dsi_area,
+            header_hmac_sha1: options.header_hmac_sha1.cloned(),
+            digest_hashtables,
+            digest_hmac_sha1: options.digest_hmac_sha1.cloned(),
             config,
         })
     }
 
-    fn load_overlays(
-        config_path: &Path,
-        processor: &str,
-        hmac_sha1: Option<HmacSha1>,
-        options: &RomLoadOptions,
-    ) -> Result<OverlayTable<'a>, RomSaveError> {
+    fn load_overlays(config_path: &Path, processor: &'static str) -> Result<LoadedOverlays<'a>, RomSaveError> {
         let path = config_path.parent().unwrap();
-        let mut overlays = vec![];
         let overlay_table_config: OverlayTableConfig = serde_yml::from_reader(open_file(config_path)?)?;
-        let num_overlays = overlay_table_config.overlays.len();
+        let mut loaded = LoadedOverlays {
+            processor,
+            table_signed: overlay_table_config.table_signed,
+            table_signature: overlay_table_config.table_signature,
+            ..LoadedOverlays::empty(processor)
+        };
         for mut config in overlay_table_config.overlays.into_iter() {
             let data = read_file(path.join(config.file_name))?;
             let compressed = config.info.compressed;
             config.info.compressed = false;
-            let mut overlay = Overlay::new(data, OverlayOptions { info: config.info, originally_compressed: compressed })?;
-
-            if options.compress {
-                if compressed {
-                    log::info!("Compressing {processor} overlay {}/{}", overlay.id(), num_overlays - 1);
-                    overlay.compress()?;
-                }
-
-                if config.signed {
-                    let Some(ref hmac_sha1) = hmac_sha1 else {
-                        return NoHmacSha1KeySnafu {}.fail();
-                    };
-                    overlay.sign(hmac_sha1)?;
-                }
-            }
+            let overlay = Overlay::new(data, OverlayOptions { info: config.info, originally_compressed: compressed })?;
 
-            overlays.push(overlay);
+            loaded.overlays.push(overlay);
+            loaded.compressed.push(compressed);
+            loaded.signed.push(config.signed);
         }
-
-        let mut overlay_table = OverlayTable::new(overlays);
-        if overlay_table_config.table_signed {
-            let Some(ref hmac_sha1) = hmac_sha1 else {
-                return NoHmacSha1KeySnafu {}.fail();
-            };
-            if let Some(signature) = overlay_table_config.table_signature {
-                overlay_table.set_signature(signature);
-            } else {
-                overlay_table.sign(hmac_sha1);
-            }
-        }
-
-        Ok(overlay_table)
+        Ok(loaded)
     }
 
     /// Saves this ROM to a path as separate files.
@@ -579,15 +723,17 @@ impl<'a> Rom<'a> {
         {
             log::info!("Saving ROM assets");
             let files_path = path.join(&self.config.files_dir);
-            self.files.traverse_files(["/"], |file, path| {
-                let path = files_path.join(path);
-                // TODO: Rewrite traverse_files as an iterator so these errors can be returned
-                create_dir_all(&path).expect("failed to create file directory");
-                create_file(path.join(file.name()))
-                    .expect("failed to create file")
-                    .write_all(file.contents())
-                    .expect("failed to write file");
-            });
+            if let Some(files_compression) = &self.config.files_compression {
+                self.files.save_decompressed(&files_path, path.join(files_compression))?;
+            } else {
+                self.files.save(&files_path)?;
+            }
+            if let Some(files_narcs) = &self.config.files_narcs {
+                self.files.save_narcs(&files_path, path.join(files_narcs))?;
+            }
+            if let Some(file_ids) = &self.config.file_ids {
+                self.files.save_file_ids(path.join(file_ids))?;
+            }
         }
         let mut path_order_file = create_file_and_dirs(path.join(&self.config.path_order))?;
         for path in &self.path_order {
@@ -595,6 +741,32 @@ impl<'a> Rom<'a> {
             path_order_file.write_all("\n".as_bytes())?;
         }
 
+        // --------------------- Save DSi area ---------------------
+        if let (Some(dsi_area), Some(dsi_config)) = (&self.dsi_area, &self.config.dsi) {
+            create_file_and_dirs(path.join(&dsi_config.reserved))?.write_all(dsi_area.reserved())?;
+            create_file_and_dirs(path.join(&dsi_config.arm9i_bin))?.write_all(dsi_area.arm9i().full_data())?;
+            serde_yml::to_writer(create_file_and_dirs(path.join(&dsi_config.arm9i_config))?, dsi_area.arm9i().offsets())?;
+            if let Some(gap) = &dsi_config.gap {
+                create_file_and_dirs(path.join(gap))?.write_all(dsi_area.gap())?;
+            }
+            create_file_and_dirs(path.join(&dsi_config.arm7i_bin))?.write_all(dsi_area.arm7i().full_data())?;
+            serde_yml::to_writer(create_file_and_dirs(path.join(&dsi_config.arm7i_config))?, dsi_area.arm7i().offsets())?;
+            if let Some(trailing) = &dsi_config.trailing {
+                create_file_and_dirs(path.join(trailing))?.write_all(dsi_area.trailing())?;
+            }
+        }
+        if let (Some(digest_hashtables), Some(dsi_config)) = (&self.digest_hashtables, &self.config.dsi) {
+            if let Some(sector) = &dsi_config.digest_sector_hashtable {
+                create_file_and_dirs(path.join(sector))?.write_all(&digest_hashtables.sector)?;
+            }
+            if let Some(block) = &dsi_config.digest_block_hashtable {
+                create_file_and_dirs(path.join(block))?.write_all(&digest_hashtables.block)?;
+            }
+            if let Some(sectors_sha1) = &dsi_config.digest_sectors_sha1 {
+                create_file_and_dirs(path.join(sectors_sha1))?.write_all(&digest_hashtables.sectors_sha1)?;
+            }
+        }
+
         Ok(())
     }
 
@@ -654,6 +826,7 @@ impl<'a> Rom<'a> {
         let banner = rom.banner()?;
         let file_root = FileSystem::parse(&fnt, fat, rom)?;
         let path_order = file_root.compute_path_order();
+        let file_aliases = Self::find_file_aliases(&file_root, fat);
 
         let arm9 = rom.arm9()?;
         let mut decompressed_arm9 = arm9.clone();
@@ -685,6 +858,36 @@ impl<'a> Rom<'a> {
 
         let alignment = rom.alignments()?;
 
+        let mut dsi_area = rom.dsi_area()?;
+        if let Some(dsi_area) = &mut dsi_area {
+            dsi_area.modcrypt(header)?;
+        }
+        let digest_hashtables = rom.digest_hashtables()?;
+        let has_digest = digest_hashtables.is_some();
+        let dsi = if let Some(dsi_alignment) = rom.dsi_alignments()? {
+            let has_gap = dsi_area.as_ref().is_some_and(|dsi_area| !dsi_area.gap().is_empty());
+            let has_trailing = dsi_area.as_ref().is_some_and(|dsi_area| !dsi_area.trailing().is_empty());
+            Some(RomConfigDsi {
+                padding_value: rom.dsi_padding_value()?,
+                reserved: "dsi_area_reserved.bin".into(),
+                arm9i_bin: "arm9i/arm9i.bin".into(),
+                arm9i_config: "arm9i/arm9i.yaml".into(),
+                gap: has_gap.then(|| "dsi_area_gap.bin".into()),
+                arm7i_bin: "arm7i/arm7i.bin".into(),
+                arm7i_config: "arm7i/arm7i.yaml".into(),
+                trailing: has_trailing.then(|| "dsi_area_trailing.bin".into()),
+                digest_sector_hashtable: has_digest.then(|| "digest/sector_hashtable.bin".into()),
+                digest_block_hashtable: has_digest.then(|| "digest/block_hashtable.bin".into()),
+                digest_sectors_sha1: has_digest.then(|| "digest/sectors_sha1.bin".into()),
+                digest_sector_hashtable_offset: has_digest.then_some(header.digest_sector_hashtable.offset),
+                digest_block_hashtable_offset: has_digest.then_some(header.digest_block_hashtable.offset),
+                dsi_area_offset: rom.dsi_area_offset()?,
+                alignment: dsi_alignment,
+            })
+        } else {
+            None
+        };
+
         let config = RomConfig {
             file_image_padding_value: rom.file_image_padding_value()?,
             section_padding_value: rom.section_padding_value()?,
@@ -702,8 +905,14 @@ impl<'a> Rom<'a> {
             banner: "banner/banner.yaml".into(),
             files_dir: "files/".into(),
             path_order: "path_order.txt".into(),
+            files_compression: None,
+            files_narcs: None,
+            file_ids: None,
+            file_aliases,
+            dedupe_files: false,
             arm9_hmac_sha1_key: has_arm9_hmac_sha1.then_some("arm9/hmac_sha1_key.bin".into()),
             alignment,
+            dsi,
         };
 
         Ok(Self {
@@ -716,10 +925,37 @@ impl<'a> Rom<'a> {
             banner: Banner::load_raw(&banner),
             files: file_root,
             path_order,
+            dsi_area,
+            header_hmac_sha1: None,
+            digest_hashtables,
+            digest_hmac_sha1: None,
             config,
         })
     }
 
+    /// Returns the asset files which point to the same contents as a file with a lower ID in the `fat`. Empty files are
+    /// ignored, since they don't take up any space.
+    fn find_file_aliases(files: &FileSystem, fat: &[FileAlloc]) -> Vec<RomConfigFileAlias> {
+        let mut files = files
+            .traverse_files(["/"])
+            .map(|(file, path)| (file.id(), FileSystem::manifest_path(&path, file.name())))
+            .collect::<Vec<_>>();
+        files.sort_unstable_by_key(|(id, _)| *id);
+
+        let mut targets = HashMap::<(u32, u32), String>::new();
+        let mut aliases = vec![];
+        for (id, path) in files {
+            let Some(alloc) = fat.get(id as usize).filter(|alloc| alloc.end > alloc.start) else { continue };
+            match targets.entry((alloc.start, alloc.end)) {
+                Entry::Occupied(target) => aliases.push(RomConfigFileAlias { path, target: target.get().clone() }),
+                Entry::Vacant(entry) => {
+                    entry.insert(path);
+                }
+            }
+        }
+        aliases
+    }
+
     /// Builds a raw ROM.
     ///
     /// # Errors
@@ -817,22 +1053,139 @@ impl<'a> Rom<'a> {
         // --------------------- Write files ---------------------
         self.align_file_image(&mut cursor, self.config.alignment.file_image_block)?;
         self.files.sort_for_rom();
-        self.files.traverse_files(self.path_order.iter().map(|s| s.as_str()), |file, _| {
-            // TODO: Rewrite traverse_files as an iterator so these errors can be returned
-            self.align_file_image(&mut cursor, self.config.alignment.file).expect("failed to align after file");
-
+        let alias_targets = self.file_alias_targets();
+        let mut written_aliases = HashMap::<u16, (FileAlloc, &[u8])>::new();
+        let mut written_contents = HashMap::<&[u8], FileAlloc>::new();
+        for (file, path) in self.files.traverse_files(&self.path_order) {
             let contents = file.contents();
+            let alias_target = alias_targets.get(&file.id()).copied().unwrap_or(file.id());
+            let shared_alloc = match written_aliases.get(&alias_target) {
+                Some(&(alloc, target_contents)) if target_contents == contents => Some(alloc),
+                _ if self.config.dedupe_files && !contents.is_empty() => written_contents.get(contents).copied(),
+                _ => None,
+            };
+            if let Some(alloc) = shared_alloc {
+                file_allocs[file.id() as usize] = alloc;
+                continue;
+            }
+
+            self.align_file_image(&mut cursor, self.config.alignment.file)?;
+
             let start = cursor.position() as u32;
             let end = start + contents.len() as u32;
-            file_allocs[file.id() as usize] = FileAlloc { start, end };
+            let alloc = FileAlloc { start, end };
+            file_allocs[file.id() as usize] = alloc;
+            written_aliases.entry(alias_target).or_insert((alloc, contents));
+            if self.config.dedupe_files {
+                written_contents.entry(contents).or_insert(alloc);
+            }
 
-            cursor.write_all(contents).expect("failed to write file contents");
-        });
+            let path = path.join(file.name()).to_string_lossy().into_owned();
+            cursor.write_all(contents).context(WriteFileSnafu { path, id: file.id() })?;
+        }
 
-        // --------------------- Write padding ---------------------
         context.rom_size = Some(cursor.position() as u32);
-        let padded_rom_size = cursor.position().next_power_of_two().max(128 * 1024) as u32;
-        self.align_file_image(&mut cursor, padded_rom_size)?;
+
+        let mut original_digest = None;
+        if let (Some(dsi_area), Some(dsi_config)) = (&self.dsi_area, &self.config.dsi) {
+            let digest = self.header.dsi.as_ref().filter(|dsi| dsi.digest_sector_size != 0 && dsi.digest_sector_count != 0);
+            if let Some(header_dsi) = digest {
+                // --------------------- Write digest hashtable placeholders ---------------------
+                let sector_size = header_dsi.digest_sector_size;
+                let arm9_offset = context.arm9_offset.unwrap();
+                let ds_area = TableOffset {
+                    offset: arm9_offset,
+                    size: (context.rom_size.unwrap() - arm9_offset).next_multiple_of(sector_size),
+                };
+
+                // The DSi area is aligned, so its size can be computed before it has been written
+                let arm9i_start = dsi_area.reserved().len() as u32;
+                let arm9i_end = arm9i_start + dsi_area.arm9i().full_data().len() as u32;
+                let arm7i_start = (arm9i_end + dsi_area.gap().len() as u32).next_multiple_of(dsi_config.alignment.arm7i);
+                let dsi_area_end = arm7i_start + dsi_area.arm7i().full_data().len() as u32;
+                let dsi_area_size = (dsi_area_end - arm9i_start).next_multiple_of(sector_size);
+
+                let digest = Digest {
+                    ds_area,
+                    dsi_area: TableOffset { offset: 0, size: dsi_area_size },
+                    sector_size,
+                    sectors_per_block: header_dsi.digest_sector_count,
+                };
+
+                let ds_area_end = ds_area.offset + ds_area.size;
+                self.pad_to_offset(&mut cursor, Some(ds_area_end), 1, dsi_config.padding_value)?;
+                self.pad_to_offset(
+                    &mut cursor,
+                    dsi_config.digest_sector_hashtable_offset,
+                    dsi_config.alignment.digest_sector_hashtable,
+                    dsi_config.padding_value,
+                )?;
+                // Write the original hashtables, which stay valid as long as the ROM is not modified
+                let original = self.digest_hashtables.as_ref().filter(|hashtables| {
+                    hashtables.sector.len() == digest.sector_hashtable_size()
+                        && hashtables.block.len() == digest.block_hashtable_size()
+                });
+                context.digest_sector_hashtable =
+                    Some(TableOffset { offset: cursor.position() as u32, size: digest.sector_hashtable_size() as u32 });
+                match original {
+                    Some(hashtables) => cursor.write_all(&hashtables.sector)?,
+                    None => cursor.write_all(&vec![0; digest.sector_hashtable_size()])?,
+                }
+
+                self.pad_to_offset(
+                    &mut cursor,
+                    dsi_config.digest_block_hashtable_offset,
+                    dsi_config.alignment.digest_block_hashtable,
+                    dsi_config.padding_value,
+                )?;
+                context.digest_block_hashtable =
+                    Some(TableOffset { offset: cursor.position() as u32, size: digest.block_hashtable_size() as u32 });
+                match original {
+                    Some(hashtables) => cursor.write_all(&hashtables.block)?,
+                    None => cursor.write_all(&vec![0; digest.block_hashtable_size()])?,
+                }
+                original_digest = original;
+
+                context.digest_ds_area = Some(digest.ds_area);
+                context.digest_dsi_area = Some(digest.dsi_area);
+            }
+
+            // --------------------- Write DSi area ---------------------
+            self.pad_to_offset(
+                &mut cursor,
+                dsi_config.dsi_area_offset,
+                dsi_config.alignment.dsi_area,
+                dsi_config.padding_value,
+            )?;
+            let dsi_area_offset = cursor.position() as u32;
+            context.dsi_area_offset = Some(dsi_area_offset);
+            cursor.write_all(dsi_area.reserved())?;
+
+            // --------------------- Write ARM9i program ---------------------
+            context.arm9i_offset = Some(cursor.position() as u32);
+            if let Some(digest_dsi_area) = &mut context.digest_dsi_area {
+                digest_dsi_area.offset = cursor.position() as u32;
+            }
+            cursor.write_all(dsi_area.arm9i().full_data())?;
+            cursor.write_all(dsi_area.gap())?;
+
+            // --------------------- Write ARM7i program ---------------------
+            let arm7i_offset =
+                (cursor.position() as u32 - dsi_area_offset).next_multiple_of(dsi_config.alignment.arm7i) + dsi_area_offset;
+            self.pad_to_offset(&mut cursor, Some(arm7i_offset), 1, dsi_config.padding_value)?;
+            context.arm7i_offset = Some(cursor.position() as u32);
+            cursor.write_all(dsi_area.arm7i().full_data())?;
+            cursor.write_all(dsi_area.trailing())?;
+
+            // --------------------- Write padding ---------------------
+            context.rom_size_dsi = Some(cursor.position() as u32);
+            let padded_rom_size = cursor.position().next_power_of_two().max(128 * 1024) as u32;
+            self.align(&mut cursor, padded_rom_size, dsi_config.padding_value)?;
+        } else {
+            // --------------------- Write padding ---------------------
+            let padded_rom_size = cursor.position().next_power_of_two().max(128 * 1024) as u32;
+            self.align_file_image(&mut cursor, padded_rom_size)?;
+        }
 
         // --------------------- Update FAT ---------------------
         cursor.set_position(context.fat_offset.unwrap().offset as u64);
@@ -840,9 +1193,51 @@ impl<'a> Rom<'a> {
 
         // --------------------- Update header ---------------------
         cursor.set_position(context.header_offset.unwrap() as u64);
-        let header = self.header.build(&context, &self)?;
+        let mut header = self.header.build(&context, &self)?;
+        if let Some(hmac_sha1) = &self.header_hmac_sha1 {
+            HeaderHmacs::compute(cursor.get_ref(), &header, hmac_sha1)?.apply(&mut header);
+        }
         cursor.write_all(bytemuck::bytes_of(&header))?;
 
+        // --------------------- Encrypt modcrypt areas ---------------------
+        if let Some(dsi_area) = &self.dsi_area {
+            if header.dsi_flags.modcrypted() {
+                let mut dsi_area = dsi_area.clone();
+                dsi_area.modcrypt(&header)?;
+                cursor.set_position(context.arm9i_offset.unwrap() as u64);
+                cursor.write_all(dsi_area.arm9i().full_data())?;
+                cursor.set_position(context.arm7i_offset.unwrap() as u64);
+                cursor.write_all(dsi_area.arm7i().full_data())?;
+            }
+        }
+
+        // --------------------- Update digest hashtables ---------------------
+        if let Some(digest) = Digest::from_header(&header) {
+            match &self.digest_hmac_sha1 {
+                None => {
+                    let sectors_sha1 = digest.sectors_sha1(cursor.get_ref())?;
+                    if original_digest.map_or(true, |original: &DigestHashtables| original.sectors_sha1 != sectors_sha1) {
+                        return DigestHmacSha1KeyNeededSnafu {}.fail();
+                    }
+                }
+                Some(hmac_sha1) if Digest::verify(cursor.get_ref(), &header, hmac_sha1)?.is_valid() => {}
+                Some(hmac_sha1) => {
+                    log::info!("Updating DSi digest hashtables");
+                    let sector_hashtable = digest.compute_sector_hashtable(cursor.get_ref(), hmac_sha1)?;
+                    cursor.set_position(header.digest_sector_hashtable.offset as u64);
+                    cursor.write_all(&sector_hashtable)?;
+
+                    let block_hashtable = digest.compute_block_hashtable(&sector_hashtable, hmac_sha1);
+                    cursor.set_position(header.digest_block_hashtable.offset as u64);
+                    cursor.write_all(&block_hashtable)?;
+
+                    header.sha1_hmac_digest = hmac_sha1.compute(&block_hashtable);
+                    cursor.set_position(context.header_offset.unwrap() as u64);
+                    cursor.write_all(bytemuck::bytes_of(&header))?;
+                }
+            }
+        }
+
         Ok(raw::Rom::new(cursor.into_inner()))
     }
 
@@ -856,6 +1251,23 @@ impl<'a> Rom<'a> {
         Ok(())
     }
 
+    /// Pads up to `offset`, or to the next multiple of `alignment` if there's no offset or the cursor is already past it.
+    fn pad_to_offset(
+        &self,
+        cursor: &mut Cursor<Vec<u8>>,
+        offset: Option<u32>,
+        alignment: u32,
+        padding_value: u8,
+    ) -> Result<(), RomBuildError> {
+        match offset {
+            Some(offset) if offset as u64 >= cursor.position() => {
+                cursor.write_all(&vec![padding_value; (offset as u64 - cursor.position()) as usize])?;
+                Ok(())
+            }
+            _ => self.align(cursor, alignment, padding_value),
+        }
+    }
+
     fn align_section(&self, cursor: &mut Cursor<Vec<u8>>, alignment: u32) -> Result<(), RomBuildError> {
         self.align(cursor, alignment, self.config.section_padding_value)
     }
@@ -864,6 +1276,22 @@ impl<'a> Rom<'a> {
         self.align(cursor, alignment, self.config.file_image_padding_value)
     }
 
+    /// Maps the ID of every aliased file in the config to the ID of its target. Aliases of files which no longer exist are
+    /// ignored.
+    fn file_alias_targets(&self) -> HashMap<u16, u16> {
+        let mut targets = HashMap::new();
+        for alias in &self.config.file_aliases {
+            let (Some(id), Some(target)) = (self.files.find_path(&alias.path), self.files.find_path(&alias.target)) else {
+                log::warn!("Ignoring file alias {} of {}, file not found", alias.path, alias.target);
+                continue;
+            };
+            if FileSystem::is_file(id) && FileSystem::is_file(target) {
+                targets.insert(id, target);
+            }
+        }
+        targets
+    }
+
     /// Returns a reference to the header logo of this [`Rom`].
     pub fn header_logo(&self) -> &Logo {
         &self.header_logo
@@ -904,10 +1332,112 @@ impl<'a> Rom<'a> {
         &self.header
     }
 
+    /// Returns a reference to the DSi area of this [`Rom`], if it has one.
+    pub fn dsi_area(&self) -> Option<&DsiArea> {
+        self.dsi_area.as_ref()
+    }
+
+    /// Returns a reference to the asset files of this [`Rom`].
+    pub fn files(&self) -> &FileSystem<'a> {
+        &self.files
+    }
+
+    /// Returns a mutable reference to the asset files of this [`Rom`]. Prefer [`Self::move_path`] and [`Self::remove_path`]
+    /// to move and remove files, as they also update the path order.
+    pub fn files_mut(&mut self) -> &mut FileSystem<'a> {
+        &mut self.files
+    }
+
+    /// Returns the path order, which decides the order of the asset files in the ROM. See [`FileSystem::traverse_files`].
+    pub fn path_order(&self) -> &[String] {
+        &self.path_order
+    }
+
+    /// Moves an asset file or directory like [`FileSystem::move_path`], and updates the path order so that it keeps its place
+    /// in the ROM.
+    ///
+    /// # Errors
+    ///
+    /// See [`FileSystem::move_path`].
+    pub fn move_path(&mut self, from: &str, to: &str) -> Result<u16, FileEditError> {
+        let id = self.files.move_path(from, to)?;
+        Self::move_path_refs(&mut self.path_order, &mut self.config.file_aliases, from, to);
+        Ok(id)
+    }
+
+    /// Updates the path order entries and file aliases at or inside `from` to point to `to` instead.
+    fn move_path_refs(path_order: &mut [String], file_aliases: &mut [RomConfigFileAlias], from: &str, to: &str) {
+        let from = from.trim_matches('/');
+        let to = to.trim_matches('/');
+        for path in path_order {
+            if let Some(rest) = Self::strip_path_prefix(path, from) {
+                *path = format!("/{to}{rest}");
+            }
+        }
+        for alias in file_aliases {
+            for path in [&mut alias.path, &mut alias.target] {
+                if let Some(rest) = Self::strip_path_prefix(path, from) {
+                    *path = format!("{to}{rest}");
+                }
+            }
+        }
+    }
+
+    /// Removes an asset file or directory like [`FileSystem::remove`], along with its entries in the path order.
+    ///
+    /// # Errors
+    ///
+    /// See [`FileSystem::remove`].
+    pub fn remove_path(&mut self, path: &str) -> Result<(), FileEditError> {
+        self.files.remove(path)?;
+        Self::remove_path_refs(&mut self.path_order, &mut self.config.file_aliases, path);
+        Ok(())
+    }
+
+    /// Removes the path order entries and file aliases at or inside `path`.
+    fn remove_path_refs(path_order: &mut Vec<String>, file_aliases: &mut Vec<RomConfigFileAlias>, path: &str) {
+        let path = path.trim_matches('/');
+        path_order.retain(|entry| Self::strip_path_prefix(entry, path).is_none());
+        file_aliases.retain(|alias| {
+            Self::strip_path_prefix(&alias.path, path).is_none() && Self::strip_path_prefix(&alias.target, path).is_none()
+        });
+    }
+
+    /// Returns the rest of a path order entry if it is `prefix` or inside of it.
+    fn strip_path_prefix<'p>(entry: &'p str, prefix: &str) -> Option<&'p str> {
+        let rest = entry.trim_matches('/').strip_prefix(prefix)?;
+        (rest.is_empty() || rest.starts_with('/')).then_some(rest)
+    }
+
     /// Returns the [`RomConfig`] consisting of paths to extracted files.
     pub fn config(&self) -> &RomConfig {
         &self.config
     }
+
+    /// Sets whether [`Self::save`] saves BIOS-compressed asset files decompressed. If enabled, their formats are recorded in
+    /// a [`FileCompressionManifest`](super::FileCompressionManifest) which [`Self::load`] uses to compress them again.
+    pub fn set_decompress_files(&mut self, decompress: bool) {
+        self.config.files_compression = decompress.then(|| FILE_COMPRESSION_MANIFEST.into());
+    }
+
+    /// Sets whether [`Self::save`] expands NARC asset files into folders. If enabled, the expanded files are recorded in a
+    /// [`NarcManifest`](super::NarcManifest) which [`Self::load`] uses to pack them again.
+    pub fn set_expand_narcs(&mut self, expand: bool) {
+        self.config.files_narcs = expand.then(|| FILE_NARC_MANIFEST.into());
+    }
+
+    /// Sets whether [`Self::build`] writes asset files with identical contents only once, so that they share the same
+    /// contents in the file allocation table.
+    pub fn set_dedupe_files(&mut self, dedupe: bool) {
+        self.config.dedupe_files = dedupe;
+    }
+
+    /// Sets whether [`Self::save`] records the ID of every asset file. If enabled, the IDs are saved in a
+    /// [`FileIdManifest`](super::FileIdManifest) which [`Self::load`] uses to keep existing files at the same IDs, so that
+    /// adding a file doesn't shift the IDs of others.
+    pub fn set_pin_file_ids(&mut self, pin: bool) {
+        self.config.file_ids = pin.then(|| FILE_ID_MANIFEST.into());
+    }
 }
 
 /// Build context, generated during [`Rom::build`] and later passed to [`Header::build`] to fill in the header.
@@ -939,8 +1469,24 @@ pub struct BuildContext<'a> {
     pub arm9_build_info_offset: Option<u32>,
     /// ARM7 build info offset.
     pub arm7_build_info_offset: Option<u32>,
-    /// Total ROM size.
+    /// Total ROM size, excluding the DSi area.
     pub rom_size: Option<u32>,
+    /// DSi area offset.
+    pub dsi_area_offset: Option<u32>,
+    /// ARM9i program offset.
+    pub arm9i_offset: Option<u32>,
+    /// ARM7i program offset.
+    pub arm7i_offset: Option<u32>,
+    /// Total ROM size, including the DSi area.
+    pub rom_size_dsi: Option<u32>,
+    /// DS area digest range.
+    pub digest_ds_area: Option<TableOffset>,
+    /// DSi area digest range.
+    pub digest_dsi_area: Option<TableOffset>,
+    /// Digest sector hashtable offset.
+    pub digest_sector_hashtable: Option<TableOffset>,
+    /// Digest block hashtable offset.
+    pub digest_block_hashtable: Option<TableOffset>,
 }
 
 /// Options for [`Rom::load`].
@@ -949,6 +1495,15 @@ pub struct RomLoadOptions<'a> {
     pub key: Option<&'a BlowfishKey>,
     /// If true (default), compress ARM9 and overlays if they are configured with `compressed: true`.
     pub compress: bool,
+    /// LZ77 strategy to compress ARM9 and overlays with. The default [`Lz77Strategy::Greedy`] is needed for built ROMs to
+    /// match the originals, while [`Lz77Strategy::Optimal`] produces smaller output.
+    pub lz77_strategy: Lz77Strategy,
+    /// Number of threads to compress ARM9 and overlays with. Defaults to the available parallelism, and values below 1 are
+    /// treated as 1. The output is the same regardless of this value.
+    pub compression_workers: usize,
+    /// If true, reuse results of previous loads when compressing ARM9 and overlays. They are cached in a `.dsrom-cache`
+    /// directory next to the config file, see [`CompressionCache`]. Defaults to false.
+    pub compression_cache: bool,
     /// If true (default), encrypt ARM9 if it's configured with `encrypted: true`.
     pub encrypt: bool,
     /// If true (default), load asset files.
@@ -957,10 +1512,246 @@ pub struct RomLoadOptions<'a> {
     pub load_header: bool,
     /// If true (default), load banner.
     pub load_banner: bool,
+    /// HMAC-SHA1 key to recompute the ARM9, ARM7 and banner HMACs in the header with. If `None` (default), the HMACs are
+    /// copied from the header YAML.
+    pub header_hmac_sha1: Option<&'a HmacSha1>,
+    /// HMAC-SHA1 key to update the DSi digest hashtables with, if they don't match the built ROM. If `None` (default), the
+    /// original hashtables are kept if the ROM was not modified, otherwise building fails with
+    /// [`RomBuildError::DigestHmacSha1KeyNeeded`].
+    pub digest_hmac_sha1: Option<&'a HmacSha1>,
 }
 
 impl Default for RomLoadOptions<'_> {
     fn default() -> Self {
-        Self { key: None, compress: true, encrypt: true, load_files: true, load_header: true, load_banner: true }
+        Self {
+            key: None,
+            compress: true,
+            lz77_strategy: Lz77Strategy::Greedy,
+            compression_workers: thread::available_parallelism().map_or(1, NonZeroUsize::get),
+            compression_cache: false,
+            encrypt: true,
+            load_files: true,
+            load_header: true,
+            load_banner: true,
+            header_hmac_sha1: None,
+            digest_hmac_sha1: None,
+        }
+    }
+}
+
+/// Overlays read by [`Rom::load`] which have not been compressed or signed yet.
+struct LoadedOverlays<'a> {
+    processor: &'static str,
+    overlays: Vec<Overlay<'a>>,
+    /// Whether each overlay is configured to be compressed.
+    compressed: Vec<bool>,
+    /// Whether each overlay is configured to be signed.
+    signed: Vec<bool>,
+    table_signed: bool,
+    table_signature: Option<HmacSha1Signature>,
+}
+
+impl<'a> LoadedOverlays<'a> {
+    fn empty(processor: &'static str) -> Self {
+        Self { processor, overlays: vec![], compressed: vec![], signed: vec![], table_signed: false, table_signature: None }
+    }
+
+    fn compress_jobs(&mut self) -> impl Iterator<Item = CompressJob<'_, 'a>> {
+        let processor = self.processor;
+        let count = self.overlays.len();
+        self.overlays
+            .iter_mut()
+            .zip(self.compressed.iter())
+            .filter(|(_, &compressed)| compressed)
+            .map(move |(overlay, _)| CompressJob::Overlay { overlay, processor, count })
+    }
+
+    /// Signs the overlays and their table if configured to, and returns the table.
+    fn into_table(self, hmac_sha1: Option<&HmacSha1>, options: &RomLoadOptions) -> Result<OverlayTable<'a>, RomSaveError> {
+        let mut overlays = self.overlays;
+        if options.compress {
+            for (overlay, signed) in overlays.iter_mut().zip(self.signed) {
+                if signed {
+                    let Some(hmac_sha1) = hmac_sha1 else {
+                        return NoHmacSha1KeySnafu {}.fail();
+                    };
+                    overlay.sign(hmac_sha1)?;
+                }
+            }
+        }
+
+        let mut overlay_table = OverlayTable::new(overlays);
+        if self.table_signed {
+            let Some(hmac_sha1) = hmac_sha1 else {
+                return NoHmacSha1KeySnafu {}.fail();
+            };
+            if let Some(signature) = self.table_signature {
+                overlay_table.set_signature(signature);
+            } else {
+                overlay_table.sign(hmac_sha1);
+            }
+        }
+
+        Ok(overlay_table)
+    }
+}
+
+/// A module to compress in [`Rom::load`].
+enum CompressJob<'r, 'a> {
+    Arm9(&'r mut Arm9<'a>),
+    Overlay { overlay: &'r mut Overlay<'a>, processor: &'static str, count: usize },
+}
+
+impl CompressJob<'_, '_> {
+    fn run(self, strategy: Lz77Strategy, cache: Option<&CompressionCache>) -> Result<(), RomSaveError> {
+        match self {
+            CompressJob::Arm9(arm9) => {
+                log::info!("Compressing ARM9 program");
+                arm9.compress_with_cache(strategy, cache)?;
+            }
+            CompressJob::Overlay { overlay, processor, count } => {
+                let id = overlay.id();
+                log::info!("Compressing {processor} overlay {id}/{}", count - 1);
+                overlay.compress_with_cache(strategy, cache).context(OverlayCompressSnafu { processor, id })?;
+            }
+        }
+        Ok(())
+    }
+
+    /// Runs all jobs on up to `workers` threads. If any jobs fail, the error of the first failing job in `jobs` is returned,
+    /// so that the result doesn't depend on scheduling.
+    fn run_all(
+        jobs: Vec<Self>,
+        workers: usize,
+        strategy: Lz77Strategy,
+        cache: Option<&CompressionCache>,
+    ) -> Result<(), RomSaveError> {
+        let workers = workers.clamp(1, jobs.len().max(1));
+        if workers == 1 {
+            return jobs.into_iter().try_for_each(|job| job.run(strategy, cache));
+        }
+
+        let queue = Mutex::new(jobs.into_iter().enumerate());
+        let mut results = thread::scope(|scope| {
+            let handles = (0..workers)
+                .map(|_| {
+                    scope.spawn(|| {
+                        let mut results = vec![];
+                        loop {
+                            let Some((index, job)) = queue.lock().unwrap().next() else {
+                                break;
+                            };
+                            results.push((index, job.run(strategy, cache)));
+                        }
+                        results
+                    })
+                })
+                .collect::<Vec<_>>();
+            handles
+                .into_iter()
+                .flat_map(|handle| handle.join().unwrap_or_else(|payload| panic::resume_unwind(payload)))
+                .collect::<Vec<_>>()
+        });
+
+        results.sort_unstable_by_key(|(index, _)| *index);
+        results.into_iter().try_for_each(|(_, result)| result)
+    }
+}
+
+#[cfg(test)]
+impl<'a> Rom<'a> {
+    /// Returns a ROM with the given header and programs, and no overlays or files.
+    pub(crate) fn with_programs(header: Header, arm9: Arm9<'a>, arm7: Arm7<'a>, dsi_area: Option<DsiArea<'a>>) -> Self {
+        let autoload = |name: &str| RomConfigAutoload {
+            bin: format!("arm9/{name}.bin").into(),
+            config: format!("arm9/{name}.yaml").into(),
+        };
+        let config = RomConfig {
+            file_image_padding_value: 0xff,
+            section_padding_value: 0xff,
+            header: "header.yaml".into(),
+            header_logo: "header_logo.png".into(),
+            arm9_bin: "arm9/arm9.bin".into(),
+            arm9_config: "arm9/arm9.yaml".into(),
+            arm7_bin: "arm7/arm7.bin".into(),
+            arm7_config: "arm7/arm7.yaml".into(),
+            itcm: autoload("itcm"),
+            dtcm: autoload("dtcm"),
+            unknown_autoloads: vec![],
+            arm9_overlays: None,
+            arm7_overlays: None,
+            banner: "banner/banner.yaml".into(),
+            files_dir: "files/".into(),
+            path_order: "path_order.txt".into(),
+            files_compression: None,
+            files_narcs: None,
+            file_ids: None,
+            file_aliases: vec![],
+            dedupe_files: false,
+            arm9_hmac_sha1_key: None,
+            alignment: super::RomConfigAlignment {
+                arm9: 0x200,
+                arm9_overlay_table: 0x200,
+                arm9_overlay: 0x200,
+                arm7: 0x200,
+                arm7_overlay_table: 0x200,
+                arm7_overlay: 0x200,
+                file_name_table: 0x200,
+                file_allocation_table: 0x200,
+                banner: 0x200,
+                file_image_block: 0x200,
+                file: 0x200,
+            },
+            dsi: None,
+        };
+        Self {
+            header,
+            header_logo: Logo::default(),
+            arm9,
+            arm9_overlay_table: OverlayTable::default(),
+            arm7,
+            arm7_overlay_table: OverlayTable::default(),
+            banner: Banner::default(),
+            files: FileSystem::new(0),
+            path_order: vec![],
+            dsi_area,
+            header_hmac_sha1: None,
+            digest_hashtables: None,
+            digest_hmac_sha1: None,
+            config,
+        }
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn alias(path: &str, target: &str) -> RomConfigFileAlias {
+        RomConfigFileAlias { path: path.into(), target: target.into() }
+    }
+
+    #[test]
+    fn move_path_updates_path_order() {
+        let mut path_order = ["/", "/x", "/x/c.bin", "/xy/d.bin", "/y"].map(String::from).to_vec();
+        let mut file_aliases = vec![alias("x/c.bin", "a.bin"), alias("y/e.bin", "x/c.bin")];
+        Rom::move_path_refs(&mut path_order, &mut file_aliases, "/x/", "y/z");
+
+        assert_eq!(path_order, ["/", "/y/z", "/y/z/c.bin", "/xy/d.bin", "/y"]);
+        assert_eq!(file_aliases[0].path, "y/z/c.bin");
+        assert_eq!(file_aliases[0].target, "a.bin");
+        assert_eq!(file_aliases[1].path, "y/e.bin");
+        assert_eq!(file_aliases[1].target, "y/z/c.bin");
+    }
+
+    #[test]
+    fn remove_path_updates_path_order() {
+        let mut path_order = ["/", "/x", "/x/c.bin", "/xy/d.bin", "/y"].map(String::from).to_vec();
+        let mut file_aliases = vec![alias("x/c.bin", "a.bin"), alias("y/e.bin", "x/c.bin"), alias("y/f.bin", "a.bin")];
+        Rom::remove_path_refs(&mut path_order, &mut file_aliases, "x");
+
+        assert_eq!(path_order, ["/", "/xy/d.bin", "/y"]);
+        assert_eq!(file_aliases.len(), 1);
+        assert_eq!(file_aliases[0].path, "y/f.bin");
     }
 }
@@ -0,0 +1,48 @@
+use std::{ffi::OsStr, fs};
+
+use anyhow::Result;
+use ds_rom::rom::{
+    dsiware::{DsiWare, Tmd},
+    raw,
+};
+
+#[test]
+fn test_dsiware_extract_build() -> Result<()> {
+    let cwd = std::env::current_dir()?;
+    let roms_dir = cwd.join("tests/roms/");
+
+    for entry in roms_dir.read_dir()? {
+        let entry = entry?;
+        let path = entry.path();
+        if path.extension() != Some(OsStr::new("srl")) {
+            continue;
+        }
+        let tmd_path = path.with_extension("tmd");
+        if !tmd_path.is_file() {
+            continue;
+        }
+        let file_name = path.file_name().unwrap().to_string_lossy();
+        let base_name = path.file_stem().unwrap().to_string_lossy();
+        let extract_path = roms_dir.join(format!("dsiware_{base_name}"));
+
+        // Extract
+        let raw_rom = raw::Rom::from_file(&path)?;
+        let tmd_data = fs::read(&tmd_path)?;
+        let dsiware = DsiWare::extract(&raw_rom, &tmd_data)?;
+        dsiware.save(&extract_path, None)?;
+
+        // Build
+        let dsiware = DsiWare::load(extract_path.join("config.yaml"), Default::default())?;
+        let (srl, tmd) = dsiware.build(None)?;
+
+        // Compare
+        let target_tmd = Tmd::new(tmd_data.as_slice())?;
+        assert!(target_tmd.contents() == tmd.contents(), "{} TMD content records did not match", file_name);
+        assert!(target_tmd.full_data() == tmd.full_data(), "{} TMD did not match", file_name);
+        assert!(raw_rom.data() == srl.data(), "{} did not match", file_name);
+
+        // Delete
+        fs::remove_dir_all(&extract_path)?;
+    }
+    Ok(())
+}